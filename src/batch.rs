@@ -0,0 +1,375 @@
+// Import serde for reading manifests and writing summaries
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+// Import standard library items
+use std::{
+    fmt, fs,
+    num::NonZeroUsize, // For the number of available CPU cores
+    panic::{self, AssertUnwindSafe}, // For keeping a panicking job from ending the batch
+    path::Path,
+    sync::Mutex,       // For sharing the job queue between worker threads
+    thread,
+    time::Instant,
+};
+
+// Import job files and our error type
+use crate::{config::*, error::GenError};
+
+/// A list of jobs read from a manifest file
+/// `defaults` is merged under every job, field by field, so jobs only list what differs
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Manifest {
+    /// Settings shared by every job, in the same layout as a job file
+    defaults: Value,
+    /// Job files, each overriding the defaults
+    jobs: Vec<Value>,
+}
+
+/// Read a batch manifest, picking TOML or JSON from its extension
+///
+/// # Returns
+/// * `Ok(Vec<JobConfig>)` - Every job with the defaults merged in, in the order they are listed
+/// * `Err(GenError)` - If the manifest can't be read or a job doesn't validate, naming the job
+pub fn read_manifest(path: &str) -> Result<Vec<JobConfig>, GenError> {
+    let format = ConfigFormat::from_path(path).ok_or_else(|| {
+        GenError::Unsupported(format!("Unknown manifest format for {}, expected .toml or .json", path))
+    })?;
+    let text = fs::read_to_string(path).map_err(|e| GenError::io(path, e))?;
+    let manifest: Manifest = match format {
+        ConfigFormat::Toml => toml::from_str(&text).map_err(|e| GenError::decode(path, e))?,
+        ConfigFormat::Json => serde_json::from_str(&text).map_err(|e| GenError::decode(path, e))?,
+    };
+
+    let Manifest { defaults, jobs } = manifest;
+    jobs.into_iter()
+        .enumerate()
+        .map(|(i, job)| {
+            let mut merged = defaults.clone();
+            merge(&mut merged, job);
+            let job = serde_json::from_value::<JobConfig>(merged)
+                .map_err(|e| GenError::decode(path, format!("job {}: {}", i + 1, e)))?;
+            job.options
+                .validate()
+                .map_err(|e| GenError::Invalid(format!("{}: job {}: {}", path, i + 1, e)))?;
+            Ok(job)
+        })
+        .collect()
+}
+
+/// Merge `over` into `base`, replacing everything but tables, which are merged key by key
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Object(base), Value::Object(over)) => {
+            for (key, value) in over {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// Make one job per heightmap matching a pattern with a single `*`, like `maps/*_height.png`
+/// The part matched by `*` replaces the `*` in the colormap and output patterns
+///
+/// # Arguments
+/// * `pattern` - Heightmap path pattern, the `*` only matches within one directory
+/// * `colormap` - Colormap path pattern, None to color each map with its heightmap
+/// * `output` - Output path pattern, None to write a `.brs` next to each heightmap
+/// * `defaults` - Job the inputs, colormap and output are filled into
+///
+/// # Returns
+/// * `Ok(Vec<JobConfig>)` - One job per matching file, sorted by path
+/// * `Err(GenError)` - If the pattern is malformed or its directory can't be read
+pub fn glob_jobs(
+    pattern: &str,
+    colormap: Option<&str>,
+    output: Option<&str>,
+    defaults: &JobConfig,
+) -> Result<Vec<JobConfig>, GenError> {
+    let path = Path::new(pattern);
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    let file_pattern = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+    let (prefix, suffix) = match file_pattern.split_once('*') {
+        Some((prefix, suffix)) if !suffix.contains('*') && !dir.to_string_lossy().contains('*') => (prefix, suffix),
+        _ => {
+            return Err(GenError::Invalid(format!(
+                "Batch pattern '{}' must have one * in its file name",
+                pattern
+            )))
+        }
+    };
+
+    let dir_name = dir.to_string_lossy();
+    let mut matched = fs::read_dir(dir)
+        .map_err(|e| GenError::io(dir_name.as_ref(), e))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let stem = name.strip_prefix(prefix)?.strip_suffix(suffix)?.to_string();
+            Some((entry.path().to_string_lossy().into_owned(), stem))
+        })
+        .collect::<Vec<(String, String)>>();
+    matched.sort();
+
+    Ok(matched
+        .into_iter()
+        .map(|(heightmap, stem)| JobConfig {
+            colormap: colormap.map(|c| c.replace('*', &stem)),
+            output: Some(match output {
+                Some(output) => output.replace('*', &stem),
+                None => Path::new(&heightmap).with_extension("brs").to_string_lossy().into_owned(),
+            }),
+            inputs: vec![heightmap],
+            ..defaults.clone()
+        })
+        .collect())
+}
+
+/// How one job of a batch went
+#[derive(Debug, Clone, Serialize)]
+pub struct JobOutcome {
+    /// Output file of the job
+    pub output: String,
+    /// Number of bricks written, None if the job failed
+    pub bricks: Option<usize>,
+    /// Wall clock time in seconds
+    pub seconds: f64,
+    /// Why the job failed, if it did
+    pub error: Option<String>,
+}
+
+/// Outcome of every job in a batch, in the order they were listed
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatchSummary {
+    /// One outcome per job
+    pub jobs: Vec<JobOutcome>,
+    /// Wall clock time in seconds for the whole batch
+    pub seconds: f64,
+}
+
+impl BatchSummary {
+    /// Number of jobs that failed
+    pub fn failures(&self) -> usize {
+        self.jobs.iter().filter(|j| j.error.is_some()).count()
+    }
+}
+
+/// Run every job of a batch, several at once if asked
+///
+/// # Arguments
+/// * `jobs` - Jobs to run
+/// * `parallel` - Number of jobs to run at once (0 = one per CPU core)
+/// * `run` - Runs one job and returns how many bricks it wrote
+///
+/// # Returns
+/// * The outcome of every job, a failing or panicking job doesn't stop the others
+pub fn run_batch<F>(jobs: &[JobConfig], parallel: usize, run: F) -> BatchSummary
+where
+    F: Fn(&JobConfig) -> Result<usize, GenError> + Sync,
+{
+    let start = Instant::now();
+    let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let parallel = match parallel {
+        0 => cores,
+        n => n,
+    }
+    .min(jobs.len())
+    .max(1);
+    // Jobs running at once share the cores, unless a job sets its own number of threads
+    let threads = (cores / parallel).max(1);
+
+    // Each worker takes the next job from the queue and stores its outcome in the job's slot
+    let queue = Mutex::new(jobs.iter().enumerate());
+    let outcomes = Mutex::new(vec![None; jobs.len()]);
+    let run_jobs = || loop {
+        let next = queue.lock().unwrap().next();
+        let (i, job) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        let job_start = Instant::now();
+        let result = if parallel > 1 && job.options.threads == 0 {
+            let mut job = job.clone();
+            job.options.threads = threads;
+            run_isolated(&run, &job)
+        } else {
+            run_isolated(&run, job)
+        };
+        let outcome = JobOutcome {
+            output: job.output.clone().unwrap_or_default(),
+            bricks: result.as_ref().ok().copied(),
+            seconds: job_start.elapsed().as_secs_f64(),
+            error: result.err().map(|e| e.to_string()),
+        };
+        outcomes.lock().unwrap()[i] = Some(outcome);
+    };
+    thread::scope(|s| {
+        for _ in 0..parallel {
+            s.spawn(run_jobs);
+        }
+    });
+
+    BatchSummary {
+        jobs: outcomes.into_inner().unwrap().into_iter().flatten().collect(),
+        seconds: start.elapsed().as_secs_f64(),
+    }
+}
+
+/// Run one job, turning a panic into an error so it shows up in the summary
+fn run_isolated<F>(run: &F, job: &JobConfig) -> Result<usize, GenError>
+where
+    F: Fn(&JobConfig) -> Result<usize, GenError>,
+{
+    panic::catch_unwind(AssertUnwindSafe(|| run(job))).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+        Err(GenError::Invalid(format!("Job panicked: {}", message)))
+    })
+}
+
+/// Summary table printed after a batch
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Batch summary")?;
+        for job in &self.jobs {
+            match (&job.bricks, &job.error) {
+                (Some(bricks), _) => writeln!(f, "  {:<40}{:>10} bricks  {:.2}s", job.output, bricks, job.seconds)?,
+                (None, error) => writeln!(
+                    f,
+                    "  {:<40}{:>10}  {}",
+                    job.output,
+                    "failed",
+                    error.as_deref().unwrap_or("")
+                )?,
+            }
+        }
+        let bricks: usize = self.jobs.iter().filter_map(|j| j.bricks).sum();
+        writeln!(
+            f,
+            "  {} jobs, {} failed, {} bricks in {:.2}s",
+            self.jobs.len(),
+            self.failures(),
+            bricks,
+            self.seconds
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::GenOptions;
+    use std::{env, process};
+
+    /// Empty temporary directory for one test
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("heightmap-test-{}-{}", process::id(), name));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Job writing to `output`, with the given number of threads
+    fn job(output: &str, threads: usize) -> JobConfig {
+        JobConfig {
+            output: Some(output.to_string()),
+            options: GenOptions::builder().threads(threads).build().unwrap(),
+            ..JobConfig::default()
+        }
+    }
+
+    #[test]
+    fn jobs_share_the_cores_and_a_panic_only_fails_its_job() {
+        let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let jobs = [job("a", 0), job("b", 0), job("c", 3), job("d", 0)];
+        let threads = Mutex::new(vec![]);
+        let summary = run_batch(&jobs, 2, |job| {
+            let output = job.output.clone().unwrap();
+            threads.lock().unwrap().push((output.clone(), job.options.threads));
+            if output == "b" {
+                panic!("bad job");
+            }
+            Ok(output.len())
+        });
+
+        // Jobs left to pick their threads get half the cores each, the others keep theirs
+        let mut threads = threads.into_inner().unwrap();
+        threads.sort();
+        let half = (cores / 2).max(1);
+        let expected = [("a", half), ("b", half), ("c", 3), ("d", half)];
+        assert_eq!(threads, expected.map(|(output, threads)| (output.to_string(), threads)));
+
+        // The panic is reported in its job's slot and the rest still run
+        let outputs = summary.jobs.iter().map(|j| j.output.as_str()).collect::<Vec<_>>();
+        assert_eq!(outputs, ["a", "b", "c", "d"]);
+        assert_eq!(summary.failures(), 1);
+        assert_eq!(summary.jobs[1].error.as_deref(), Some("Job panicked: bad job"));
+        assert!(summary.jobs.iter().filter(|j| j.output != "b").all(|j| j.bricks == Some(1)));
+    }
+
+    #[test]
+    fn manifest_jobs_start_from_the_defaults() {
+        let dir = temp_dir("manifest");
+        let manifest = dir.join("jobs.toml");
+        let text = "[defaults]\nowner = \"Builder\"\n\n[defaults.options]\nscale = 20\ncull = true\n\n\
+                    [[jobs]]\ninputs = [\"a.png\"]\noutput = \"a.brs\"\n\n\
+                    [[jobs]]\ninputs = [\"b.png\"]\nowner = \"Someone\"\n\n[jobs.options]\nscale = 5\n";
+        fs::write(&manifest, text).unwrap();
+        let jobs = read_manifest(manifest.to_str().unwrap()).unwrap();
+
+        // Options are merged field by field, everything else is replaced
+        assert_eq!(jobs.len(), 2);
+        assert_eq!((jobs[0].owner.as_deref(), jobs[0].output.as_deref()), (Some("Builder"), Some("a.brs")));
+        assert_eq!((jobs[0].options.scale, jobs[0].options.cull), (20, true));
+        assert_eq!((jobs[1].owner.as_deref(), jobs[1].output.as_deref()), (Some("Someone"), None));
+        assert_eq!((jobs[1].options.scale, jobs[1].options.cull), (5, true));
+
+        // A job that doesn't validate is named
+        fs::write(&manifest, "[[jobs]]\ninputs = [\"a.png\"]\n\n[[jobs]]\n[jobs.options]\nscale = 0\n").unwrap();
+        let err = read_manifest(manifest.to_str().unwrap()).unwrap_err().to_string();
+        fs::remove_dir_all(&dir).ok();
+        assert!(err.contains("job 2"), "{}", err);
+    }
+
+    #[test]
+    fn patterns_make_a_job_per_matching_file() {
+        let dir = temp_dir("glob");
+        for name in ["hills_height.png", "lake_height.png", "lake_color.png", "notes_height.txt"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        fs::create_dir(dir.join("dir_height.png")).unwrap();
+        let pattern = dir.join("*_height.png");
+        let defaults = JobConfig { owner: Some("Builder".to_string()), ..JobConfig::default() };
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        // Matches are sorted, and only files match
+        let jobs = glob_jobs(pattern.to_str().unwrap(), Some(&path("*_color.png")), Some("out/*.brs"), &defaults).unwrap();
+        let found: Vec<_> = jobs
+            .iter()
+            .map(|job| (job.inputs.clone(), job.colormap.clone(), job.output.clone(), job.owner.clone()))
+            .collect();
+        let builder = Some("Builder".to_string());
+        assert_eq!(
+            found,
+            [
+                (vec![path("hills_height.png")], Some(path("hills_color.png")), Some("out/hills.brs".to_string()), builder.clone()),
+                (vec![path("lake_height.png")], Some(path("lake_color.png")), Some("out/lake.brs".to_string()), builder),
+            ]
+        );
+
+        // Without patterns for them, each map colors itself and is saved next to its heightmap
+        let jobs = glob_jobs(pattern.to_str().unwrap(), None, None, &JobConfig::default()).unwrap();
+        assert_eq!(jobs[0].colormap, None);
+        assert_eq!(jobs[0].output, Some(path("hills_height.brs")));
+
+        let two_stars = dir.join("*_*.png");
+        let err = glob_jobs(two_stars.to_str().unwrap(), None, None, &defaults);
+        fs::remove_dir_all(&dir).ok();
+        assert!(matches!(err, Err(GenError::Invalid(_))));
+    }
+}
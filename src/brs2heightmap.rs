@@ -0,0 +1,108 @@
+// Use the library crate for save reading and rasterization
+use heightmap::{error::GenError, raster::*, util::read_save};
+// External crate imports for command-line parsing and logging
+use clap::{clap_app, ArgMatches}; // Command-line argument parsing macro and parsed arguments
+use env_logger::Builder;          // Configures logging output
+use log::{error, info, LevelFilter}; // Logging macros and level filtering
+use std::io::Write;               // Writes formatted log lines
+
+fn main() {
+    // Configure logging the same way as the heightmap binary
+    Builder::new()
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .filter(None, LevelFilter::Info)
+        .init();
+
+    // The scaling options match the heightmap binary so the images regenerate the same terrain
+    let matches = clap_app!(brs2heightmap =>
+        (version: env!("CARGO_PKG_VERSION"))
+        (author: "github.com/Meshiest")
+        (about: "Converts Brickadia save files back to heightmap and colormap png files")
+        (@arg INPUT: +required "Input BRS file")
+        (@arg output: -o --output +takes_value "Output 16-bit heightmap PNG (default heightmap.png)")
+        (@arg colormap: -c --colormap +takes_value "Output colormap PNG (default colormap.png)")
+        (@arg vertical: -v --vertical +takes_value "Vertical scale divisor (default 1)")
+        (@arg size: -s --size +takes_value "Pixel size in studs (default 1)")
+        (@arg micro: --micro "Pixel size is in micro bricks instead of studs")
+        (@arg lrgb: --lrgb "Write linear rgb colors instead of sRGB")
+    )
+    .get_matches();
+
+    let in_file = matches.value_of("INPUT").unwrap();
+    let heightmap_file = matches.value_of("output").unwrap_or("heightmap.png");
+    let colormap_file = matches.value_of("colormap").unwrap_or("colormap.png");
+
+    let size = match parse_arg(&matches, "size", "Size", 1) {
+        Ok(size) => size,
+        Err(err) => return error!("{}", err),
+    };
+    let scale = match parse_arg(&matches, "vertical", "Scale", 1) {
+        Ok(scale) => scale,
+        Err(err) => return error!("{}", err),
+    };
+    // Micro bricks are 1/5 the size of regular bricks
+    let units = if matches.is_present("micro") { Some(size) } else { size.checked_mul(5) };
+    let options = match units {
+        Some(size) => RasterOptions {
+            size,
+            scale,
+            lrgb: matches.is_present("lrgb"),
+        },
+        None => return error!("Pixel size {} is too large", size),
+    };
+
+    info!("Reading save {}", in_file);
+    let save = match read_save(in_file) {
+        Ok(save) => save,
+        Err(err) => {
+            return error!("Error reading save: {}", err);
+        }
+    };
+
+    info!("Rasterizing {} bricks", save.bricks.len());
+    let (heightmap, colormap) = match save_to_maps(&save, &options) {
+        Ok(maps) => maps,
+        Err(err) => {
+            return error!("Error rasterizing save: {}", err);
+        }
+    };
+
+    info!(
+        "Writing {}x{} images to {} and {}",
+        heightmap.width(),
+        heightmap.height(),
+        heightmap_file,
+        colormap_file
+    );
+    if let Err(err) = heightmap.save(heightmap_file) {
+        return error!("Error writing heightmap: {}", err);
+    }
+    if let Err(err) = colormap.save(colormap_file) {
+        return error!("Error writing colormap: {}", err);
+    }
+
+    // 16-bit heightmaps are read at full precision with --hdmap
+    info!(
+        "Done! Regenerate with: heightmap {} -c {} --hdmap -s {} -v {}{}{}",
+        heightmap_file,
+        colormap_file,
+        size,
+        options.scale,
+        if matches.is_present("micro") { " --micro" } else { "" },
+        if options.lrgb { " --lrgb" } else { "" },
+    );
+}
+
+/// Parse an optional whole number argument, reporting bad values like the heightmap binary
+///
+/// # Returns
+/// * `Ok(value)` with the parsed value, or `default` if the argument wasn't given
+/// * `Err(GenError::Invalid)` if the value isn't a whole number
+fn parse_arg(matches: &ArgMatches, name: &str, what: &str, default: u32) -> Result<u32, GenError> {
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse::<u32>()
+            .map_err(|_| GenError::Invalid(format!("{} must be a whole number, got '{}'", what, value))),
+        None => Ok(default),
+    }
+}
@@ -0,0 +1,226 @@
+// Import our error, map, quadtree and utility modules
+use crate::error::*; // Error type
+use crate::map::*;   // Heightmap and Colormap traits and the scaling/quantizing adapters
+use crate::progress::*; // Progress reporting and cancellation
+use crate::quad::*;  // Brick generation
+use crate::stats::*; // Generation statistics
+use crate::util::*;  // Generation options
+// Import Brickadia save file structures
+use brickadia::save::Brick;
+// Import logging for progress updates
+use log::info;
+// Import serde for including the choice in JSON reports
+use serde::Serialize;
+
+/// Settings tried by budget mode, ordered from highest to lowest fidelity
+/// Each entry is (horizontal downsample factor, color channel step, height step)
+const LADDER: [(u32, u8, u32); 17] = [
+    (1, 1, 1),
+    (1, 4, 1),
+    (1, 8, 2),
+    (1, 16, 4),
+    (2, 8, 2),
+    (2, 16, 4),
+    (2, 32, 8),
+    (3, 16, 4),
+    (4, 16, 4),
+    (4, 32, 8),
+    (6, 32, 8),
+    (8, 32, 8),
+    (8, 64, 16),
+    (12, 64, 16),
+    (16, 64, 16),
+    (24, 64, 32),
+    (32, 128, 32),
+];
+
+/// One configuration tried in budget mode and the number of bricks it produced
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetAttempt {
+    /// Number of source pixels per brick pixel along each axis
+    pub downsample: u32,
+    /// Color channels are rounded to a multiple of this
+    pub color_step: u8,
+    /// Heights are rounded down to a multiple of this
+    pub height_step: u32,
+    /// Number of bricks generated with these settings
+    pub bricks: usize,
+}
+
+/// The outcome of budget mode, included in the generation report
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetChoice {
+    /// The brick budget that had to be met
+    pub max_bricks: usize,
+    /// The chosen (highest fidelity) settings that fit the budget
+    pub chosen: BudgetAttempt,
+    /// Every configuration tried, in order
+    pub attempts: Vec<BudgetAttempt>,
+}
+
+/// Generate bricks with the highest fidelity settings that stay under a brick budget
+/// Runs the full pipeline with progressively coarser horizontal scale, color and height
+/// quantization until the brick count fits
+///
+/// # Arguments
+/// * `heightmap` - Source of elevation data
+/// * `colormap` - Source of color data
+/// * `options` - Base generation options, `size` is multiplied by the downsample factor
+/// * `max_bricks` - Maximum number of bricks allowed
+/// * `progress` - Receives the phase and progress of the attempts, and can cancel generation
+///   Each attempt takes a share of the progress by its number of pixels, as if every attempt had to run
+///
+/// # Returns
+/// * `Ok((Vec<Brick>, GenStats))` - Bricks and statistics for the chosen settings, with `stats.budget` set
+/// * `Err(GenError)` - `LimitExceeded` if even the coarsest settings exceed the budget, or why generation failed
+pub fn gen_within_budget(
+    heightmap: &(dyn Heightmap + Sync),
+    colormap: &(dyn Colormap + Sync),
+    options: &GenOptions,
+    max_bricks: usize,
+    progress: &dyn Progress,
+) -> Result<(Vec<Brick>, GenStats), GenError> {
+    options.validate()?;
+    let progress = ProgressScope::new(progress);
+    let mut attempts: Vec<BudgetAttempt> = vec![];
+
+    // Attempts stop once pixels would be too large to be bricks, everything after is coarser
+    let ladder: Vec<_> = LADDER
+        .iter()
+        .copied()
+        .take_while(|&(downsample, _, _)| {
+            options.size.checked_mul(downsample).is_some_and(|size| size <= options.limits().max_xy)
+        })
+        .collect();
+    // Validated options always fit the first step, but the error names the limit rather than a brick count
+    if ladder.is_empty() {
+        return Err(GenError::LimitExceeded(format!(
+            "Bricks can't be {} units wide (at most {})",
+            u64::from(options.size) * 2,
+            u64::from(options.limits().max_xy) * 2
+        )));
+    }
+
+    // Each attempt's share of the progress is its number of pixels
+    let area = |downsample| {
+        let (width, height) = HeightmapScaled::new(heightmap, downsample).size();
+        width as f32 * height as f32
+    };
+    let total = ladder.iter().map(|&(downsample, _, _)| area(downsample)).sum::<f32>().max(1.0);
+    let mut before = 0.0;
+
+    for (downsample, color_step, height_step) in ladder {
+        info!(
+            "Budget attempt: {}x downsample, color step {}, height step {}",
+            downsample, color_step, height_step
+        );
+
+        // Layer the adapters over the source maps
+        let scaled_height = HeightmapScaled::new(heightmap, downsample);
+        let scaled_color = ColormapScaled::new(colormap, downsample);
+        let height = HeightmapQuantized::new(&scaled_height, height_step);
+        let color = ColormapQuantized::new(&scaled_color, color_step);
+
+        // Larger pixels keep the terrain the same size in the world
+        let mut attempt_options = options.clone();
+        attempt_options.size *= downsample;
+
+        let attempt_progress = progress.part(before / total, (before + area(downsample)) / total);
+        before += area(downsample);
+        let (bricks, mut stats) = gen_opt_heightmap_scoped(&height, &color, attempt_options, attempt_progress)?;
+
+        let attempt = BudgetAttempt {
+            downsample,
+            color_step,
+            height_step,
+            bricks: bricks.len(),
+        };
+        attempts.push(attempt.clone());
+
+        if bricks.len() <= max_bricks {
+            info!(
+                "Chose {}x downsample, color step {}, height step {} ({} of {} bricks)",
+                downsample,
+                color_step,
+                height_step,
+                bricks.len(),
+                max_bricks
+            );
+            stats.budget = Some(BudgetChoice {
+                max_bricks,
+                chosen: attempt,
+                attempts,
+            });
+            return Ok((bricks, stats));
+        }
+        info!("  {} bricks is over the budget of {}", bricks.len(), max_bricks);
+    }
+
+    Err(GenError::LimitExceeded(format!(
+        "Could not fit under {} bricks, the coarsest settings produced {}",
+        max_bricks,
+        attempts.last().map(|a| a.bricks).unwrap_or(0)
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bumpy map where every pixel has its own color, so coarser settings give fewer bricks
+    fn terrain(size: u32) -> (HeightmapGrid, ColormapGrid) {
+        let heights = (0..size * size).map(|i| 10 + (i % size * 13 + i / size * 7) % 50).collect();
+        let colors = (0..size * size).map(|i| [(i * 7) as u8, (i * 3) as u8, (i / size * 8) as u8, 255]).collect();
+        (
+            HeightmapGrid::new(heights, (size, size)).unwrap(),
+            ColormapGrid::new(colors, (size, size), true).unwrap(),
+        )
+    }
+
+    #[test]
+    fn budget_picks_the_first_settings_that_fit() {
+        let (heightmap, colormap) = terrain(32);
+        let options = GenOptions::default();
+        let full = gen_opt_heightmap(&heightmap, &colormap, options.clone(), &NoProgress).unwrap().len();
+
+        // A budget the full fidelity map fits in is met on the first attempt
+        let (bricks, stats) = gen_within_budget(&heightmap, &colormap, &options, full, &NoProgress).unwrap();
+        let choice = stats.budget.unwrap();
+        assert_eq!(bricks.len(), full);
+        assert_eq!(choice.attempts.len(), 1);
+        assert_eq!((choice.chosen.downsample, choice.chosen.color_step, choice.chosen.height_step), LADDER[0]);
+
+        // A tighter budget walks down the ladder until one fits, skipping nothing on the way
+        let max_bricks = full / 4;
+        let (bricks, stats) = gen_within_budget(&heightmap, &colormap, &options, max_bricks, &NoProgress).unwrap();
+        let choice = stats.budget.unwrap();
+        let (last, tried) = choice.attempts.split_last().unwrap();
+        assert!(!tried.is_empty());
+        assert!(tried.iter().all(|attempt| attempt.bricks > max_bricks));
+        assert!(last.bricks <= max_bricks);
+        assert_eq!(bricks.len(), last.bricks);
+        assert_eq!(stats.bricks, last.bricks);
+        for (attempt, &(downsample, color_step, height_step)) in choice.attempts.iter().zip(LADDER.iter()) {
+            assert_eq!((attempt.downsample, attempt.color_step, attempt.height_step), (downsample, color_step, height_step));
+        }
+    }
+
+    #[test]
+    fn budget_stops_at_the_largest_brick() {
+        let (heightmap, colormap) = terrain(32);
+
+        // Pixels of 40 studs can only be doubled, so the ladder ends after the 2x steps
+        let options = GenOptions::builder().pixel_size(40).build().unwrap();
+        let coarsest = {
+            let scaled_height = HeightmapScaled::new(&heightmap, 2);
+            let scaled_color = ColormapScaled::new(&colormap, 2);
+            let options = GenOptions::builder().pixel_size(80).build().unwrap();
+            let height = HeightmapQuantized::new(&scaled_height, 8);
+            let color = ColormapQuantized::new(&scaled_color, 32);
+            gen_opt_heightmap(&height, &color, options, &NoProgress).unwrap().len()
+        };
+        let err = gen_within_budget(&heightmap, &colormap, &options, 1, &NoProgress).unwrap_err();
+        assert!(matches!(err, GenError::LimitExceeded(_)));
+        assert!(err.to_string().ends_with(&format!("the coarsest settings produced {}", coarsest)), "{}", err);
+    }
+}
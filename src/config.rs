@@ -0,0 +1,140 @@
+// Import serde for (de)serializing job files
+use serde::{Deserialize, Serialize};
+// Import standard library items for file I/O
+use std::fs;
+
+// Import generation options and file helpers from our util module, and our error type
+use crate::{error::GenError, util::*};
+
+/// Text formats that generation jobs can be read from and printed as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// TOML (.toml)
+    Toml,
+    /// JSON (.json)
+    Json,
+}
+
+impl ConfigFormat {
+    /// Pick the config format from a file path's extension
+    /// Returns None if the extension isn't a config format
+    pub fn from_path(path: &str) -> Option<Self> {
+        match file_ext(path).map(|ext| ext.to_lowercase()).as_deref() {
+            Some("toml") => Some(ConfigFormat::Toml),
+            Some("json") => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Everything needed to generate one save: input and output paths, the owner and the options
+/// Missing fields keep their default, so a file only needs the settings that differ
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobConfig {
+    /// Input heightmap images (or a single brick dump)
+    pub inputs: Vec<String>,
+    /// Input colormap image, None to use the first heightmap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colormap: Option<String>,
+    /// Output save, mesh or brick dump, None for ./out.brs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// JSON generation report to write, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<String>,
+    /// Name of the brick owner, None for Generator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// UUID of the brick owner, None for the default generator id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<String>,
+    /// Generation options, with sizes in Brickadia units like `GenOptions`
+    pub options: GenOptions,
+}
+
+impl JobConfig {
+    /// Read a job file, picking TOML or JSON from its extension
+    ///
+    /// # Returns
+    /// * `Ok(JobConfig)` - If the file was read and its options validate
+    /// * `Err(GenError)` - If the file can't be read, has unknown fields or the options don't go together
+    pub fn read(path: &str) -> Result<Self, GenError> {
+        let format = ConfigFormat::from_path(path).ok_or_else(|| {
+            GenError::Unsupported(format!("Unknown config format for {}, expected .toml or .json", path))
+        })?;
+        let text = fs::read_to_string(path).map_err(|e| GenError::io(path, e))?;
+        let config: JobConfig = match format {
+            ConfigFormat::Toml => toml::from_str(&text).map_err(|e| GenError::decode(path, e))?,
+            ConfigFormat::Json => serde_json::from_str(&text).map_err(|e| GenError::decode(path, e))?,
+        };
+        config
+            .options
+            .validate()
+            .map_err(|e| GenError::Invalid(format!("{}: {}", path, e)))?;
+        Ok(config)
+    }
+
+    /// Print the job in a config format, so it can be saved and read back with `read`
+    pub fn to_text(&self, format: ConfigFormat) -> Result<String, GenError> {
+        match format {
+            // Going through a value puts plain fields before tables, as TOML requires
+            ConfigFormat::Toml => toml::Value::try_from(self)
+                .and_then(|value| toml::to_string_pretty(&value))
+                .map_err(|e| GenError::Invalid(format!("Could not write config: {}", e))),
+            ConfigFormat::Json => serde_json::to_string_pretty(self)
+                .map(|text| text + "\n")
+                .map_err(|e| GenError::Invalid(format!("Could not write config: {}", e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    /// Write a job file to a temporary path and read it back
+    fn read(name: &str, text: &str) -> Result<JobConfig, GenError> {
+        let path = env::temp_dir().join(format!("heightmap-test-{}-{}", process::id(), name));
+        fs::write(&path, text).unwrap();
+        let config = JobConfig::read(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+        config
+    }
+
+    #[test]
+    fn job_files_reject_unknown_fields_and_default_the_rest() {
+        let job = read("job.toml", "inputs = [\"map.png\"]\n\n[options]\nscale = 4\ncull = true\n").unwrap();
+        assert_eq!(job.inputs, ["map.png"]);
+        assert_eq!(job.output, None);
+        assert_eq!((job.options.scale, job.options.cull), (4, true));
+        assert_eq!(job.options.size, GenOptions::default().size);
+
+        // Misspelled fields are errors, at the top and inside the options and the subsoil
+        assert!(matches!(read("job.toml", "input = [\"map.png\"]\n"), Err(GenError::Decode { .. })));
+        assert!(matches!(read("job.json", r#"{"options": {"scael": 4}}"#), Err(GenError::Decode { .. })));
+        let subsoil = "[options.subsoil]\ncolor = [60, 40, 20, 255]\ndepht = 10\n";
+        assert!(matches!(read("job.toml", subsoil), Err(GenError::Decode { .. })));
+
+        // Options that don't go together are caught when the file is read
+        assert!(matches!(read("job.toml", "[options]\nimg = true\ngen_full_layers_above_height = 10\n"), Err(GenError::Invalid(_))));
+        assert!(matches!(read("job.yaml", ""), Err(GenError::Unsupported(_))));
+    }
+
+    #[test]
+    fn printed_jobs_read_back_the_same() {
+        let job = JobConfig {
+            inputs: vec!["a.png".to_string(), "b.png".to_string()],
+            colormap: Some("color.png".to_string()),
+            output: Some("out.brs".to_string()),
+            options: GenOptions::builder().kind(BrickKind::Tile).pixel_size(2).scale(12).glow(true).build().unwrap(),
+            ..JobConfig::default()
+        };
+        for (format, name) in [(ConfigFormat::Toml, "dump.toml"), (ConfigFormat::Json, "dump.json")] {
+            let text = job.to_text(format).unwrap();
+            let read_back = read(name, &text).unwrap();
+            assert_eq!(format!("{:?}", read_back), format!("{:?}", job), "{}", name);
+        }
+    }
+}
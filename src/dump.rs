@@ -0,0 +1,332 @@
+// Import Brickadia save file structures
+use brickadia::save::{Brick, BrickColor, Collision, Color, Size};
+// Import serde for (de)serializing brick records
+use serde::{Deserialize, Serialize};
+// Import standard library items for file I/O
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+};
+
+// Import asset and material name tables from our util module, and our error type
+use crate::{error::GenError, util::*};
+
+/// Text formats that bricks can be dumped to and loaded from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// One JSON object per line (.jsonl or .ndjson)
+    JsonLines,
+    /// Comma separated values with a header row (.csv)
+    Csv,
+}
+
+impl DumpFormat {
+    /// Pick the dump format from a file path's extension
+    /// Returns None if the file isn't a brick dump
+    pub fn from_path(path: &str) -> Option<Self> {
+        match file_ext(path).map(|ext| ext.to_lowercase()).as_deref() {
+            Some("jsonl") | Some("ndjson") => Some(DumpFormat::JsonLines),
+            Some("csv") => Some(DumpFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// A single brick in a flat, tool friendly layout
+/// Field names are the JSON keys and CSV column headers of the dump formats
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrickRecord {
+    /// Brick asset name (e.g. PB_DefaultBrick)
+    pub asset: String,
+    /// Half-extents of the brick in Brickadia units
+    pub size_x: u32,
+    pub size_y: u32,
+    pub size_z: u32,
+    /// Center position of the brick in Brickadia units
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    /// RGBA color of the brick, as stored in the save (linear RGB)
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+    /// Material name (e.g. BMC_Plastic)
+    pub material: String,
+    /// Material intensity (0-10)
+    pub material_intensity: u32,
+    /// Collision flags
+    pub collide_player: bool,
+    pub collide_weapon: bool,
+    pub collide_interaction: bool,
+    pub collide_tool: bool,
+    /// Name and UUID of the brick owner
+    pub owner: String,
+    pub owner_id: String,
+}
+
+impl BrickRecord {
+    /// Flatten a generated brick into a record
+    ///
+    /// # Arguments
+    /// * `brick` - The brick to convert
+    /// * `owner_id` - UUID string of the brick owner
+    /// * `owner_name` - Display name of the brick owner
+    pub fn from_brick(brick: &Brick, owner_id: &str, owner_name: &str) -> Self {
+        let (size_x, size_y, size_z) = match brick.size {
+            Size::Procedural(x, y, z) => (x, y, z),
+            Size::Empty => (0, 0, 0),
+        };
+        let [r, g, b, a] = match &brick.color {
+            BrickColor::Unique(c) => [c.r, c.g, c.b, c.a],
+            BrickColor::Index(_) => [255, 255, 255, 255],
+        };
+
+        BrickRecord {
+            asset: BRICK_ASSETS
+                .get(brick.asset_name_index as usize)
+                .unwrap_or(&BRICK_ASSETS[0])
+                .to_string(),
+            size_x,
+            size_y,
+            size_z,
+            x: brick.position.0,
+            y: brick.position.1,
+            z: brick.position.2,
+            r,
+            g,
+            b,
+            a,
+            material: MATERIALS
+                .get(brick.material_index as usize)
+                .unwrap_or(&MATERIALS[0])
+                .to_string(),
+            material_intensity: brick.material_intensity,
+            collide_player: brick.collision.player,
+            collide_weapon: brick.collision.weapon,
+            collide_interaction: brick.collision.interaction,
+            collide_tool: brick.collision.tool,
+            owner: owner_name.to_string(),
+            owner_id: owner_id.to_string(),
+        }
+    }
+
+    /// Convert this record back into a brick for `bricks_to_save`
+    ///
+    /// # Returns
+    /// * `Ok(Brick)` if the asset and material names are known
+    /// * `Err(GenError::Invalid)` if the record references an unknown asset or material
+    pub fn to_brick(&self) -> Result<Brick, GenError> {
+        let asset = BRICK_ASSETS
+            .iter()
+            .position(|&a| a == self.asset)
+            .ok_or_else(|| GenError::Invalid(format!("Unknown brick asset '{}'", self.asset)))?;
+        let material = MATERIALS
+            .iter()
+            .position(|&m| m == self.material)
+            .ok_or_else(|| GenError::Invalid(format!("Unknown material '{}'", self.material)))?;
+
+        Ok(Brick {
+            asset_name_index: asset as u32,
+            size: if self.size_x == 0 && self.size_y == 0 && self.size_z == 0 {
+                Size::Empty
+            } else {
+                Size::Procedural(self.size_x, self.size_y, self.size_z)
+            },
+            position: (self.x, self.y, self.z),
+            collision: Collision {
+                player: self.collide_player,
+                weapon: self.collide_weapon,
+                interaction: self.collide_interaction,
+                tool: self.collide_tool,
+            },
+            color: BrickColor::Unique(Color {
+                r: self.r,
+                g: self.g,
+                b: self.b,
+                a: self.a,
+            }),
+            owner_index: 1,  // bricks_to_save writes a single owner
+            material_index: material as u32,
+            material_intensity: self.material_intensity,
+            ..Default::default()
+        })
+    }
+}
+
+/// Write bricks as a JSON Lines or CSV dump
+///
+/// # Arguments
+/// * `bricks` - Bricks to dump
+/// * `format` - Dump format to write
+/// * `out` - Writer for the dump
+/// * `owner_id` - UUID string of the brick owner
+/// * `owner_name` - Display name of the brick owner
+pub fn write_dump<W: Write>(
+    bricks: &[Brick],
+    format: DumpFormat,
+    mut out: W,
+    owner_id: &str,
+    owner_name: &str,
+) -> io::Result<()> {
+    match format {
+        DumpFormat::JsonLines => {
+            for brick in bricks {
+                serde_json::to_writer(&mut out, &BrickRecord::from_brick(brick, owner_id, owner_name))?;
+                writeln!(out)?;
+            }
+            out.flush()
+        }
+        DumpFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for brick in bricks {
+                writer.serialize(BrickRecord::from_brick(brick, owner_id, owner_name))?;
+            }
+            writer.flush()
+        }
+    }
+}
+
+/// Read brick records from a JSON Lines or CSV dump file
+/// Blank lines in JSON Lines files are skipped
+///
+/// # Arguments
+/// * `file` - Path to the dump file, the format is picked from its extension
+///
+/// # Returns
+/// * `Ok(Vec<BrickRecord>)` if every record parsed
+/// * `Err(GenError)` with the offending line if the file couldn't be read or parsed
+pub fn read_dump(file: &str) -> Result<Vec<BrickRecord>, GenError> {
+    let format = DumpFormat::from_path(file)
+        .ok_or_else(|| GenError::Unsupported(format!("Unsupported brick dump format for '{}'", file)))?;
+    let reader = BufReader::new(File::open(file).map_err(|e| GenError::io(file, e))?);
+
+    match format {
+        DumpFormat::JsonLines => {
+            let mut records = vec![];
+            for (i, line) in reader.lines().enumerate() {
+                let line = line.map_err(|e| GenError::io(file, e))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                records.push(
+                    serde_json::from_str(&line)
+                        .map_err(|e| GenError::decode(file, format!("line {}: {}", i + 1, e)))?,
+                );
+            }
+            Ok(records)
+        }
+        DumpFormat::Csv => csv::Reader::from_reader(reader)
+            .deserialize()
+            .collect::<Result<Vec<BrickRecord>, _>>()
+            .map_err(|e| GenError::decode(file, e)),
+    }
+}
+
+/// Convert brick records back into bricks
+///
+/// # Returns
+/// * `Ok(Vec<Brick>)` if every record converted
+/// * `Err(GenError::Invalid)` with the record number if any record is invalid
+pub fn records_to_bricks(records: &[BrickRecord]) -> Result<Vec<Brick>, GenError> {
+    records
+        .iter()
+        .enumerate()
+        .map(|(i, r)| r.to_brick().map_err(|e| GenError::Invalid(format!("record {}: {}", i + 1, e))))
+        .collect()
+}
+
+/// Owner of the bricks of a dump, which is written to a save with a single owner
+///
+/// # Returns
+/// * `Ok(Some((owner_id, owner_name)))` if every record has the same owner, None for an empty dump
+/// * `Err(GenError::Invalid)` with the first record that has another owner
+pub fn records_owner(records: &[BrickRecord]) -> Result<Option<(String, String)>, GenError> {
+    let first = match records.first() {
+        Some(first) => first,
+        None => return Ok(None),
+    };
+    match records
+        .iter()
+        .position(|r| r.owner_id != first.owner_id || r.owner != first.owner)
+    {
+        Some(i) => Err(GenError::Invalid(format!(
+            "record {}: owner '{}' ({}) differs from '{}' ({}), bricks can only be loaded with one owner",
+            i + 1,
+            records[i].owner,
+            records[i].owner_id,
+            first.owner,
+            first.owner_id
+        ))),
+        None => Ok(Some((first.owner_id.clone(), first.owner.clone()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{map::*, progress::NoProgress, quad::*};
+    use std::{env, fs, process};
+
+    /// Bricks of a small map with a glowing, non-colliding surface on a tile subsoil
+    fn bricks() -> Vec<Brick> {
+        let heights = (0..64).map(|i| 20 + i % 8 * 9 + i / 8 * 4).collect();
+        let colors = (0..64).map(|i| [(i * 3) as u8, 120, (255 - i) as u8, 255]).collect();
+        let heightmap = HeightmapGrid::new(heights, (8, 8)).unwrap();
+        let colormap = ColormapGrid::new(colors, (8, 8), true).unwrap();
+        let subsoil = Subsoil {
+            color: [60, 40, 20, 255],
+            depth: 20,
+            kind: Some(BrickKind::Tile),
+            collide: true,
+        };
+        let options = GenOptions::builder().glow(true).nocollide(true).subsoil(Some(subsoil)).build().unwrap();
+        gen_opt_heightmap(&heightmap, &colormap, options, &NoProgress).unwrap()
+    }
+
+    #[test]
+    fn dumps_load_back_the_same_bricks() {
+        let bricks = bricks();
+        for ext in ["jsonl", "csv"] {
+            let path = env::temp_dir().join(format!("heightmap-test-{}-dump.{}", process::id(), ext));
+            let file = path.to_str().unwrap();
+            let format = DumpFormat::from_path(file).unwrap();
+            write_dump(&bricks, format, File::create(&path).unwrap(), "0c9c6e35-3a8c-4e4f-a11f-2b7b1c4c6f6e", "Dumper")
+                .unwrap();
+            let records = read_dump(file);
+            fs::remove_file(&path).ok();
+
+            let records = records.unwrap();
+            let loaded = records_to_bricks(&records).unwrap();
+            assert_eq!(format!("{:?}", loaded), format!("{:?}", bricks), "{} dump", ext);
+            assert_eq!(
+                records_owner(&records).unwrap(),
+                Some(("0c9c6e35-3a8c-4e4f-a11f-2b7b1c4c6f6e".to_string(), "Dumper".to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn dumps_with_bad_lines_or_several_owners_are_rejected() {
+        let path = env::temp_dir().join(format!("heightmap-test-{}-bad.jsonl", process::id()));
+        let file = path.to_str().unwrap();
+        let mut out = vec![];
+        write_dump(&bricks()[..2], DumpFormat::JsonLines, &mut out, "0c9c6e35-3a8c-4e4f-a11f-2b7b1c4c6f6e", "Dumper")
+            .unwrap();
+
+        // Blank lines are skipped, but a broken line is named
+        let text = String::from_utf8(out).unwrap();
+        fs::write(&path, format!("{}\n\n{{\"x\": 1}}\n", text.trim_end())).unwrap();
+        let broken = read_dump(file);
+        fs::write(&path, format!("\n{}", text)).unwrap();
+        let records = read_dump(file);
+        fs::remove_file(&path).ok();
+        assert!(broken.unwrap_err().to_string().contains("line 4"));
+
+        let mut records = records.unwrap();
+        assert_eq!(records.len(), 2);
+        records[1].owner = "Someone else".to_string();
+        assert!(records_owner(&records).unwrap_err().to_string().starts_with("record 2"));
+        assert_eq!(records_owner(&[]).unwrap(), None);
+    }
+}
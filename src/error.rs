@@ -0,0 +1,82 @@
+// Import standard library items for error formatting and I/O errors
+use std::{error, fmt, io};
+
+/// Everything that can go wrong while reading inputs, generating bricks and writing outputs
+/// Match on the variant to handle a cause, or print it for a message that says what to fix
+#[derive(Debug)]
+pub enum GenError {
+    /// A file couldn't be opened, read or written
+    Io {
+        /// Path of the file
+        path: String,
+        /// The underlying I/O error
+        source: io::Error,
+    },
+    /// A file was read but its contents couldn't be decoded (a broken image, save, dump or cache)
+    Decode {
+        /// Path of the file
+        path: String,
+        /// What the decoder reported
+        reason: String,
+    },
+    /// Images that have to line up pixel for pixel have different sizes
+    DimensionMismatch {
+        /// Width and height of the first image
+        expected: (u32, u32),
+        /// Width and height of the image that doesn't match
+        found: (u32, u32),
+    },
+    /// A file format or output isn't supported
+    Unsupported(String),
+    /// An option or value couldn't be used, like a malformed color
+    Invalid(String),
+    /// Bricks or a brick budget would be beyond what's allowed
+    LimitExceeded(String),
+    /// The progress callback asked generation to stop
+    Cancelled,
+}
+
+impl GenError {
+    /// Wrap an I/O error with the path of the file it happened on
+    pub fn io(path: impl Into<String>, source: io::Error) -> Self {
+        GenError::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Describe a file whose contents couldn't be decoded
+    pub fn decode(path: impl Into<String>, reason: impl ToString) -> Self {
+        GenError::Decode {
+            path: path.into(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for GenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GenError::Io { path, source } => write!(f, "{}: {}", path, source),
+            GenError::Decode { path, reason } => write!(f, "Could not decode {}: {}", path, reason),
+            GenError::DimensionMismatch { expected, found } => write!(
+                f,
+                "Images must be the same size, expected {}x{} but found {}x{}",
+                expected.0, expected.1, found.0, found.1
+            ),
+            GenError::Unsupported(what) | GenError::Invalid(what) | GenError::LimitExceeded(what) => {
+                write!(f, "{}", what)
+            }
+            GenError::Cancelled => write!(f, "Stopped by user"),
+        }
+    }
+}
+
+impl error::Error for GenError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            GenError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,912 @@
+// Import Brickadia save file structures and the save writer
+use brickadia::{
+    save::{Brick, BrickColor, SaveData, Size},
+    write::SaveWriter,
+};
+// Import byte ordering for the binary glTF buffer and save sections
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+// Import zlib compression for streamed save sections
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+// Import standard library items for file output
+use std::{
+    env, fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+// Import the save builder, name tables and color helpers from our util module
+use crate::{dump::*, error::GenError, progress::*, util::*};
+
+/// Brickadia units are centimeters, exported meshes are in meters
+const UNITS_TO_METERS: f32 = 0.01;
+
+/// Corner indices (bit 0 = max X, bit 1 = max Y, bit 2 = max Z) for each face of a box,
+/// wound counter-clockwise when viewed from outside, paired with the face normal index
+const BOX_FACES: [([usize; 4], usize); 6] = [
+    ([0, 4, 6, 2], 0), // -X
+    ([1, 3, 7, 5], 1), // +X
+    ([0, 1, 5, 4], 2), // -Y (bottom)
+    ([2, 6, 7, 3], 3), // +Y (top)
+    ([0, 2, 3, 1], 4), // -Z
+    ([4, 5, 7, 6], 5), // +Z
+];
+
+/// Outward normals matching the normal indices in `BOX_FACES`
+const BOX_NORMALS: [[f32; 3]; 6] = [
+    [-1.0, 0.0, 0.0],
+    [1.0, 0.0, 0.0],
+    [0.0, -1.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, 0.0, -1.0],
+    [0.0, 0.0, 1.0],
+];
+
+/// Output file formats that generated bricks can be written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Brickadia save file (.brs)
+    Brs,
+    /// Wavefront OBJ mesh with a companion MTL material library (.obj)
+    Obj,
+    /// Binary glTF 2.0 mesh (.glb)
+    Glb,
+    /// Brick dump for external tools (.jsonl, .ndjson or .csv)
+    Dump(DumpFormat),
+}
+
+impl OutputFormat {
+    /// Pick the output format from a file path's extension
+    /// Anything that isn't a known mesh or dump extension is written as a Brickadia save
+    pub fn from_path(path: &str) -> Self {
+        if let Some(dump) = DumpFormat::from_path(path) {
+            return OutputFormat::Dump(dump);
+        }
+        match file_ext(path).map(|ext| ext.to_lowercase()).as_deref() {
+            Some("obj") => OutputFormat::Obj,
+            Some("glb") => OutputFormat::Glb,
+            _ => OutputFormat::Brs,
+        }
+    }
+}
+
+/// Human readable format names for log messages
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            OutputFormat::Brs => "Save",
+            OutputFormat::Obj => "OBJ mesh",
+            OutputFormat::Glb => "glTF mesh",
+            OutputFormat::Dump(DumpFormat::JsonLines) => "JSON Lines brick dump",
+            OutputFormat::Dump(DumpFormat::Csv) => "CSV brick dump",
+        })
+    }
+}
+
+/// Axis-aligned box covering a single brick in exported mesh space
+/// Mesh space is Y-up and right-handed, measured in meters
+struct BrickBox {
+    /// Minimum corner of the box
+    min: [f32; 3],
+    /// Maximum corner of the box
+    max: [f32; 3],
+    /// Linear RGBA color of the brick
+    color: [u8; 4],
+}
+
+impl BrickBox {
+    /// Build the exported box for a brick, or None for bricks without a procedural size
+    fn new(brick: &Brick) -> Option<Self> {
+        let (sx, sy, sz) = match brick.size {
+            Size::Procedural(x, y, z) => (x as f32, y as f32, z as f32),
+            Size::Empty => return None,
+        };
+        let (px, py, pz) = (
+            brick.position.0 as f32,
+            brick.position.1 as f32,
+            brick.position.2 as f32,
+        );
+
+        // Brickadia is Z-up and left-handed, swapping Y and Z makes it Y-up and right-handed
+        Some(BrickBox {
+            min: [
+                (px - sx) * UNITS_TO_METERS,
+                (pz - sz) * UNITS_TO_METERS,
+                (py - sy) * UNITS_TO_METERS,
+            ],
+            max: [
+                (px + sx) * UNITS_TO_METERS,
+                (pz + sz) * UNITS_TO_METERS,
+                (py + sy) * UNITS_TO_METERS,
+            ],
+            color: match &brick.color {
+                BrickColor::Unique(c) => [c.r, c.g, c.b, c.a],
+                // Colorset colors aren't known without the save's palette
+                BrickColor::Index(_) => [255, 255, 255, 255],
+            },
+        })
+    }
+
+    /// Get the position of one of the 8 corners of this box
+    fn corner(&self, i: usize) -> [f32; 3] {
+        [
+            if i & 1 == 0 { self.min[0] } else { self.max[0] },
+            if i & 2 == 0 { self.min[1] } else { self.max[1] },
+            if i & 4 == 0 { self.min[2] } else { self.max[2] },
+        ]
+    }
+}
+
+/// Group exported brick boxes by material index
+/// Bricks with materials outside of `MATERIALS` are grouped with plastic
+fn boxes_by_material(bricks: &[Brick]) -> Vec<Vec<BrickBox>> {
+    let mut groups: Vec<Vec<BrickBox>> = MATERIALS.iter().map(|_| vec![]).collect();
+    for brick in bricks {
+        if let Some(b) = BrickBox::new(brick) {
+            let material = brick.material_index as usize;
+            groups[if material < MATERIALS.len() { material } else { 0 }].push(b);
+        }
+    }
+    groups
+}
+
+/// Write bricks as a Wavefront OBJ mesh with a material library
+/// Each brick becomes a box with per-vertex colors, grouped by brick material
+///
+/// # Arguments
+/// * `bricks` - Bricks to export
+/// * `obj` - Writer for the OBJ geometry
+/// * `mtl` - Writer for the MTL material library
+/// * `mtl_name` - File name of the material library, referenced from the OBJ
+pub fn write_obj<W: Write, M: Write>(
+    bricks: &[Brick],
+    mut obj: W,
+    mtl: M,
+    mtl_name: &str,
+) -> io::Result<()> {
+    write_obj_header(&mut obj, mtl, mtl_name)?;
+    write_obj_bricks(&mut obj, bricks, &mut 1)?;
+    obj.flush()
+}
+
+/// Write the material library and the start of an OBJ mesh, before any bricks
+fn write_obj_header<W: Write, M: Write>(obj: &mut W, mut mtl: M, mtl_name: &str) -> io::Result<()> {
+    // Materials only carry the surface type, brick colors are stored per vertex
+    for name in MATERIALS {
+        writeln!(mtl, "newmtl {}", name)?;
+        writeln!(mtl, "Kd 1.000 1.000 1.000")?;
+        if name == "BMC_Glow" {
+            writeln!(mtl, "Ke 1.000 1.000 1.000")?;
+        }
+        writeln!(mtl, "illum 2")?;
+        writeln!(mtl)?;
+    }
+
+    writeln!(obj, "# Generated by heightmap2brs")?;
+    writeln!(obj, "mtllib {}", mtl_name)?;
+    writeln!(obj, "o terrain")?;
+    for n in BOX_NORMALS {
+        writeln!(obj, "vn {} {} {}", n[0], n[1], n[2])?;
+    }
+    mtl.flush()
+}
+
+/// Write bricks as boxes in an OBJ mesh, grouped by material
+/// Can be called several times to add more bricks to the same mesh
+///
+/// # Arguments
+/// * `obj` - Writer for the OBJ geometry
+/// * `bricks` - Bricks to add
+/// * `vertex_offset` - Index of the next vertex, OBJ indices are 1-based and count every vertex written so far
+fn write_obj_bricks<W: Write>(obj: &mut W, bricks: &[Brick], vertex_offset: &mut usize) -> io::Result<()> {
+    for (material, boxes) in boxes_by_material(bricks).iter().enumerate() {
+        if boxes.is_empty() {
+            continue;
+        }
+        writeln!(obj, "g {}", MATERIALS[material])?;
+        writeln!(obj, "usemtl {}", MATERIALS[material])?;
+
+        for b in boxes {
+            // OBJ vertex colors are display colors, so convert back to sRGB
+            let [r, g, bl] = [b.color[0], b.color[1], b.color[2]]
+                .map(|c| to_srgb_gamma(c) as f32 / 255.0);
+            for i in 0..8 {
+                let [x, y, z] = b.corner(i);
+                writeln!(obj, "v {:.2} {:.2} {:.2} {:.4} {:.4} {:.4}", x, y, z, r, g, bl)?;
+            }
+            for (corners, normal) in BOX_FACES {
+                let [a, b, c, d] = corners.map(|i| i + *vertex_offset);
+                let n = normal + 1;
+                writeln!(obj, "f {a}//{n} {b}//{n} {c}//{n} {d}//{n}")?;
+            }
+            *vertex_offset += 8;
+        }
+    }
+    Ok(())
+}
+
+/// Write bricks as a binary glTF 2.0 (.glb) mesh
+/// Each brick becomes a box with per-vertex colors, with one primitive per brick material
+///
+/// # Arguments
+/// * `bricks` - Bricks to export
+/// * `out` - Writer for the .glb file
+pub fn write_glb<W: Write>(bricks: &[Brick], mut out: W) -> io::Result<()> {
+    let mut bin: Vec<u8> = vec![];
+    let mut buffer_views = vec![];
+    let mut accessors = vec![];
+    let mut materials = vec![];
+    let mut primitives = vec![];
+
+    // Append a section to the binary buffer as a new buffer view, keeping 4 byte alignment
+    let mut push_view = |bin: &mut Vec<u8>, data: Vec<u8>, target: u32| {
+        buffer_views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+            bin.len(),
+            data.len(),
+            target
+        ));
+        bin.extend(data);
+        buffer_views.len() - 1
+    };
+
+    for (material, boxes) in boxes_by_material(bricks).iter().enumerate() {
+        if boxes.is_empty() {
+            continue;
+        }
+
+        // Faces need their own vertices to have flat normals, so every box has 24 vertices
+        let vertex_count = boxes.len() * 24;
+        let mut positions = Vec::with_capacity(vertex_count * 12);
+        let mut normals = Vec::with_capacity(vertex_count * 12);
+        let mut colors = Vec::with_capacity(vertex_count * 4);
+        let mut indices = Vec::with_capacity(boxes.len() * 36 * 4);
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+
+        for (i, b) in boxes.iter().enumerate() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(b.min[axis]);
+                max[axis] = max[axis].max(b.max[axis]);
+            }
+            for (face, (corners, normal)) in BOX_FACES.iter().enumerate() {
+                for &corner in corners {
+                    for v in b.corner(corner) {
+                        positions.write_f32::<LittleEndian>(v)?;
+                    }
+                    for v in BOX_NORMALS[*normal] {
+                        normals.write_f32::<LittleEndian>(v)?;
+                    }
+                    // glTF vertex colors are linear, which is what bricks store
+                    colors.extend(b.color);
+                }
+                // Two triangles per quad face
+                let base = (i * 24 + face * 4) as u32;
+                for offset in [0, 1, 2, 0, 2, 3] {
+                    indices.write_u32::<LittleEndian>(base + offset)?;
+                }
+            }
+        }
+
+        let position_view = push_view(&mut bin, positions, 34962);
+        let normal_view = push_view(&mut bin, normals, 34962);
+        let color_view = push_view(&mut bin, colors, 34962);
+        let index_view = push_view(&mut bin, indices, 34963);
+
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+            position_view, vertex_count, min[0], min[1], min[2], max[0], max[1], max[2]
+        ));
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC3"}}"#,
+            normal_view, vertex_count
+        ));
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":5121,"normalized":true,"count":{},"type":"VEC4"}}"#,
+            color_view, vertex_count
+        ));
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+            index_view,
+            boxes.len() * 36
+        ));
+
+        let name = MATERIALS[material];
+        materials.push(format!(
+            r#"{{"name":"{}","pbrMetallicRoughness":{{"metallicFactor":0.0,"roughnessFactor":0.6}}{}}}"#,
+            name,
+            if name == "BMC_Glow" {
+                r#","emissiveFactor":[1.0,1.0,1.0]"#
+            } else {
+                ""
+            }
+        ));
+
+        let first = accessors.len() - 4;
+        primitives.push(format!(
+            r#"{{"attributes":{{"POSITION":{},"NORMAL":{},"COLOR_0":{}}},"indices":{},"material":{}}}"#,
+            first,
+            first + 1,
+            first + 2,
+            first + 3,
+            materials.len() - 1
+        ));
+    }
+
+    // A mesh needs at least one primitive, so an empty export is just an empty node
+    let json = if primitives.is_empty() {
+        r#"{"asset":{"version":"2.0","generator":"heightmap2brs"},"scene":0,"scenes":[{"nodes":[0]}],"nodes":[{"name":"terrain"}]}"#.to_string()
+    } else {
+        format!(
+            r#"{{"asset":{{"version":"2.0","generator":"heightmap2brs"}},"scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"name":"terrain","mesh":0}}],"meshes":[{{"name":"terrain","primitives":[{}]}}],"materials":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{}}}]}}"#,
+            primitives.join(","),
+            materials.join(","),
+            accessors.join(","),
+            buffer_views.join(","),
+            bin.len()
+        )
+    };
+
+    // Chunks must be 4 byte aligned, JSON is padded with spaces and binary with zeros
+    let mut json = json.into_bytes();
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+
+    let has_bin = !bin.is_empty();
+    let total_len = 12 + 8 + json.len() + if has_bin { 8 + bin.len() } else { 0 };
+
+    // Header: magic, version, total length
+    out.write_all(b"glTF")?;
+    out.write_u32::<LittleEndian>(2)?;
+    out.write_u32::<LittleEndian>(total_len as u32)?;
+
+    // JSON chunk
+    out.write_u32::<LittleEndian>(json.len() as u32)?;
+    out.write_all(b"JSON")?;
+    out.write_all(&json)?;
+
+    // Binary chunk
+    if has_bin {
+        out.write_u32::<LittleEndian>(bin.len() as u32)?;
+        out.write_all(b"BIN\0")?;
+        out.write_all(&bin)?;
+    }
+
+    out.flush()
+}
+
+/// Create the output of a save, which is standard output for `STDIO_PATH`
+fn create_save_output(path: &Path) -> Result<BufWriter<Box<dyn Write>>, GenError> {
+    let out: Box<dyn Write> = if path == Path::new(STDIO_PATH) {
+        Box::new(io::stdout())
+    } else {
+        Box::new(File::create(path).map_err(|e| GenError::io(path.display().to_string(), e))?)
+    };
+    Ok(BufWriter::new(out))
+}
+
+/// Write generated bricks to a file, choosing the format from the file extension
+/// `.obj` and `.glb` are exported as meshes, `.jsonl`, `.ndjson` and `.csv` as brick dumps,
+/// anything else is written as a Brickadia save (to standard output for `-`)
+///
+/// # Arguments
+/// * `out_file` - Path of the file to create
+/// * `bricks` - Bricks to write
+/// * `owner_id` - UUID string for the brick owner (saves and dumps only)
+/// * `owner_name` - Display name for the brick owner (saves and dumps only)
+/// * `progress` - Receives the start and end of writing, as the bricks are written in one go
+///
+/// # Returns
+/// * `Ok(())` if the file was written
+/// * `Err(GenError)` if the file couldn't be created or written
+pub fn write_bricks(
+    out_file: &str,
+    bricks: Vec<Brick>,
+    owner_id: String,
+    owner_name: String,
+    progress: &dyn Progress,
+) -> Result<(), GenError> {
+    let progress = ProgressScope::new(progress);
+    let count = bricks.len() as u64;
+    progress.report(Phase::Writing, 0, count, 0.0)?;
+
+    let create = |path: &Path| {
+        File::create(path)
+            .map(BufWriter::new)
+            .map_err(|e| GenError::io(path.display().to_string(), e))
+    };
+    let path = Path::new(out_file);
+
+    match OutputFormat::from_path(out_file) {
+        OutputFormat::Brs => SaveWriter::new(create_save_output(path)?, bricks_to_save(bricks, owner_id, owner_name))
+            .write()
+            .map_err(|e| GenError::io(out_file, io::Error::other(e.to_string()))),
+        OutputFormat::Obj => {
+            // The material library sits next to the OBJ with the same name
+            let mtl_path = path.with_extension("mtl");
+            let mtl_name = mtl_path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("terrain.mtl")
+                .to_string();
+            write_obj(&bricks, create(path)?, create(&mtl_path)?, &mtl_name)
+                .map_err(|e| GenError::io(out_file, e))
+        }
+        OutputFormat::Glb => {
+            write_glb(&bricks, create(path)?).map_err(|e| GenError::io(out_file, e))
+        }
+        OutputFormat::Dump(format) => {
+            write_dump(&bricks, format, create(path)?, &owner_id, &owner_name)
+                .map_err(|e| GenError::io(out_file, e))
+        }
+    }?;
+    progress.report(Phase::Writing, count, count, 1.0)
+}
+
+/// Length of the save header that comes before the first section (magic, save version, game version)
+const SAVE_PREFIX_LEN: usize = 9;
+
+/// Where a `BrickStream` writes to, and what it needs to finish the file
+enum StreamTarget {
+    /// Brickadia save, with the bricks section compressed into a temporary file until the end
+    Brs {
+        out: BufWriter<Box<dyn Write>>,
+        temp_path: PathBuf,
+        bricks: ZlibEncoder<BufWriter<File>>,
+        /// Uncompressed length of the bricks section so far
+        len: u64,
+        owner_id: String,
+        owner_name: String,
+    },
+    /// OBJ mesh, bricks are appended as they come in
+    Obj {
+        obj: BufWriter<File>,
+        vertex_offset: usize,
+    },
+    /// JSON Lines brick dump
+    JsonLines {
+        out: BufWriter<File>,
+        owner_id: String,
+        owner_name: String,
+    },
+    /// CSV brick dump, one writer so the header row is only written once
+    Csv {
+        out: Box<csv::Writer<BufWriter<File>>>,
+        owner_id: String,
+        owner_name: String,
+    },
+}
+
+/// Writes bricks to a file in batches, so they never have to be in memory all at once
+/// Saves keep their bricks in a temporary file next to the output until `finish`,
+/// because the save headers need the final brick count
+/// glTF meshes need every brick up front and can't be streamed
+pub struct BrickStream {
+    /// The file being written
+    target: StreamTarget,
+    /// Number of bricks written so far
+    bricks: usize,
+    /// Path of the output, for errors
+    path: String,
+}
+
+impl BrickStream {
+    /// Start writing bricks to a file, choosing the format from the file extension like `write_bricks`
+    ///
+    /// # Arguments
+    /// * `out_file` - Path of the file to create
+    /// * `owner_id` - UUID string for the brick owner (saves and dumps only)
+    /// * `owner_name` - Display name for the brick owner (saves and dumps only)
+    ///
+    /// # Returns
+    /// * `Ok(BrickStream)` if the output (and temporary) files were created
+    /// * `Err(GenError)` if a file couldn't be created or the format can't be streamed
+    pub fn create(out_file: &str, owner_id: String, owner_name: String) -> Result<Self, GenError> {
+        let create = |path: &Path| {
+            File::create(path)
+                .map(BufWriter::new)
+                .map_err(|e| GenError::io(path.display().to_string(), e))
+        };
+        let path = Path::new(out_file);
+
+        let target = match OutputFormat::from_path(out_file) {
+            OutputFormat::Brs => {
+                // Saves written to standard output keep their bricks in the system's temporary directory
+                let temp_path = if out_file == STDIO_PATH {
+                    env::temp_dir().join(format!("heightmap-{}.brs.tmp", process::id()))
+                } else {
+                    path.with_extension("brs.tmp")
+                };
+                StreamTarget::Brs {
+                    out: create_save_output(path)?,
+                    bricks: ZlibEncoder::new(create(&temp_path)?, Compression::default()),
+                    temp_path,
+                    len: 0,
+                    owner_id,
+                    owner_name,
+                }
+            }
+            OutputFormat::Obj => {
+                let mtl_path = path.with_extension("mtl");
+                let mtl_name = mtl_path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("terrain.mtl")
+                    .to_string();
+                let mut obj = create(path)?;
+                write_obj_header(&mut obj, create(&mtl_path)?, &mtl_name)
+                    .map_err(|e| GenError::io(out_file, e))?;
+                StreamTarget::Obj { obj, vertex_offset: 1 }
+            }
+            OutputFormat::Glb => {
+                return Err(GenError::Unsupported(
+                    "glTF meshes can't be written in blocks, use .obj instead".to_string(),
+                ))
+            }
+            OutputFormat::Dump(DumpFormat::JsonLines) => StreamTarget::JsonLines {
+                out: create(path)?,
+                owner_id,
+                owner_name,
+            },
+            OutputFormat::Dump(DumpFormat::Csv) => StreamTarget::Csv {
+                out: Box::new(csv::Writer::from_writer(create(path)?)),
+                owner_id,
+                owner_name,
+            },
+        };
+
+        Ok(BrickStream {
+            target,
+            bricks: 0,
+            path: out_file.to_string(),
+        })
+    }
+
+    /// Write a batch of bricks after the ones already written
+    pub fn write(&mut self, bricks: Vec<Brick>) -> Result<(), GenError> {
+        self.bricks += bricks.len();
+        let res = match &mut self.target {
+            StreamTarget::Brs { bricks: out, len, .. } => encode_bricks(bricks).and_then(|data| {
+                *len += data.len() as u64;
+                out.write_all(&data)
+            }),
+            StreamTarget::Obj { obj, vertex_offset } => write_obj_bricks(obj, &bricks, vertex_offset),
+            StreamTarget::JsonLines { out, owner_id, owner_name } => bricks.iter().try_for_each(|brick| {
+                serde_json::to_writer(&mut *out, &BrickRecord::from_brick(brick, owner_id, owner_name))?;
+                writeln!(out)
+            }),
+            StreamTarget::Csv { out, owner_id, owner_name } => bricks.iter().try_for_each(|brick| {
+                out.serialize(BrickRecord::from_brick(brick, owner_id, owner_name))
+                    .map_err(io::Error::from)
+            }),
+        };
+        res.map_err(|e| GenError::io(self.path.as_str(), e))
+    }
+
+    /// Finish the file after the last batch of bricks
+    /// Saves get their headers and the bricks from the temporary file, which is then removed
+    pub fn finish(self) -> Result<(), GenError> {
+        let BrickStream { target, bricks: brick_count, path } = self;
+        let res = match target {
+            StreamTarget::Brs {
+                mut out,
+                temp_path,
+                bricks,
+                len,
+                owner_id,
+                owner_name,
+            } => {
+                let res = bricks
+                    .finish()
+                    .and_then(|w| w.into_inner().map_err(|e| e.into_error()))
+                    .and_then(|_| {
+                        out.write_all(&save_headers(brick_count, owner_id, owner_name)?)?;
+                        copy_bricks_section(&mut out, &temp_path, len)?;
+                        // No bricks have components
+                        write_section(&mut out, &0_i32.to_le_bytes())?;
+                        out.flush()
+                    });
+                fs::remove_file(&temp_path).ok();
+                res
+            }
+            StreamTarget::Obj { mut obj, .. } => obj.flush(),
+            StreamTarget::JsonLines { mut out, .. } => out.flush(),
+            StreamTarget::Csv { mut out, .. } => out.flush(),
+        };
+        res.map_err(|e| GenError::io(path, e))
+    }
+}
+
+/// Write batches of bricks to a file as they come, like the blocks of `gen_opt_heightmap_iter`
+/// Each batch is written and dropped before the next one is taken, see `BrickStream`
+///
+/// # Arguments
+/// * `out_file` - Path of the file to create, see `BrickStream::create`
+/// * `batches` - Batches of bricks to write, in order
+/// * `owner_id` - UUID string for the brick owner (saves and dumps only)
+/// * `owner_name` - Display name for the brick owner (saves and dumps only)
+/// * `progress` - Receives the number of batches written, and the fraction of them if `batches`
+///   knows how many it has, and can stop writing
+///
+/// # Returns
+/// * `Ok(usize)` - Number of bricks written
+/// * `Err(GenError)` - The first error of a batch or of writing, the file is left unfinished
+pub fn stream_bricks<I>(
+    out_file: &str,
+    batches: I,
+    owner_id: String,
+    owner_name: String,
+    progress: &dyn Progress,
+) -> Result<usize, GenError>
+where
+    I: IntoIterator<Item = Result<Vec<Brick>, GenError>>,
+{
+    let progress = ProgressScope::new(progress);
+    let batches = batches.into_iter();
+    let total = batches.size_hint().1.unwrap_or(0) as u64;
+
+    let mut stream = BrickStream::create(out_file, owner_id, owner_name)?;
+    for (i, bricks) in batches.enumerate() {
+        stream.write(bricks?)?;
+        let written = i as u64 + 1;
+        let fraction = if total > 0 { written as f32 / total as f32 } else { 0.0 };
+        progress.report(Phase::Writing, written, total, fraction)?;
+    }
+    let count = stream.bricks;
+    stream.finish()?;
+    Ok(count)
+}
+
+/// Write a save section the way the brickadia writer does:
+/// (uncompressed length, compressed length, compressed bytes) if compression helps,
+/// otherwise (length, 0, bytes)
+fn write_section<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+
+    out.write_i32::<LittleEndian>(data.len() as i32)?;
+    if compressed.len() < data.len() {
+        out.write_i32::<LittleEndian>(compressed.len() as i32)?;
+        out.write_all(&compressed)
+    } else {
+        out.write_i32::<LittleEndian>(0)?;
+        out.write_all(data)
+    }
+}
+
+/// Split an uncompressed save into its sections, after the header prefix
+/// Sections are headers 1 and 2, then the bricks and components
+fn save_sections(save: &[u8]) -> Vec<&[u8]> {
+    let mut sections = vec![];
+    let mut pos = SAVE_PREFIX_LEN;
+    while pos + 8 <= save.len() {
+        let len = LittleEndian::read_i32(&save[pos..]) as usize;
+        sections.push(&save[pos + 8..pos + 8 + len]);
+        pos += 8 + len;
+
+        // A preview sits between header 2 and the bricks, this writer never adds one
+        if sections.len() == 2 {
+            pos += 1;
+        }
+    }
+    sections
+}
+
+/// Write an uncompressed save with the brickadia writer
+fn write_uncompressed(save: SaveData) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    SaveWriter::uncompressed(&mut out, save)
+        .write()
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(out)
+}
+
+/// Encode bricks into the bricks section of a save
+/// Every brick starts on a byte boundary, so sections of consecutive batches can be joined together
+fn encode_bricks(bricks: Vec<Brick>) -> io::Result<Vec<u8>> {
+    let save = write_uncompressed(bricks_to_save(bricks, String::new(), String::new()))?;
+    Ok(save_sections(&save)[2].to_vec())
+}
+
+/// Build the start of a save up to (not including) the bricks section
+///
+/// # Arguments
+/// * `brick_count` - Total number of bricks in the save
+/// * `owner_id` - UUID string for the brick owner
+/// * `owner_name` - Display name for the brick owner
+fn save_headers(brick_count: usize, owner_id: String, owner_name: String) -> io::Result<Vec<u8>> {
+    let mut save = bricks_to_save(vec![], owner_id, owner_name);
+    save.header2.brick_owners[0].bricks = brick_count as u32;
+    let uncompressed = write_uncompressed(save)?;
+
+    // Header 1 ends with the brick count
+    let sections = save_sections(&uncompressed);
+    let mut header1 = sections[0].to_vec();
+    let count_at = header1.len() - 4;
+    LittleEndian::write_i32(&mut header1[count_at..], brick_count as i32);
+
+    let mut out = uncompressed[..SAVE_PREFIX_LEN].to_vec();
+    write_section(&mut out, &header1)?;
+    write_section(&mut out, sections[1])?;
+    out.push(0);  // No preview
+    Ok(out)
+}
+
+/// Copy the compressed bricks section from the temporary file into the save
+/// Like `write_section`, the bricks are stored uncompressed if compression didn't help
+fn copy_bricks_section<W: Write>(out: &mut W, temp_path: &Path, len: u64) -> io::Result<()> {
+    let compressed_len = fs::metadata(temp_path)?.len();
+    let mut temp = BufReader::new(File::open(temp_path)?);
+
+    out.write_i32::<LittleEndian>(len as i32)?;
+    if compressed_len < len {
+        out.write_i32::<LittleEndian>(compressed_len as i32)?;
+        io::copy(&mut temp, out)?;
+    } else {
+        out.write_i32::<LittleEndian>(0)?;
+        io::copy(&mut ZlibDecoder::new(temp), out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{map::*, quad::*};
+    use brickadia::save::Color;
+
+    /// Hilly map with a few colors
+    fn terrain(size: u32) -> (HeightmapGrid, ColormapGrid) {
+        let heights = (0..size * size).map(|i| 10 + (i % size * 7 + i / size * 3) % 40).collect();
+        let colors = (0..size * size)
+            .map(|i| [(i % 3 * 80) as u8, (i / size % 4 * 60) as u8, 90, 255])
+            .collect();
+        (
+            HeightmapGrid::new(heights, (size, size)).unwrap(),
+            ColormapGrid::new(colors, (size, size), true).unwrap(),
+        )
+    }
+
+    /// Brick of the given size and material at a position, in Brickadia units
+    fn brick(position: (i32, i32, i32), size: (u32, u32, u32), material_index: u32) -> Brick {
+        Brick {
+            position,
+            size: Size::Procedural(size.0, size.1, size.2),
+            color: BrickColor::Unique(Color { r: 200, g: 100, b: 50, a: 255 }),
+            material_index,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn obj_meshes_have_a_box_per_brick() {
+        let bricks = [brick((5, 5, 2), (5, 5, 2), 0), brick((15, 5, 4), (5, 5, 4), 0), brick((5, 15, 6), (5, 5, 6), 1)];
+        let (mut obj, mut mtl) = (vec![], vec![]);
+        write_obj(&bricks, &mut obj, &mut mtl, "terrain.mtl").unwrap();
+        let (obj, mtl) = (String::from_utf8(obj).unwrap(), String::from_utf8(mtl).unwrap());
+
+        let lines = |prefix: &str| obj.lines().filter(|l| l.starts_with(prefix)).collect::<Vec<_>>();
+        assert_eq!(lines("mtllib "), ["mtllib terrain.mtl"]);
+        assert_eq!(lines("vn ").len(), 6);
+        assert_eq!(lines("v ").len(), 8 * bricks.len());
+        assert_eq!(lines("f ").len(), 6 * bricks.len());
+        assert_eq!(lines("usemtl "), ["usemtl BMC_Plastic", "usemtl BMC_Glow"]);
+        assert_eq!(mtl.matches("newmtl ").count(), MATERIALS.len());
+
+        // Faces only use the vertices written before them, OBJ indices start at 1
+        for face in lines("f ") {
+            for corner in face.split_whitespace().skip(1) {
+                let vertex: usize = corner.split("//").next().unwrap().parse().unwrap();
+                assert!((1..=8 * bricks.len()).contains(&vertex), "{}", face);
+            }
+        }
+
+        // The first brick spans 0..10 units on X and Y and 0..4 on Z, which is Y up in meters
+        let first: Vec<f32> = lines("v ")[7].split_whitespace().skip(1).take(3).map(|v| v.parse().unwrap()).collect();
+        assert_eq!(first, [0.1, 0.04, 0.1]);
+    }
+
+    #[test]
+    fn glb_lengths_match_their_chunks() {
+        let bricks = [brick((5, 5, 2), (5, 5, 2), 0), brick((15, 5, 4), (5, 5, 4), 0), brick((5, 15, 6), (5, 5, 6), 1)];
+        let mut glb = vec![];
+        write_glb(&bricks, &mut glb).unwrap();
+
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(LittleEndian::read_u32(&glb[4..8]), 2);
+        assert_eq!(LittleEndian::read_u32(&glb[8..12]) as usize, glb.len());
+
+        // A JSON chunk, then the binary chunk, both 4 byte aligned
+        let json_len = LittleEndian::read_u32(&glb[12..16]) as usize;
+        assert_eq!(&glb[16..20], b"JSON");
+        let json: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+        let bin_start = 20 + json_len;
+        let bin_len = LittleEndian::read_u32(&glb[bin_start..bin_start + 4]) as usize;
+        assert_eq!(&glb[bin_start + 4..bin_start + 8], b"BIN\0");
+        assert_eq!(bin_start + 8 + bin_len, glb.len());
+        assert!(json_len.is_multiple_of(4) && bin_len.is_multiple_of(4));
+        assert_eq!(json["buffers"][0]["byteLength"].as_u64(), Some(bin_len as u64));
+
+        // One primitive per material, with 24 vertices and 36 indices per box
+        let primitives = json["meshes"][0]["primitives"].as_array().unwrap();
+        assert_eq!(primitives.len(), 2);
+        let count = |accessor: &serde_json::Value| json["accessors"][accessor.as_u64().unwrap() as usize]["count"].as_u64();
+        for (primitive, boxes) in primitives.iter().zip([2, 1]) {
+            assert_eq!(count(&primitive["attributes"]["POSITION"]), Some(24 * boxes));
+            assert_eq!(count(&primitive["indices"]), Some(36 * boxes));
+        }
+
+        // Without bricks there is no binary chunk
+        let mut empty = vec![];
+        write_glb(&[], &mut empty).unwrap();
+        let json_len = LittleEndian::read_u32(&empty[12..16]) as usize;
+        assert_eq!(empty.len(), 20 + json_len);
+        assert_eq!(LittleEndian::read_u32(&empty[8..12]) as usize, empty.len());
+    }
+
+    #[test]
+    fn streamed_saves_read_back_like_whole_saves() {
+        let dir = env::temp_dir();
+        let whole_path = dir.join(format!("heightmap-test-{}-whole.brs", process::id()));
+        let streamed_path = dir.join(format!("heightmap-test-{}-streamed.brs", process::id()));
+        let (whole_file, streamed_file) = (whole_path.to_str().unwrap(), streamed_path.to_str().unwrap());
+        let (owner_id, owner_name) = ("0c9c6e35-3a8c-4e4f-a11f-2b7b1c4c6f6e".to_string(), "Streamer".to_string());
+
+        // The map is generated in blocks, once for each save
+        let (heightmap, colormap) = terrain(48);
+        let blocks = || gen_opt_heightmap_iter(&heightmap, &colormap, GenOptions::default(), 16, &NoProgress).unwrap();
+
+        let bricks: Vec<Brick> = blocks().flat_map(Result::unwrap).collect();
+        write_bricks(whole_file, bricks, owner_id.clone(), owner_name.clone(), &NoProgress).unwrap();
+
+        // An empty batch in the middle must not break the bricks section
+        let mut batches: Vec<_> = blocks().collect();
+        assert!(batches.len() > 2, "the map is generated in several blocks");
+        batches.insert(2, Ok(vec![]));
+        let written = stream_bricks(streamed_file, batches, owner_id, owner_name, &NoProgress).unwrap();
+
+        let whole = read_save(whole_file).unwrap();
+        let streamed = read_save(streamed_file).unwrap();
+        fs::remove_file(&whole_path).ok();
+        fs::remove_file(&streamed_path).ok();
+
+        assert_eq!(written, whole.bricks.len());
+        assert_eq!(streamed.header1.brick_count, whole.header1.brick_count);
+        assert_eq!(streamed.header1.brick_count as usize, written);
+        assert_eq!(format!("{:?}", streamed.header1.author), format!("{:?}", whole.header1.author));
+        assert_eq!(
+            format!("{:?}", streamed.header2.brick_owners),
+            format!("{:?}", whole.header2.brick_owners)
+        );
+        assert_eq!(streamed.header2.brick_assets, whole.header2.brick_assets);
+        assert_eq!(streamed.header2.materials, whole.header2.materials);
+        assert_eq!(format!("{:?}", streamed.bricks), format!("{:?}", whole.bricks));
+        assert!(streamed.components.is_empty());
+    }
+
+    #[test]
+    fn saves_are_byte_identical_on_any_thread_count() {
+        // Large enough for every thread to get several strips of the map
+        let (heightmap, colormap) = terrain(96);
+        for optimizer in [Optimizer::Quadtree, Optimizer::Greedy, Optimizer::Best] {
+            let save_bytes = |threads| {
+                let options = GenOptions::builder().optimizer(optimizer).threads(threads).build().unwrap();
+                let bricks = gen_opt_heightmap(&heightmap, &colormap, options, &NoProgress).unwrap();
+                let path = env::temp_dir().join(format!("heightmap-test-{}-{}-{}.brs", process::id(), optimizer, threads));
+                let file = path.to_str().unwrap();
+                write_bricks(file, bricks, "0c9c6e35-3a8c-4e4f-a11f-2b7b1c4c6f6e".to_string(), "Threads".to_string(), &NoProgress)
+                    .unwrap();
+                let bytes = fs::read(&path).unwrap();
+                fs::remove_file(&path).ok();
+                bytes
+            };
+            assert!(save_bytes(1) == save_bytes(4), "{} saves differ between 1 and 4 threads", optimizer);
+        }
+    }
+}
@@ -8,7 +8,6 @@ use std::{
 
 use super::{logger, util::load_image_from_path};
 use crate::gui::util::maps_from_files;
-use brickadia::write::SaveWriter;
 use eframe::App;
 use egui::{
     vec2, Button, CentralPanel, Color32, Context, Id, ProgressBar, ScrollArea, TextureHandle,
@@ -17,19 +16,27 @@ use egui::{
 use log::{error, info};
 use poll_promise::Promise;
 use {
-    heightmap::{quad::*, util::*},
-    std::{fs::File, path::Path},
+    heightmap::{error::GenError, export::*, progress::*, quad::*, util::*},
+    std::path::Path,
 };
 
-#[derive(PartialEq, Clone)]
-enum BrickMode {
-    Default,
-    Tile,
-    Stud,
-    Micro,
+type ProgressState = (String, f32);
+
+/// Sends generation progress to the progress bar, and stops generating when the token is cancelled
+struct GuiProgress<F: Fn(&ProgressUpdate)> {
+    update: F,
+    cancel: CancelToken,
 }
 
-type Progress = (&'static str, f32);
+impl<F: Fn(&ProgressUpdate)> Progress for GuiProgress<F> {
+    fn update(&self, update: &ProgressUpdate) {
+        (self.update)(update)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+}
 
 pub struct HeightmapApp {
     // options for the generator
@@ -48,12 +55,16 @@ pub struct HeightmapApp {
     opt_snap: bool,
     opt_glow: bool,
     gen_full_layers_above_height: u32,
-    mode: BrickMode,
-    progress: Progress,
-    progress_channel: (Sender<Progress>, Receiver<Progress>),
-    promise: Option<Promise<Result<(), String>>>,
+    opt_subsoil: bool,
+    subsoil_color: [u8; 3],
+    subsoil_depth: u32,
+    mode: BrickKind,
+    optimizer: Optimizer,
+    progress: ProgressState,
+    progress_channel: (Sender<ProgressState>, Receiver<ProgressState>),
+    promise: Option<Promise<Result<(), GenError>>>,
     texture_handles: HashMap<String, TextureHandle>,
-    gen_interrupt: Option<Sender<()>>,
+    gen_interrupt: Option<CancelToken>,
 }
 
 impl Default for HeightmapApp {
@@ -75,9 +86,13 @@ impl Default for HeightmapApp {
             opt_glow: false,
             opt_hdmap: false,
             gen_full_layers_above_height: 0,
-            mode: BrickMode::Default,
+            opt_subsoil: false,
+            subsoil_color: [107, 79, 51],
+            subsoil_depth: 40,
+            mode: BrickKind::Brick,
+            optimizer: Optimizer::Quadtree,
             promise: None,
-            progress: ("Pending", 0.),
+            progress: ("Pending".to_string(), 0.),
             progress_channel: mpsc::channel(),
             texture_handles: HashMap::new(),
             gen_interrupt: None,
@@ -86,54 +101,64 @@ impl Default for HeightmapApp {
 }
 
 impl HeightmapApp {
-    fn options(&self) -> GenOptions {
-        // output options
-        let mut options = GenOptions {
-            size: self.horizontal_size * 5,
-            scale: self.vertical_scale,
-            cull: self.opt_cull,
-            asset: 0,
-            tile: self.mode == BrickMode::Tile,
-            micro: self.mode == BrickMode::Micro,
-            stud: self.mode == BrickMode::Stud,
-            snap: self.opt_snap,
-            img: self.heightmaps.is_empty() && self.colormap.is_some(),
-            glow: self.opt_glow,
-            hdmap: self.opt_hdmap,
-            lrgb: self.opt_lrgb,
-            nocollide: self.opt_nocollide,
-            quadtree: self.opt_quad,
-            gen_full_layers_above_height: self.gen_full_layers_above_height,
-        };
-
-        if options.tile {
-            options.asset = 1
-        } else if options.micro {
-            options.size /= 5;
-            options.asset = 2;
-        }
-        if options.stud {
-            options.asset = 3
-        }
+    fn options(&self) -> Result<GenOptions, GenError> {
+        let img = self.heightmaps.is_empty() && self.colormap.is_some();
+
+        // flat images have no underground to fill
+        let subsoil = (self.opt_subsoil && !img).then(|| {
+            let [r, g, b] = self.subsoil_color;
+            Subsoil {
+                color: if self.opt_lrgb { [r, g, b, 255] } else { to_linear_rgb([r, g, b, 255]) },
+                depth: self.subsoil_depth,
+                kind: None,
+                collide: true,
+            }
+        });
 
-        options
+        // output options
+        GenOptions::builder()
+            .kind(self.mode)
+            .pixel_size(self.horizontal_size)
+            .scale(self.vertical_scale)
+            .cull(self.opt_cull)
+            .snap(self.opt_snap)
+            .img(img)
+            .glow(self.opt_glow)
+            .hdmap(self.opt_hdmap)
+            .lrgb(self.opt_lrgb)
+            .nocollide(self.opt_nocollide)
+            .quadtree(self.opt_quad)
+            .full_layers_above(if img { 0 } else { self.gen_full_layers_above_height })
+            .optimizer(self.optimizer)
+            .subsoil(subsoil)
+            .build()
     }
 
     fn run_converter(&mut self) {
         let out_file = self.out_file.clone();
         let owner_id = self.owner_id.clone();
         let owner_name = self.owner_name.clone();
-        let options = self.options();
+        let options = match self.options() {
+            Ok(options) => options,
+            Err(err) => {
+                error!("{err}");
+                self.promise = Some(Promise::from_ready(Err(err)));
+                return;
+            }
+        };
         let heightmap_files = self.heightmaps.clone();
         let colormap_file = self.colormap.clone();
 
         let progress_tx = self.progress_channel.0.clone();
-        let progress = move |status, p| progress_tx.send((status, p)).unwrap();
+        let progress = move |status: &str, p| progress_tx.send((status.to_string(), p)).unwrap();
 
         // handle interrupts
-        let (tx, rx) = mpsc::channel::<()>();
-        self.gen_interrupt = Some(tx);
-        let is_stopped = move || rx.try_recv().is_ok();
+        let cancel = CancelToken::new();
+        self.gen_interrupt = Some(cancel.clone());
+        let is_stopped = {
+            let cancel = cancel.clone();
+            move || cancel.is_cancelled()
+        };
 
         self.promise.get_or_insert_with(|| {
             info!("Preparing converter...");
@@ -145,7 +170,7 @@ impl HeightmapApp {
                 macro_rules! stop_if_stopped {
                     () => {
                         if is_stopped() {
-                            sender.send(Err("Stopped by user".to_string()));
+                            sender.send(Err(GenError::Cancelled));
                             return;
                         }
                     };
@@ -164,10 +189,18 @@ impl HeightmapApp {
                 stop_if_stopped!();
                 progress("Generating", 0.10);
 
-                let bricks = match gen_opt_heightmap(&*heightmap, &*colormap, options, |p| {
-                    progress("Generating", 0.1 + 0.85 * p);
-                    !is_stopped()
-                }) {
+                // generation takes the bar from 10% to 95%, with the phase and time left as its text
+                let gen_progress = GuiProgress {
+                    update: |update: &ProgressUpdate| {
+                        let status = match update.eta() {
+                            Some(eta) => format!("{} ({}s left)", update.phase, eta.as_secs()),
+                            None => update.phase.to_string(),
+                        };
+                        progress(&status, 0.1 + 0.85 * update.fraction);
+                    },
+                    cancel,
+                };
+                let bricks = match gen_opt_heightmap(&*heightmap, &*colormap, options, &gen_progress) {
                     Ok(b) => b,
                     Err(err) => {
                         error!("{err}");
@@ -176,11 +209,9 @@ impl HeightmapApp {
                 };
                 stop_if_stopped!();
 
-                info!("Writing Save to {}", out_file);
+                info!("Writing {} to {}", OutputFormat::from_path(&out_file), out_file);
                 progress("Writing", 0.95);
-                let data = bricks_to_save(bricks, owner_id, owner_name);
-                if let Err(e) = SaveWriter::new(File::create(&out_file).unwrap(), data).write() {
-                    let err = format!("failed to write file: {e}");
+                if let Err(err) = write_bricks(&out_file, bricks, owner_id, owner_name, &NoProgress) {
                     error!("{err}");
                     return sender.send(Err(err));
                 }
@@ -219,7 +250,8 @@ impl HeightmapApp {
                 ui.set_enabled(true);
 
                 ui.label("Save Path")
-                    .on_hover_text("The save will be created relative to the location of the exe.");
+                    .on_hover_text("The save will be created relative to the location of the exe.\n\
+                        Use a .obj or .glb extension to export a mesh instead.");
                 ui.add(egui::TextEdit::singleline(&mut self.out_file).hint_text("File Name"));
                 ui.end_row();
 
@@ -246,6 +278,17 @@ impl HeightmapApp {
                     .on_hover_text("Height threshold above which to generate full layers (0 = disabled)");
                 ui.add(egui::Slider::new(&mut self.gen_full_layers_above_height, 0..=100).text("units"));
                 ui.end_row();
+                ui.label("Subsoil")
+                    .on_hover_text("Fill all but the top of each column with one color so the underground merges");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.opt_subsoil, "");
+                    ui.add_enabled_ui(self.opt_subsoil, |ui| {
+                        ui.color_edit_button_srgb(&mut self.subsoil_color);
+                        ui.add(egui::Slider::new(&mut self.subsoil_depth, 2..=500).text("units"))
+                            .on_hover_text("Minimum height at the top of each column that keeps the surface color");
+                    });
+                });
+                ui.end_row();
 
                 ui.label("Options")
                     .on_hover_text("A list of options for modifying how the generator works");
@@ -261,7 +304,7 @@ impl HeightmapApp {
                     ui.checkbox(&mut self.opt_lrgb, "LRGB")
                         .on_hover_text("Use linear rgb input color instead of sRGB");
                     ui.checkbox(&mut self.opt_hdmap, "HD Map")
-                        .on_hover_text("Using a high detail rgb color encoded heightmap");
+                        .on_hover_text("Using a high detail rgb color encoded (or 16-bit grayscale) heightmap");
                     ui.checkbox(&mut self.opt_glow, "Glow")
                         .on_hover_text("Glow bricks at lowest intensity");
                     ui.checkbox(&mut self.opt_quad, "Quadtree").on_hover_text(
@@ -273,16 +316,28 @@ impl HeightmapApp {
                 ui.label("Brick Type")
                     .on_hover_text("Change which brick type is used for the save file");
                 ui.horizontal(|ui| {
-                    ui.radio_value(&mut self.mode, BrickMode::Default, "Default")
+                    ui.radio_value(&mut self.mode, BrickKind::Brick, "Default")
                         .on_hover_text("Use default bricks");
-                    ui.radio_value(&mut self.mode, BrickMode::Tile, "Tile")
+                    ui.radio_value(&mut self.mode, BrickKind::Tile, "Tile")
                         .on_hover_text("Use tile bricks");
-                    ui.radio_value(&mut self.mode, BrickMode::Stud, "Stud")
+                    ui.radio_value(&mut self.mode, BrickKind::Stud, "Stud")
                         .on_hover_text("Use studded bricks");
-                    ui.radio_value(&mut self.mode, BrickMode::Micro, "Micro")
+                    ui.radio_value(&mut self.mode, BrickKind::Micro, "Micro")
                         .on_hover_text("Use micro bricks");
                 });
                 ui.end_row();
+
+                ui.label("Optimizer")
+                    .on_hover_text("Change how tiles are merged into larger bricks");
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.optimizer, Optimizer::Quadtree, "Quadtree")
+                        .on_hover_text("Merge power of two squares, then lines");
+                    ui.radio_value(&mut self.optimizer, Optimizer::Greedy, "Greedy")
+                        .on_hover_text("Merge the largest rectangles of equal height and color, then lines");
+                    ui.radio_value(&mut self.optimizer, Optimizer::Best, "Best")
+                        .on_hover_text("Run both and keep whichever has fewer bricks (takes longer)");
+                });
+                ui.end_row();
             });
 
         ui.add_space(8.0);
@@ -374,7 +429,7 @@ impl HeightmapApp {
         while let Ok(p) = self.progress_channel.1.try_recv() {
             self.progress = p;
         }
-        let (progress_text, progress) = self.progress;
+        let (progress_text, progress) = self.progress.clone();
 
         let mut clear_promise = progress > 1.0;
         let mut rendered = false;
@@ -396,7 +451,10 @@ impl HeightmapApp {
                         if ui.button("ok").clicked() {
                             clear_promise = true;
                         }
-                        ui.colored_label(Color32::RED, format!("Error: {e}"));
+                        match e {
+                            GenError::Cancelled => ui.label("Stopped"),
+                            e => ui.colored_label(Color32::RED, format!("Error: {e}")),
+                        };
                     });
                 }
                 None => {
@@ -411,11 +469,9 @@ impl HeightmapApp {
                             .text(progress_text)
                             .animate(true),
                         );
-                        if let (true, Some(tx)) = (stop_btn.clicked(), &self.gen_interrupt) {
+                        if let (true, Some(cancel)) = (stop_btn.clicked(), &self.gen_interrupt) {
                             info!("Sending interrupt...");
-                            if let Err(e) = tx.send(()) {
-                                error!("error sending interrupt {e}");
-                            }
+                            cancel.cancel();
                         }
                     });
                 }
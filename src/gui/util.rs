@@ -2,55 +2,50 @@ use std::path::Path;
 
 use egui::ColorImage;
 use heightmap::{
+    error::GenError,
     map::{Colormap, ColormapPNG, Heightmap, HeightmapFlat, HeightmapPNG},
     util::{file_ext, GenOptions},
 };
 use image::{GenericImageView, ImageError};
 
-type MapPair = (Box<dyn Heightmap>, Box<dyn Colormap>);
+type MapPair = (Box<dyn Heightmap + Sync>, Box<dyn Colormap + Sync>);
 
 pub fn maps_from_files(
     options: &GenOptions,
     heightmap_files: Vec<String>,
     colormap_file: Option<String>,
-) -> Result<MapPair, String> {
+) -> Result<MapPair, GenError> {
     let heightmap_files: Vec<String> = heightmap_files.into_iter().collect();
     let first_heightmap = heightmap_files
         .first()
         .map(|s| s.to_owned())
-        .unwrap_or_else(|| "".to_string());
+        .unwrap_or_default();
     let colormap_file = colormap_file.unwrap_or(first_heightmap);
 
     // colormap file parsing
     let colormap = match file_ext(&colormap_file.to_lowercase()) {
-        Some("png") => ColormapPNG::new(&colormap_file, options.lrgb)
-            .map_err(|e| format!("Error reading colormap: {:?}", e))?,
+        Some("png") => ColormapPNG::new(&colormap_file, options.lrgb())?,
         Some(ext) => {
-            return Err(format!("Unsupported colormap format '{}'", ext));
+            return Err(GenError::Unsupported(format!("Unsupported colormap format '{}'", ext)));
         }
         None => {
-            return Err(format!("Missing colormap format for '{}'", colormap_file));
+            return Err(GenError::Unsupported(format!("Missing colormap format for '{}'", colormap_file)));
         }
     };
 
     // heightmap file parsing
-    let heightmap: Box<dyn Heightmap> =
+    let heightmap: Box<dyn Heightmap + Sync> =
         if heightmap_files.iter().all(|f| file_ext(f) == Some("png")) {
-            if options.img {
-                Box::new(HeightmapFlat::new(colormap.size(), options.scale).unwrap())
+            if options.img() {
+                Box::new(HeightmapFlat::new(colormap.size(), options.scale()).unwrap())
             } else {
-                match HeightmapPNG::new(
+                Box::new(HeightmapPNG::new(
                     heightmap_files.iter().map(|s| s.as_ref()).collect(),
-                    options.hdmap,
-                ) {
-                    Ok(map) => Box::new(map),
-                    Err(error) => {
-                        return Err(format!("Error reading heightmap: {:?}", error));
-                    }
-                }
+                    options.hdmap(),
+                )?)
             }
         } else {
-            return Err("Unsupported heightmap format".to_string());
+            return Err(GenError::Unsupported("Unsupported heightmap format".to_string()));
         };
 
     Ok((heightmap, Box::new(colormap)))
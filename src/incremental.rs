@@ -0,0 +1,487 @@
+// Import our map, quadtree and utility modules
+use crate::error::*; // Error type
+use crate::map::*;   // Heightmap and Colormap traits
+use crate::progress::*; // Progress reporting and cancellation
+use crate::quad::*;  // Block generation and layer heights
+use crate::stats::*; // Generation statistics
+use crate::util::*;  // Generation options
+// Import Brickadia save file structures
+use brickadia::save::{Brick, BrickColor, Collision, Color, Size};
+// Import gzip compression for cache files
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+// Import logging for progress updates
+use log::info;
+// Import serde for cache files and JSON reports
+use serde::{Deserialize, Serialize};
+// Import standard library items for file I/O and timing
+use std::{
+    cmp::min,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    time::Instant,
+};
+
+/// Block size used when regenerating incrementally without `--block_size`
+pub const DEFAULT_INCREMENTAL_BLOCK: u32 = 256;
+
+/// A generated brick in a compact layout for cache files
+/// Fields are the asset, size, position, color, material, material intensity and collision flags
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct CachedBrick(u32, [u32; 3], [i32; 3], [u8; 4], u32, u32, [bool; 4]);
+
+impl CachedBrick {
+    fn from_brick(brick: &Brick) -> Self {
+        let size = match brick.size {
+            Size::Procedural(x, y, z) => [x, y, z],
+            Size::Empty => [0, 0, 0],
+        };
+        let color = match &brick.color {
+            BrickColor::Unique(c) => [c.r, c.g, c.b, c.a],
+            BrickColor::Index(_) => [255, 255, 255, 255],
+        };
+        let c = &brick.collision;
+        CachedBrick(
+            brick.asset_name_index,
+            size,
+            [brick.position.0, brick.position.1, brick.position.2],
+            color,
+            brick.material_index,
+            brick.material_intensity,
+            [c.player, c.weapon, c.interaction, c.tool],
+        )
+    }
+
+    fn to_brick(self) -> Brick {
+        let CachedBrick(asset, [x, y, z], position, [r, g, b, a], material, intensity, collision) = self;
+        Brick {
+            asset_name_index: asset,
+            size: Size::Procedural(x, y, z),
+            position: (position[0], position[1], position[2]),
+            collision: Collision {
+                player: collision[0],
+                weapon: collision[1],
+                interaction: collision[2],
+                tool: collision[3],
+            },
+            color: BrickColor::Unique(Color { r, g, b, a }),
+            owner_index: 1,  // bricks_to_save writes a single owner
+            material_index: material,
+            material_intensity: intensity,
+            ..Default::default()
+        }
+    }
+}
+
+/// One block of a cached map
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedBlock {
+    /// Hash of the block's heights and colors
+    hash: u64,
+    /// Bricks of the block grouped by layer, None if they weren't kept
+    layers: Option<Vec<Vec<CachedBrick>>>,
+}
+
+/// What a previous generation of a map looked like, block by block
+/// Made from the previous input images (hashes only) or read from a cache file (hashes and bricks)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockCache {
+    /// Hash of everything besides the pixels that changes the bricks: options, sizes and layer heights
+    key: u64,
+    /// Blocks row by row
+    blocks: Vec<CachedBlock>,
+}
+
+impl BlockCache {
+    /// Hash the blocks of a map without generating any bricks
+    ///
+    /// # Arguments
+    /// * `heightmap` - Source of elevation data
+    /// * `colormap` - Source of color data
+    /// * `options` - Configuration options the map would be generated with
+    /// * `block_size` - Width and height of each block in pixels
+    ///
+    /// # Returns
+    /// * `Ok(BlockCache)` with no bricks, to compare newer inputs against
+    /// * `Err(GenError)` if the images don't match
+    pub fn from_maps(
+        heightmap: &(dyn Heightmap + Sync),
+        colormap: &(dyn Colormap + Sync),
+        options: &GenOptions,
+        block_size: u32,
+    ) -> Result<Self, GenError> {
+        let layers = LayerHeights::new(
+            heightmap,
+            colormap,
+            options.gen_full_layers_above_height,
+            options.threads,
+        )?;
+        let blocks = block_origins(heightmap.size(), block_size)
+            .into_iter()
+            .map(|origin| CachedBlock {
+                hash: block_hash(heightmap, colormap, origin, block_size),
+                layers: None,
+            })
+            .collect();
+        Ok(BlockCache {
+            key: cache_key(heightmap.size(), options, block_size, &layers),
+            blocks,
+        })
+    }
+
+    /// Read a cache file written by `write`
+    pub fn read(path: &str) -> Result<Self, GenError> {
+        let file = File::open(path).map_err(|e| GenError::io(path, e))?;
+        serde_json::from_reader(GzDecoder::new(BufReader::new(file))).map_err(|e| GenError::decode(path, e))
+    }
+
+    /// Write the cache as gzipped JSON
+    pub fn write(&self, path: &str) -> Result<(), GenError> {
+        let file = File::create(path).map_err(|e| GenError::io(path, e))?;
+        let mut out = GzEncoder::new(BufWriter::new(file), Compression::fast());
+        serde_json::to_writer(&mut out, self)
+            .map_err(io::Error::from)
+            .and_then(|_| out.finish())
+            .and_then(|mut f| f.flush())
+            .map_err(|e| GenError::io(path, e))
+    }
+}
+
+/// A part of the map whose old bricks have to be removed before a delta save is loaded
+/// Covers every height, in Brickadia units
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ClearRegion {
+    /// Lowest X and Y corner
+    pub min: (i32, i32),
+    /// Highest X and Y corner
+    pub max: (i32, i32),
+}
+
+/// How to regenerate a map from a previous generation
+#[derive(Debug, Clone)]
+pub struct Incremental {
+    /// Width and height of each block in pixels, must match the previous generation
+    pub block_size: u32,
+    /// The previous generation, None to generate every block
+    pub previous: Option<BlockCache>,
+    /// Only write the bricks of blocks that changed (a delta save)
+    pub delta: bool,
+    /// Keep the bricks of every block in the returned cache, so the next run can reuse them
+    pub keep_bricks: bool,
+}
+
+/// Number of blocks that were generated, reused or left out, included in the generation report
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BlockReuse {
+    /// Number of blocks in the map
+    pub blocks: usize,
+    /// Blocks that changed and were generated
+    pub changed: usize,
+    /// Unchanged blocks that were generated again because their bricks weren't cached
+    pub regenerated: usize,
+    /// Unchanged blocks whose cached bricks were written
+    pub reused: usize,
+    /// Unchanged blocks left out of a delta save
+    pub skipped: usize,
+}
+
+/// Regenerate a map block by block, only generating the blocks that changed since a previous generation
+/// Unchanged blocks reuse their cached bricks, or are left out of a delta save
+///
+/// # Arguments
+/// * `heightmap` - Source of elevation data
+/// * `colormap` - Source of color data
+/// * `options` - Configuration options for brick generation
+/// * `incremental` - Block size, previous generation and output mode
+/// * `write_f` - Called with the bricks of each written block, in order, sorted like `sort_bricks`
+/// * `progress` - Receives the phase and progress of each generated block, and can cancel generation
+///
+/// # Returns
+/// * `Ok((GenStats, BlockCache, Vec<ClearRegion>))` - Statistics of the written bricks, the cache for
+///   the next run, and the regions of changed blocks
+/// * `Err(GenError)` - Why generation or writing failed, or `Cancelled`
+pub fn gen_opt_heightmap_incremental<W>(
+    heightmap: &(dyn Heightmap + Sync),
+    colormap: &(dyn Colormap + Sync),
+    options: GenOptions,
+    incremental: Incremental,
+    mut write_f: W,
+    progress: &dyn Progress,
+) -> Result<(GenStats, BlockCache, Vec<ClearRegion>), GenError>
+where
+    W: FnMut(Vec<Brick>) -> Result<(), GenError>,
+{
+    options.validate()?;
+    let progress = ProgressScope::new(progress);
+
+    let mut stats = GenStats::new(heightmap.size());
+    let block_size = incremental.block_size.max(1);
+
+    let phase = Instant::now();
+    let (width, height) = heightmap.size();
+    let share = finding_layers_share(heightmap.size(), &options);
+    let layers = LayerHeights::find(
+        heightmap,
+        colormap,
+        options.gen_full_layers_above_height,
+        options.threads,
+        &mut WorkProgress::new(progress.part(0.0, share), width as u64 * height as u64),
+    )?;
+    stats.record_phase("find layer heights", phase);
+    let progress = progress.part(share, 1.0);
+
+    // Anything besides the pixels changing means every block changed
+    let key = cache_key(heightmap.size(), &options, block_size, &layers);
+    let mut previous = incremental.previous.filter(|p| p.key == key);
+    if previous.is_none() {
+        info!("No matching previous generation, every block is generated");
+    }
+
+    let origins = block_origins(heightmap.size(), block_size);
+    let mut reuse = BlockReuse {
+        blocks: origins.len(),
+        ..Default::default()
+    };
+    let mut cache = BlockCache { key, blocks: vec![] };
+    let mut cleared = vec![];
+
+    for (i, &origin) in origins.iter().enumerate() {
+        let phase = Instant::now();
+        let hash = block_hash(heightmap, colormap, origin, block_size);
+        stats.record_phase("compare blocks", phase);
+
+        let old = previous
+            .as_mut()
+            .and_then(|p| p.blocks.get_mut(i))
+            .filter(|b| b.hash == hash)
+            .map(|b| b.layers.take());
+
+        // Skipped blocks keep their cached bricks for the next run
+        let mut kept = None;
+        let block = match old {
+            Some(cached) if incremental.delta => {
+                reuse.skipped += 1;
+                kept = if incremental.keep_bricks { cached } else { None };
+                None
+            }
+            Some(Some(cached)) => {
+                reuse.reused += 1;
+                let block_layers: Vec<Vec<Brick>> =
+                    cached.iter().map(|l| l.iter().map(|b| b.to_brick()).collect()).collect();
+                // Cached blocks aren't generated, so only their bricks are recorded
+                let mut block_stats = GenStats::default();
+                block_stats.record_bricks(&block_layers);
+                Some((block_layers, block_stats))
+            }
+            old => {
+                if old.is_some() {
+                    reuse.regenerated += 1;
+                } else {
+                    reuse.changed += 1;
+                    cleared.push(block_region(heightmap.size(), &options, origin, block_size));
+                }
+                let block_progress = progress.block(i as u32, origins.len() as u32);
+                Some(gen_block(heightmap, colormap, &options, &layers, origin, block_size, block_progress)?)
+            }
+        };
+
+        if let Some((block_layers, mut block_stats)) = block {
+            if incremental.keep_bricks {
+                kept = Some(
+                    block_layers
+                        .iter()
+                        .map(|l| l.iter().map(CachedBrick::from_brick).collect())
+                        .collect(),
+                );
+            }
+
+            let phase = Instant::now();
+            let mut block_bricks: Vec<Brick> = block_layers.into_iter().flatten().collect();
+            sort_bricks(&mut block_bricks);
+            write_f(block_bricks)?;
+            block_stats.record_phase("write output", phase);
+            stats.merge(block_stats);
+        }
+        cache.blocks.push(CachedBlock { hash, layers: kept });
+
+        // Reused and skipped blocks finish their share at once
+        let block_progress = progress.block(i as u32, origins.len() as u32);
+        block_progress.report(Phase::Writing, i as u64 + 1, origins.len() as u64, 1.0)?;
+    }
+
+    info!(
+        "{} of {} blocks changed, {} reused, {} generated again, {} left out",
+        reuse.changed, reuse.blocks, reuse.reused, reuse.regenerated, reuse.skipped
+    );
+    stats.incremental = Some(reuse);
+    Ok((stats, cache, cleared))
+}
+
+/// Top-left pixel of every block of a map, row by row
+fn block_origins(size: (u32, u32), block_size: u32) -> Vec<(u32, u32)> {
+    let (columns, rows) = (size.0.div_ceil(block_size), size.1.div_ceil(block_size));
+    (0..columns * rows)
+        .map(|i| ((i % columns) * block_size, (i / columns) * block_size))
+        .collect()
+}
+
+/// Area covered by the bricks of a block, bricks never cross block edges
+fn block_region(size: (u32, u32), options: &GenOptions, origin: (u32, u32), block_size: u32) -> ClearRegion {
+    let pixel = 2 * options.size as i32;
+    ClearRegion {
+        min: (origin.0 as i32 * pixel, origin.1 as i32 * pixel),
+        max: (
+            min(origin.0 + block_size, size.0) as i32 * pixel,
+            min(origin.1 + block_size, size.1) as i32 * pixel,
+        ),
+    }
+}
+
+/// Add bytes to an FNV-1a hash, which stays the same across runs and builds
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes {
+        hash = (hash ^ b as u64).wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Starting value of FNV-1a hashes
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// Hash the heights and colors of one block, clipped at the map edges
+fn block_hash(heightmap: &dyn Heightmap, colormap: &dyn Colormap, origin: (u32, u32), block_size: u32) -> u64 {
+    let (width, height) = heightmap.size();
+    let mut hash = FNV_OFFSET;
+    for x in origin.0..min(origin.0 + block_size, width) {
+        for y in origin.1..min(origin.1 + block_size, height) {
+            hash = fnv1a(hash, &heightmap.at(x, y).to_le_bytes());
+            hash = fnv1a(hash, &colormap.at(x, y));
+        }
+    }
+    hash
+}
+
+/// Hash the generator version, options, map and block sizes and layer heights
+/// The thread count is left out, it doesn't change the bricks
+fn cache_key(size: (u32, u32), options: &GenOptions, block_size: u32, layers: &LayerHeights) -> u64 {
+    let options = GenOptions { threads: 0, ..options.clone() };
+    let key = format!(
+        "{} {:?} {:?} {} {:?}",
+        env!("CARGO_PKG_VERSION"),
+        options,
+        size,
+        block_size,
+        layers
+    );
+    fnv1a(FNV_OFFSET, key.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sloped map in two colors, with one pixel raised if `bump` is set
+    fn terrain(bump: bool) -> (HeightmapGrid, ColormapGrid) {
+        let size = 32;
+        let mut heights: Vec<u32> = (0..size * size).map(|i| 20 + (i % size + i / size * 2) % 30).collect();
+        if bump {
+            heights[(3 * size + 10) as usize] += 50;  // Pixel (10, 3), in the second block of the first row
+        }
+        let colors = (0..size * size)
+            .map(|i| if i % 5 < 3 { [90, 140, 60, 255] } else { [120, 110, 80, 255] })
+            .collect();
+        (
+            HeightmapGrid::new(heights, (size, size)).unwrap(),
+            ColormapGrid::new(colors, (size, size), true).unwrap(),
+        )
+    }
+
+    /// Regenerate a map in blocks of 8 pixels
+    ///
+    /// # Returns
+    /// * The written bricks, how the blocks were reused, the new cache and the regions to clear
+    fn regenerate(
+        (heightmap, colormap): &(HeightmapGrid, ColormapGrid),
+        previous: Option<BlockCache>,
+        delta: bool,
+    ) -> (Vec<Brick>, BlockReuse, BlockCache, Vec<ClearRegion>) {
+        let incremental = Incremental {
+            block_size: 8,
+            previous,
+            delta,
+            keep_bricks: true,
+        };
+        let mut written = vec![];
+        let write = |bricks: Vec<Brick>| {
+            written.extend(bricks);
+            Ok(())
+        };
+        let (stats, cache, cleared) =
+            gen_opt_heightmap_incremental(heightmap, colormap, GenOptions::default(), incremental, write, &NoProgress)
+                .unwrap();
+        (written, stats.incremental.unwrap(), cache, cleared)
+    }
+
+    /// Bricks in a form that can be compared
+    fn describe(bricks: &[Brick]) -> Vec<String> {
+        bricks.iter().map(|b| format!("{:?}", b)).collect()
+    }
+
+    #[test]
+    fn unchanged_blocks_reuse_their_cached_bricks() {
+        let maps = terrain(false);
+        let (first, reuse, cache, cleared) = regenerate(&maps, None, false);
+        assert_eq!((reuse.blocks, reuse.changed), (16, 16));
+        assert_eq!(cleared.len(), 16);
+
+        // The cache goes through a file like it does between runs
+        let path = std::env::temp_dir().join(format!("heightmap-test-{}.cache", std::process::id()));
+        cache.write(path.to_str().unwrap()).unwrap();
+        let cache = BlockCache::read(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let (second, reuse, _, cleared) = regenerate(&maps, Some(cache), false);
+        assert_eq!((reuse.reused, reuse.changed, reuse.regenerated), (16, 0, 0));
+        assert!(cleared.is_empty());
+        assert_eq!(describe(&second), describe(&first));
+    }
+
+    #[test]
+    fn changed_blocks_are_generated_again() {
+        let (_, _, cache, _) = regenerate(&terrain(false), None, false);
+        let bumped = terrain(true);
+        let (fresh, _, _, _) = regenerate(&bumped, None, false);
+
+        let (written, reuse, _, cleared) = regenerate(&bumped, Some(cache.clone()), false);
+        assert_eq!((reuse.changed, reuse.reused), (1, 15));
+        assert_eq!(describe(&written), describe(&fresh));
+
+        // The changed block is cleared, over its whole area
+        let pixel = 2 * GenOptions::default().size as i32;
+        assert_eq!(cleared.len(), 1);
+        assert_eq!((cleared[0].min, cleared[0].max), ((8 * pixel, 0), (16 * pixel, 8 * pixel)));
+
+        // A delta save only has the changed block
+        let (delta, reuse, _, _) = regenerate(&bumped, Some(cache), true);
+        assert_eq!((reuse.changed, reuse.skipped), (1, 15));
+        assert!(!delta.is_empty() && delta.len() < written.len());
+        assert!(delta.iter().all(|b| b.position.0 >= 8 * pixel && b.position.0 < 16 * pixel && b.position.1 < 8 * pixel));
+    }
+
+    #[test]
+    fn previous_images_only_tell_what_changed() {
+        let options = GenOptions::default();
+        let (heightmap, colormap) = terrain(false);
+        let previous = BlockCache::from_maps(&heightmap, &colormap, &options, 8).unwrap();
+
+        // Unchanged blocks have no bricks to reuse, so they're generated again
+        let (_, reuse, _, cleared) = regenerate(&terrain(true), Some(previous), false);
+        assert_eq!((reuse.changed, reuse.regenerated, reuse.reused), (1, 15, 0));
+        assert_eq!(cleared.len(), 1);
+
+        // A cache made with other options doesn't match at all
+        let stud = GenOptions::builder().kind(BrickKind::Stud).build().unwrap();
+        let other = BlockCache::from_maps(&heightmap, &colormap, &stud, 8).unwrap();
+        let (_, reuse, _, _) = regenerate(&terrain(false), Some(other), false);
+        assert_eq!(reuse.changed, 16);
+    }
+}
@@ -1,3 +1,13 @@
+pub mod batch;
+pub mod budget;
+pub mod config;
+pub mod dump;
+pub mod error;
+pub mod export;
+pub mod incremental;
 pub mod map;
+pub mod progress;
 pub mod quad;
+pub mod raster;
+pub mod stats;
 pub mod util;
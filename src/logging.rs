@@ -0,0 +1,250 @@
+// Import the progress bar drawn while generating
+use indicatif::{ProgressBar, ProgressStyle};
+// Import the logging facade our messages go through
+use log::{debug, info, trace, LevelFilter, Log, Metadata, Record};
+// Import serde for JSON events
+use serde::Serialize;
+use serde_json::{json, Value};
+// Import standard library items
+use std::{cell::RefCell, fmt, str::FromStr, sync::Mutex};
+
+// Import progress reporting and our error type
+use crate::{error::GenError, progress::*, stats::GenStats};
+
+/// Steps the progress bar is divided into
+const BAR_STEPS: u64 = 1000;
+
+/// Phase of a progress update, and its block for maps generated in blocks
+type Step = (Phase, Option<(u32, u32)>);
+
+/// How log messages and progress are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Plain messages, with a progress bar on terminals
+    Text,
+    /// One JSON event per line, for tools and dashboards
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = GenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(GenError::Invalid(format!(
+                "Unknown log format '{}', expected text or json",
+                s
+            ))),
+        }
+    }
+}
+
+/// Prints log messages, reports and progress of the command line to standard error,
+/// so they never mix with a save written to standard output
+pub struct CliLog {
+    /// How messages are printed
+    format: LogFormat,
+    /// Most detailed messages printed
+    level: LevelFilter,
+    /// Progress bar being drawn, messages are printed above it
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl CliLog {
+    /// Start printing log messages at `level` and above
+    /// Only one logger can be set, so this is called once
+    pub fn init(level: LevelFilter, format: LogFormat) -> &'static CliLog {
+        let log: &'static CliLog = Box::leak(Box::new(CliLog {
+            format,
+            level,
+            bar: Mutex::new(None),
+        }));
+        log::set_logger(log).expect("logger is only set once");
+        log::set_max_level(level);
+        log
+    }
+
+    /// Print a report, as its text table or as a JSON event named `event`
+    pub fn report<T: Serialize + fmt::Display>(&self, event: &str, report: &T) {
+        match self.format {
+            LogFormat::Text => info!("{}", report),
+            LogFormat::Json if self.level >= LevelFilter::Info => {
+                self.event(json!({ "event": event, event: report }))
+            }
+            LogFormat::Json => {}
+        }
+    }
+
+    /// Print how many bricks each optimizer pass merged away as a JSON event
+    /// Text logs already print these as the passes run
+    pub fn merged(&self, stats: &GenStats) {
+        if self.format == LogFormat::Json && self.level >= LevelFilter::Info {
+            self.event(json!({
+                "event": "merged",
+                "quad": stats.quad_merges,
+                "greedy": stats.greedy_merges,
+                "lines": stats.line_merges,
+                "columns": stats.column_merges,
+            }));
+        }
+    }
+
+    /// Start reporting the progress of a generation
+    /// Text logs draw a bar on terminals, JSON logs get an event when each phase starts
+    pub fn progress(&'static self) -> CliProgress {
+        let bar = if self.format == LogFormat::Text && self.level >= LevelFilter::Info {
+            let style = ProgressStyle::with_template("{bar:40} {percent:>3}% {msg}")
+                .expect("progress template is valid");
+            Some(ProgressBar::new(BAR_STEPS).with_style(style))
+        } else {
+            None
+        };
+        *self.bar.lock().unwrap() = bar.clone();
+        CliProgress {
+            log: self,
+            bar,
+            last: RefCell::new(None),
+        }
+    }
+
+    /// Print a JSON event on its own line
+    fn event(&self, event: Value) {
+        self.print(&event.to_string());
+    }
+
+    /// Print a line above the progress bar, if one is drawn
+    fn print(&self, line: &str) {
+        match &*self.bar.lock().unwrap() {
+            Some(bar) => bar.suspend(|| eprintln!("{}", line)),
+            None => eprintln!("{}", line),
+        }
+    }
+}
+
+impl Log for CliLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match self.format {
+            // Messages are printed without timestamps or levels for cleaner output
+            LogFormat::Text => self.print(&record.args().to_string()),
+            LogFormat::Json => self.event(json!({
+                "event": "log",
+                "level": record.level().as_str().to_lowercase(),
+                "message": record.args().to_string(),
+            })),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Progress of one generation on the command line, the bar is cleared when this is dropped
+pub struct CliProgress {
+    /// Where events and messages go
+    log: &'static CliLog,
+    /// Progress bar, if one is drawn
+    bar: Option<ProgressBar>,
+    /// Phase and block of the last update, to tell when a new one starts
+    last: RefCell<Option<Step>>,
+}
+
+impl Progress for CliProgress {
+    fn update(&self, update: &ProgressUpdate) {
+        let step = Some((update.phase, update.block));
+        if self.last.replace(step) != step {
+            match self.log.format {
+                LogFormat::Text => debug!("Started {}", describe(update)),
+                LogFormat::Json if self.log.level >= LevelFilter::Info => self.log.event(update_event("phase", update)),
+                LogFormat::Json => {}
+            }
+        }
+        // Every update is printed too at the most verbose level
+        match self.log.format {
+            LogFormat::Text => trace!(
+                "{}: {} of {}, {:.1}% done",
+                describe(update),
+                update.done,
+                update.total,
+                update.fraction * 100.0
+            ),
+            LogFormat::Json if self.log.level >= LevelFilter::Trace => {
+                self.log.event(update_event("progress", update))
+            }
+            LogFormat::Json => {}
+        }
+
+        if let Some(bar) = &self.bar {
+            bar.set_position((update.fraction * BAR_STEPS as f32) as u64);
+            bar.set_message(match update.eta() {
+                Some(eta) => format!("{}, {}s left", describe(update), eta.as_secs()),
+                None => describe(update),
+            });
+        }
+    }
+}
+
+impl Drop for CliProgress {
+    fn drop(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+        *self.log.bar.lock().unwrap() = None;
+    }
+}
+
+/// JSON event named `event` with the fields of a progress update
+fn update_event(event: &str, update: &ProgressUpdate) -> Value {
+    let mut value = serde_json::to_value(update).unwrap_or_default();
+    value["event"] = json!(event);
+    value
+}
+
+/// Phase of an update, and its block for maps generated in blocks
+fn describe(update: &ProgressUpdate) -> String {
+    match update.block {
+        Some((block, blocks)) => format!("block {}/{}, {}", block, blocks, update.phase),
+        None => update.phase.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn log_formats_parse_in_any_case() {
+        assert_eq!("JSON".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!(matches!("yaml".parse::<LogFormat>(), Err(GenError::Invalid(_))));
+    }
+
+    #[test]
+    fn progress_events_name_their_phase_and_block() {
+        let update = ProgressUpdate {
+            phase: Phase::QuadLevel(4),
+            done: 3,
+            total: 8,
+            block: Some((2, 6)),
+            fraction: 0.5,
+            elapsed: Duration::from_secs(10),
+        };
+        assert_eq!(
+            update_event("progress", &update),
+            json!({"event": "progress", "phase": "quad_level", "n": 4, "done": 3, "total": 8, "block": [2, 6], "fraction": 0.5})
+        );
+        assert_eq!(describe(&update), "block 2/6, merging 4x tiles");
+
+        let update = ProgressUpdate { phase: Phase::Writing, block: None, ..update };
+        assert_eq!(update_event("phase", &update)["phase"], "writing");
+        assert_eq!(describe(&update), "writing output");
+    }
+}
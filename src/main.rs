@@ -1,179 +1,930 @@
 // Module declarations - tell Rust about the other source files in this project
+pub mod batch;  // Contains batch runs of many generation jobs
+pub mod budget; // Contains brick budget mode that searches for settings under a brick count
+pub mod config; // Contains job files with the inputs, outputs and options of a generation
+pub mod dump;   // Contains JSON Lines/CSV brick dumps for external tooling
+pub mod error;  // Contains the error type shared by the library
+pub mod export; // Contains mesh exporters and output format selection
+pub mod incremental; // Contains regeneration of only the changed blocks of a map
 pub mod map;   // Contains heightmap and colormap data structures and image processing
+pub mod progress; // Contains progress reporting and cancellation
 pub mod quad;  // Contains quadtree optimization for reducing brick count
+pub mod raster; // Contains rasterizing saves back into images
+pub mod stats; // Contains generation statistics and the report table
 pub mod util;  // Contains utility functions for color conversion and save file generation
+mod logging;   // Contains log formats and the progress bar of the command line
 
 // Import all public items from our modules using wildcard imports
-use crate::{map::*, quad::*, util::*};
-// External crate imports for file I/O, command-line parsing, and logging
-use brickadia::write::SaveWriter; // Writes Brickadia save files (.brs format)
-use clap::clap_app;              // Command-line argument parsing macro
-use env_logger::Builder;         // Configures logging output
+use crate::{batch::*, budget::*, config::*, dump::*, error::*, export::*, incremental::*, map::*, progress::*, quad::*, raster::*, stats::*, util::*};
+// External crate imports for command-line parsing and logging
+use clap::{clap_app, Arg, ArgMatches}; // Command-line argument parsing macro and shared arguments
+use crate::logging::*;           // Log formats and the progress bar
 use log::{error, info, LevelFilter}; // Logging macros and level filtering
-use std::{boxed::Box, fs::File, io::Write}; // Standard library items for file operations
+use std::{boxed::Box, cmp::max, env, ffi::OsString, fs::File, io::{self, Cursor, Read}, path::Path, str::FromStr, time::Instant}; // Standard library items for arguments, boxing, files, stdin, parsing and timing
+
+/// Owner id written when none is given
+const DEFAULT_OWNER_ID: &str = "a1b16aca-9627-4a16-a160-67fa9adbb7b6";
 
 fn main() {
-    // Configure logging to output info-level messages and above
-    // The custom format removes timestamps and log levels for cleaner output
-    Builder::new()
-        .format(|buf, record| writeln!(buf, "{}", record.args()))
-        .filter(None, LevelFilter::Info)
-        .init();
+    // Invocations without a subcommand generate, as they did before there were subcommands
+    // Global flags can come before the subcommand, so they're skipped when looking for it
+    let mut args = env::args_os().collect::<Vec<OsString>>();
+    let mut rest = args.iter().skip(1);
+    let first = loop {
+        match rest.next().and_then(|a| a.to_str()) {
+            Some("--dry-run") | Some("-q") | Some("--quiet") | Some("--verbose") => {}
+            Some("--log-format") => {
+                rest.next();
+            }
+            Some(a) if a.starts_with("--log-format=") => {}
+            first => break first,
+        }
+    };
+    let named = ["generate", "inspect", "preview", "convert", "batch", "help", "-h", "--help", "-V", "--version"];
+    if first.is_some_and(|a| !named.contains(&a)) {
+        args.insert(1, "generate".into());
+    }
 
     // Parse command-line arguments using the clap_app! macro
-    // This defines all the available flags and options for the program
+    // Inputs and generation options are shared by the subcommands that read maps, see `map_args`
     let matches = clap_app!(heightmap =>
         (version: env!("CARGO_PKG_VERSION"))  // Gets version from Cargo.toml
         (author: "github.com/Meshiest")
         (about: "Converts heightmap png files to Brickadia save files")
-        // Required arguments
-        (@arg INPUT: +required +multiple "Input heightmap PNG images")
-        // Optional file arguments
-        (@arg output: -o --output +takes_value "Output BRS file")
-        (@arg colormap: -c --colormap +takes_value "Input colormap PNG image")
+        (@setting SubcommandRequiredElseHelp)
+        (@arg dry_run: --("dry-run") +global "Do everything but write files, and report what would have been written")
+        (@arg quiet: -q --quiet +global "Only print warnings and errors")
+        (@arg verbose: --verbose +global +multiple "Print more, once for when each phase of generation starts, twice for every progress update (-v is the vertical scale)")
+        (@arg log_format: --("log-format") +global +takes_value "Print messages as text or as json events, one per line (default text)")
+        // Batch runs of many maps
+        (@subcommand batch =>
+            (about: "Generates every job of a manifest, or every heightmap matching a pattern")
+            (@arg MANIFEST: +required "Manifest .toml/.json of jobs, or a heightmap pattern with one * like maps/*_height.png")
+            (@arg colormap: -c --colormap +takes_value "Colormap pattern, the * is replaced by the part the heightmap pattern matched")
+            (@arg output: -o --output +takes_value "Output pattern, the * is replaced by the part the heightmap pattern matched (default a .brs next to each heightmap)")
+            (@arg config: --config +takes_value "Job file with the options, owner and report of every pattern job")
+            (@arg jobs: -j --jobs +takes_value "Number of jobs to run at once, sharing the CPU cores (default 1, 0 for one per CPU core)")
+            (@arg summary: --summary +takes_value "Write the batch summary as JSON to this file")
+        )
+        // Conversion between output formats
+        (@subcommand convert =>
+            (about: "Converts a save or brick dump to another format without generating anything")
+            (@arg INPUT: +required "Input BRS save (or .jsonl/.csv brick dump)")
+            (@arg output: -o --output +takes_value "Output BRS file (or .obj/.glb mesh, .jsonl/.csv brick dump)")
+            (@arg owner_id: --owner_id +takes_value "Set the owner id (default the input's owner)")
+            (@arg owner: --owner +takes_value "Set the owner name (default the input's owner)")
+        )
+    )
+    .subcommand(
+        clap_app!(generate =>
+            (about: "Generates a save from heightmap and colormap images (the default)")
+            // Optional file arguments
+            (@arg output: -o --output +takes_value "Output BRS file, - for standard output (or .obj/.glb mesh, .jsonl/.csv brick dump)")
+            (@arg dump_config: --("dump-config") "Print the job with every option filled in and exit, as TOML (or JSON for a .json --config)")
+            // Ownership options
+            (@arg owner_id: --owner_id  +takes_value "Set the owner id (default a1b16aca-9627-4a16-a160-67fa9adbb7b6)")
+            (@arg owner: --owner +takes_value "Set the owner name (default Generator)")
+            // Streaming and incremental options
+            (@arg block_size: --block_size +takes_value "Generate the map in blocks of this many pixels, writing bricks as each block finishes (the images are still read whole)")
+            (@arg previous: --previous +takes_value +multiple number_of_values(1) "Heightmap of the previous generation, only blocks that changed since are generated (its save must have been made in blocks of the same size)")
+            (@arg previous_colormap: --previous_colormap +takes_value "Colormap of the previous generation (default the first previous heightmap)")
+            (@arg cache: --cache +takes_value "Cache of each block's bricks, reused for unchanged blocks and updated after generating")
+            (@arg delta: --delta +takes_value "Only write the bricks of changed blocks, and write the regions to clear to this JSON file")
+            // Reporting options
+            (@arg report: --report +takes_value "Write a JSON generation report to this file")
+            (@arg max_bricks: --("max-bricks") +takes_value "Coarsen scale, colors and heights until the save has at most this many bricks")
+        )
+        .args(&map_args()),
+    )
+    .subcommand(
+        clap_app!(inspect =>
+            (about: "Prints the sizes, heights, colors and predicted brick count of a map without writing a save")
+            (@arg report: --report +takes_value "Write the map info and generation report as JSON to this file (a --config job's report isn't used)")
+        )
+        .args(&map_args()),
+    )
+    .subcommand(
+        clap_app!(preview =>
+            (about: "Renders a shaded top-down PNG of the save a map would generate")
+            (@arg output: -o --output +takes_value "Output preview PNG (default preview.png, a --config job's output isn't used)")
+        )
+        .args(&map_args()),
+    )
+    .get_matches_from(args);
+
+    // Global flags can be given before or after the subcommand
+    let (name, sub_matches) = matches.subcommand();
+    let global = |name: &str| matches.is_present(name) || sub_matches.is_some_and(|m| m.is_present(name));
+    let dry_run = global("dry_run");
+
+    // Messages go to standard error, as text with a progress bar, or as JSON events
+    // -v has always been the vertical scale, so only the long --verbose raises the level
+    // Global flags are copied into the subcommand's matches, so the counts aren't added up
+    let verbose = max(matches.occurrences_of("verbose"), sub_matches.map_or(0, |m| m.occurrences_of("verbose")));
+    let level = match (global("quiet"), verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    let log_format = sub_matches
+        .and_then(|m| m.value_of("log_format"))
+        .or_else(|| matches.value_of("log_format"))
+        .map(str::parse::<LogFormat>)
+        .transpose();
+    let log = match log_format {
+        Ok(format) => CliLog::init(level, format.unwrap_or(LogFormat::Text)),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    match (name, sub_matches) {
+        ("generate", Some(matches)) => generate(matches, dry_run, log),
+        ("inspect", Some(matches)) => inspect(matches, dry_run, log),
+        ("preview", Some(matches)) => preview(matches, dry_run, log),
+        ("convert", Some(matches)) => convert(matches, dry_run),
+        ("batch", Some(matches)) => batch(matches, dry_run),
+        _ => unreachable!("A subcommand is required"),
+    }
+}
+
+/// Arguments of every subcommand that reads maps: the inputs and the generation options
+fn map_args() -> Vec<Arg<'static, 'static>> {
+    // Name, short flag, long flag, whether it takes a value, and help
+    let args: [(&str, Option<&str>, &str, bool, &str); 24] = [
+        // Job files
+        ("config", None, "config", true, "Read inputs, outputs and options from a .toml or .json job file, other flags override it"),
+        // Input files
+        ("colormap", Some("c"), "colormap", true, "Input colormap PNG image, - for standard input"),
         // Scaling and sizing options
-        (@arg vertical: -v --vertical +takes_value "Vertical scale multiplier (default 1)")
-        (@arg size: -s --size +takes_value "Brick stud size (default 1)")
-        (@arg gen_full_layers_above: --gen_full_layers +takes_value "Generate full layers above this height (default 0)")
+        ("vertical", Some("v"), "vertical", true, "Vertical scale multiplier (default 1)"),
+        ("size", Some("s"), "size", true, "Brick stud size (default 1)"),
+        ("gen_full_layers_above", None, "gen_full_layers", true, "Generate full layers above this height (default 0)"),
+        ("preset", None, "preset", true, "Start from a preset: terrain, img2brick or micro_detail, other flags add to it"),
         // Optimization and rendering flags
-        (@arg cull: --cull "Automatically remove bottom level bricks and fully transparent bricks")
-        (@arg tile: --tile "Render bricks as tiles")
-        (@arg micro: --micro "Render bricks as micro bricks")
-        (@arg stud: --stud "Render bricks as stud cubes")
-        (@arg snap: --snap "Snap bricks to the brick grid")
+        ("cull", None, "cull", false, "Automatically remove bottom level bricks and fully transparent bricks"),
+        ("tile", None, "tile", false, "Render bricks as tiles"),
+        ("micro", None, "micro", false, "Render bricks as micro bricks"),
+        ("stud", None, "stud", false, "Render bricks as stud cubes"),
+        ("snap", None, "snap", false, "Snap bricks to the brick grid"),
         // Color and display options
-        (@arg lrgb: --lrgb "Use linear rgb input color instead of sRGB")
-        (@arg img: -i --img "Make the heightmap flat and render an image")
-        (@arg glow: --glow "Make the heightmap glow at 0 intensity")
-        (@arg hdmap: --hdmap "Using a high detail rgb color encoded heightmap")
-        // Physics and ownership options
-        (@arg nocollide: --nocollide "Disable brick collision")
-        (@arg owner_id: --owner_id  +takes_value "Set the owner id (default a1b16aca-9627-4a16-a160-67fa9adbb7b6)")
-        (@arg owner: --owner +takes_value "Set the owner name (default Generator)")
-    )
-    .get_matches();
+        ("lrgb", None, "lrgb", false, "Use linear rgb input color instead of sRGB"),
+        ("img", Some("i"), "img", false, "Make the heightmap flat and render an image"),
+        ("glow", None, "glow", false, "Make the heightmap glow at 0 intensity"),
+        ("hdmap", None, "hdmap", false, "Using a high detail rgb color encoded (or 16-bit grayscale) heightmap"),
+        // Physics options
+        ("nocollide", None, "nocollide", false, "Disable brick collision"),
+        // Underground options
+        ("subsoil", None, "subsoil", true, "Fill all but the top of each column with this color (RRGGBB or r,g,b)"),
+        ("subsoil_depth", None, "subsoil_depth", true, "Minimum height at the top of each column that keeps the surface color (default 40)"),
+        ("subsoil_asset", None, "subsoil_asset", true, "Brick type of subsoil bricks: brick, tile, micro or stud (default same as the surface)"),
+        ("subsoil_nocollide", None, "subsoil_nocollide", false, "Disable subsoil brick collision"),
+        // Performance options
+        ("threads", None, "threads", true, "Number of threads to generate with (default 0, one per CPU core)"),
+        ("optimizer", None, "optimizer", true, "Brick merging optimizer: quadtree, greedy or best (default quadtree)"),
+        ("seed", None, "seed", true, "Seed reserved for randomised generation steps, none use it yet (default 0)"),
+        // Inputs, last so they are listed after the options
+        ("INPUT", None, "", false, "Input heightmap PNG images, - for standard input (or a .jsonl/.csv brick dump)"),
+    ];
+    args.iter()
+        .map(|&(name, short, long, takes_value, help)| {
+            let arg = Arg::with_name(name).help(help);
+            let arg = match short {
+                Some(short) => arg.short(short),
+                None => arg,
+            };
+            match long {
+                // Positional inputs have no flag
+                "" => arg.multiple(true),
+                long => arg.long(long).takes_value(takes_value),
+            }
+        })
+        .collect()
+}
+
+/// Build the job of a subcommand that reads maps from its config file and arguments
+/// Values given on the command line take priority over the config file
+///
+/// # Returns
+/// * `Ok(JobConfig)` with the options built and validated, and paths left unset if none were given
+/// * `Err(GenError)` if the config can't be read or the options don't go together
+fn job_from_args(matches: &ArgMatches) -> Result<JobConfig, GenError> {
+    // Jobs can be read from a config file, values given on the command line take priority
+    let config = match matches.value_of("config") {
+        Some(config_file) => JobConfig::read(config_file)?,
+        None => JobConfig::default(),
+    };
 
     // Extract file paths from command-line arguments
-    let heightmap_files = matches.values_of("INPUT").unwrap().collect::<Vec<&str>>();
+    let inputs = match matches.values_of("INPUT") {
+        Some(files) => files.map(String::from).collect(),
+        None => config.inputs,
+    };
+    let get = |name: &str, config: Option<String>| matches.value_of(name).map(String::from).or(config);
+    let colormap = get("colormap", config.colormap);
+    let output = get("output", config.output);
+    let report = get("report", config.report);
+    let owner = get("owner", config.owner);
+    let owner_id = get("owner_id", config.owner_id);
+
+    // Optimizer used to merge tiles into bricks
+    let optimizer = matches.value_of("optimizer").map(str::parse::<Optimizer>).transpose()?;
+
+    // Underground fill, its color is converted like the colormap's once the options are built
+    let subsoil = match matches.value_of("subsoil").map(parse_color).transpose()? {
+        Some(color) => Some(Subsoil {
+            color,
+            depth: parse_arg(matches, "subsoil_depth", "Subsoil depth")?.unwrap_or(40),
+            kind: matches.value_of("subsoil_asset").map(str::parse::<BrickKind>).transpose()?,
+            collide: !matches.is_present("subsoil_nocollide"),
+        }),
+        None => None,
+    };
+    // The other subsoil flags only describe the subsoil given with it
+    let subsoil_flags = ["subsoil_depth", "subsoil_asset", "subsoil_nocollide"];
+    if subsoil.is_none() && subsoil_flags.iter().any(|flag| matches.is_present(flag)) {
+        return Err(GenError::Invalid(
+            "--subsoil_depth, --subsoil_asset and --subsoil_nocollide need --subsoil".to_string(),
+        ));
+    }
+
+    // Presets set the brick type and flags, which the options below override or add to
+    let mut builder = GenOptionsBuilder::from(config.options);
+    if let Some(preset) = matches.value_of("preset") {
+        builder = builder.preset(preset.parse::<Preset>()?);
+    }
+
+    // Brick type, at most one can be picked
+    let kinds = [("tile", BrickKind::Tile), ("micro", BrickKind::Micro), ("stud", BrickKind::Stud)]
+        .iter()
+        .filter(|(flag, _)| matches.is_present(flag))
+        .map(|&(_, kind)| kind)
+        .collect::<Vec<BrickKind>>();
+    match kinds[..] {
+        [] => {}
+        [kind] => builder = builder.kind(kind),
+        _ => return Err(GenError::Invalid("Only one of --tile, --micro and --stud can be used".to_string())),
+    }
+
+    // Size and scale keep the preset's values unless given
+    if let Some(size) = parse_arg(matches, "size", "Size")? {
+        // Brick size in studs (or micro bricks), converted to Brickadia units by the builder
+        builder = builder.pixel_size(size);
+    }
+    if let Some(scale) = parse_arg(matches, "vertical", "Scale")? {
+        // Vertical scaling factor for height values
+        builder = builder.scale(scale);
+    }
+    if let Some(height) = parse_arg(matches, "gen_full_layers_above", "Generate full layers above height")? {
+        builder = builder.full_layers_above(height);
+    }
+
+    // Flags only turn settings on, so a preset's settings are kept unless a flag adds to them
+    type Setter = fn(GenOptionsBuilder, bool) -> GenOptionsBuilder;
+    let flags: [(&str, Setter); 7] = [
+        ("cull", GenOptionsBuilder::cull),           // Remove transparent and bottom-level bricks
+        ("snap", GenOptionsBuilder::snap),           // Snap to brick grid
+        ("img", GenOptionsBuilder::img),             // Flat heightmap for image rendering
+        ("glow", GenOptionsBuilder::glow),           // Make bricks glow
+        ("hdmap", GenOptionsBuilder::hdmap),         // High detail RGBA-encoded heightmap
+        ("lrgb", GenOptionsBuilder::lrgb),           // Use linear RGB instead of sRGB
+        ("nocollide", GenOptionsBuilder::nocollide), // Disable collision
+    ];
+    for (name, set) in flags.iter() {
+        if matches.is_present(name) {
+            builder = set(builder, true);
+        }
+    }
+
+    // Number of worker threads, 0 uses every CPU core
+    if let Some(threads) = parse_arg(matches, "threads", "Threads")? {
+        builder = builder.threads(threads);
+    }
+    if let Some(optimizer) = optimizer {
+        builder = builder.optimizer(optimizer);
+    }
+    // Seed reserved for randomised steps, so saves will stay reproducible once there are some
+    if let Some(seed) = parse_arg(matches, "seed", "Seed")? {
+        builder = builder.seed(seed);
+    }
+
+    // Build generation options from the config and command-line arguments
+    let mut options = builder.build()?;
+    // The subsoil flags replace the config's subsoil as a whole, with the color given in sRGB
+    // unless the config, a preset or --lrgb made colors linear
+    if let Some(subsoil) = subsoil {
+        let color = if options.lrgb { subsoil.color } else { to_linear_rgb(subsoil.color) };
+        options = GenOptionsBuilder::from(options)
+            .subsoil(Some(Subsoil { color, ..subsoil }))
+            .build()?;
+    }
+
+    Ok(JobConfig {
+        inputs,
+        colormap,
+        output,
+        report,
+        owner,
+        owner_id,
+        options,
+    })
+}
+
+/// Parse the value of an argument as a number, if it was given
+///
+/// # Arguments
+/// * `name` - Name of the argument
+/// * `what` - What the value is, for the error message
+///
+/// # Returns
+/// * `Ok(Option<T>)` - The parsed value, None if the argument wasn't given
+/// * `Err(GenError::Invalid)` - If the value isn't a number
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str, what: &str) -> Result<Option<T>, GenError> {
+    matches
+        .value_of(name)
+        .map(|value| {
+            value
+                .parse::<T>()
+                .map_err(|_| GenError::Invalid(format!("{} must be a whole number, got '{}'", what, value)))
+        })
+        .transpose()
+}
+
+/// Run the generate subcommand: read the maps, generate bricks and write them
+fn generate(matches: &ArgMatches, dry_run: bool, log: &'static CliLog) {
+    let job = match job_from_args(matches) {
+        Ok(job) => job,
+        Err(err) => return error!("{}", err),
+    };
+
+    // Print the effective job instead of running it
+    if matches.is_present("dump_config") {
+        let job = JobConfig {
+            output: Some(job.output.clone().unwrap_or_else(|| "./out.brs".to_string())),
+            owner: Some(job.owner.clone().unwrap_or_else(|| "Generator".to_string())),
+            owner_id: Some(job.owner_id.clone().unwrap_or_else(|| DEFAULT_OWNER_ID.to_string())),
+            ..job
+        };
+        let format = matches
+            .value_of("config")
+            .and_then(ConfigFormat::from_path)
+            .unwrap_or(ConfigFormat::Toml);
+        return match job.to_text(format) {
+            Ok(text) => print!("{}", text),
+            Err(err) => error!("{}", err),
+        };
+    }
+
+    let JobConfig { inputs, colormap, output, report: report_file, owner, owner_id: given_owner_id, options } = job;
+    let heightmap_files = inputs.iter().map(String::as_str).collect::<Vec<&str>>();
+    if heightmap_files.is_empty() {
+        return error!("No input files, give them on the command line or as inputs in a --config file");
+    }
     // If no colormap is specified, use the first heightmap file as the colormap
-    let colormap_file = matches
-        .value_of("colormap")
-        .unwrap_or(heightmap_files[0])
-        .to_string();
+    let colormap_file = colormap.unwrap_or_else(|| heightmap_files[0].to_string());
     // Default output file if none specified
-    let out_file = matches
-        .value_of("output")
-        .unwrap_or("./out.brs")
-        .to_string();
+    let out_file = output.unwrap_or_else(|| "./out.brs".to_string());
+
+    // Brick dumps skip generation entirely and are written straight to the output
+    if heightmap_files.iter().any(|f| DumpFormat::from_path(f).is_some()) {
+        if heightmap_files.len() != 1 {
+            return error!("Only one brick dump can be loaded at a time");
+        }
+        return convert_file(heightmap_files[0], &out_file, given_owner_id, owner, dry_run);
+    }
 
     // Extract owner information for the Brickadia save file
     // Each brick in Brickadia has an owner ID and name
-    let owner_id = matches
-        .value_of("owner_id")
-        .unwrap_or("a1b16aca-9627-4a16-a160-67fa9adbb7b6")  // Default UUID
-        .to_string();
-    let owner_name = matches.value_of("owner").unwrap_or("Generator").to_string();
-
-    // Build generation options from command-line arguments
-    let mut options = GenOptions {
-        // Brick size in Brickadia studs (multiplied by 5 for internal units)
-        size: matches
-            .value_of("size")
-            .unwrap_or("1")
-            .parse::<u32>()
-            .expect("Size must be integer")
-            * 5,
-        // Vertical scaling factor for height values
-        scale: matches
-            .value_of("vertical")
-            .unwrap_or("1")
-            .parse::<u32>()
-            .expect("Scale must be integer"),
-        // Whether to remove transparent and bottom-level bricks
-        cull: matches.is_present("cull"),
-        // Brick asset type (set below based on tile/micro/stud flags)
-        asset: 0,
-        // Brick type flags
-        tile: matches.is_present("tile"),   // Use tile bricks
-        micro: matches.is_present("micro"), // Use micro bricks
-        stud: matches.is_present("stud"),   // Use studded bricks
-        // Position and alignment options
-        snap: matches.is_present("snap"),   // Snap to brick grid
-        // Rendering mode flags
-        img: matches.is_present("img"),     // Flat heightmap for image rendering
-        glow: matches.is_present("glow"),   // Make bricks glow
-        hdmap: matches.is_present("hdmap"), // High detail RGBA-encoded heightmap
-        lrgb: matches.is_present("lrgb"),   // Use linear RGB instead of sRGB
-        nocollide: matches.is_present("nocollide"), // Disable collision
-        quadtree: true, // Always enable quadtree optimization
-        gen_full_layers_above_height: matches
-            .value_of("gen_full_layers_above")
-            .unwrap_or("0")
-            .parse::<u32>()
-            .expect("Generate full layers above height must be integer"),
-    };
-
-    // Set the appropriate brick asset index based on brick type
-    // Asset indices correspond to different brick types in Brickadia
-    if options.tile {
-        options.asset = 1  // Tile brick asset
-    } else if options.micro {
-        options.size /= 5; // Micro bricks are 1/5 the size of regular bricks
-        options.asset = 2; // Micro brick asset
-    }
-    if options.stud {
-        options.asset = 3  // Studded brick asset (overrides tile/micro if set)
+    let owner_id = given_owner_id.unwrap_or_else(|| DEFAULT_OWNER_ID.to_string());
+    let owner_name = owner.unwrap_or_else(|| "Generator".to_string());
+
+    // Parse the brick budget, if one was given
+    let max_bricks = match parse_arg::<usize>(matches, "max_bricks", "Max bricks") {
+        Ok(max_bricks) => max_bricks,
+        Err(err) => return error!("{}", err),
+    };
+
+    // Parse the block size for streaming generation, if one was given
+    let block_size = match parse_arg::<u32>(matches, "block_size", "Block size") {
+        Ok(block_size) => block_size,
+        Err(err) => return error!("{}", err),
+    };
+
+    // Incremental regeneration compares the blocks against a cache file or the previous images
+    let cache_file = matches.value_of("cache");
+    let delta_file = matches.value_of("delta");
+    let previous_files = matches.values_of("previous").map(|files| files.collect::<Vec<&str>>());
+    let incremental = cache_file.is_some() || delta_file.is_some() || previous_files.is_some();
+
+    // Budget mode needs every brick of an attempt to count them, so it can't generate in blocks
+    if max_bricks.is_some() {
+        if incremental {
+            return error!("--cache, --delta and --previous can't be used with --max-bricks");
+        }
+        if block_size.is_some() {
+            return error!("--block_size can't be used with --max-bricks");
+        }
     }
+    let block_size = block_size.or_else(|| incremental.then_some(DEFAULT_INCREMENTAL_BLOCK));
 
     info!("Reading image files");
+    let read_start = Instant::now();
 
-    // Parse the colormap file to determine brick colors
-    // The colormap provides RGB color values for each pixel position
-    let colormap = match file_ext(&colormap_file.to_lowercase()) {
-        Some("png") => match ColormapPNG::new(&colormap_file, options.lrgb) {
-            Ok(map) => map,
+    let progress = log.progress();
+    let maps = read_maps(heightmap_files, &colormap_file, &options, &progress);
+    drop(progress);
+    let (colormap, heightmap) = match maps {
+        Ok(maps) => maps,
+        Err(err) => return error!("Error reading images: {}", err),
+    };
+
+    let read_time = read_start.elapsed().as_secs_f64();
+
+    let mut stats = if let Some(block_size) = block_size {
+        // A cache with bricks is better than the previous images, which only say what changed
+        let previous = match (cache_file, previous_files) {
+            (Some(cache_file), _) if Path::new(cache_file).exists() => {
+                info!("Reading cache {}", cache_file);
+                match BlockCache::read(cache_file) {
+                    Ok(cache) => Some(cache),
+                    Err(err) => {
+                        info!("{}, every block is generated", err);
+                        None
+                    }
+                }
+            }
+            (_, Some(previous_files)) => {
+                info!("Reading previous image files");
+                let previous_colormap = matches.value_of("previous_colormap").unwrap_or(previous_files[0]);
+                let previous = read_maps(previous_files, previous_colormap, &options, &NoProgress)
+                    .and_then(|(colormap, heightmap)| {
+                        BlockCache::from_maps(&*heightmap, &colormap, &options, block_size)
+                    });
+                match previous {
+                    Ok(previous) => Some(previous),
+                    Err(err) => return error!("Error reading previous images: {}", err),
+                }
+            }
+            _ => None,
+        };
+
+        // Bricks are written as soon as each block is generated, or dropped in a dry run
+        let mut stream = if dry_run {
+            None
+        } else {
+            info!("Writing {} to {}", OutputFormat::from_path(&out_file), out_file);
+            match BrickStream::create(&out_file, owner_id, owner_name) {
+                Ok(stream) => Some(stream),
+                Err(err) => {
+                    return error!("Error writing output: {}", err);
+                }
+            }
+        };
+        let mut write = |bricks| match &mut stream {
+            Some(stream) => stream.write(bricks),
+            None => Ok(()),
+        };
+        let progress = log.progress();
+        let result = if incremental {
+            let incremental = Incremental {
+                block_size,
+                previous,
+                delta: delta_file.is_some(),
+                keep_bricks: cache_file.is_some(),
+            };
+            gen_opt_heightmap_incremental(
+                &*heightmap,
+                &colormap,
+                options,
+                incremental,
+                &mut write,
+                &progress,
+            )
+            .map(|(stats, cache, cleared)| (stats, Some((cache, cleared))))
+        } else {
+            gen_opt_heightmap_blocks(
+                &*heightmap,
+                &colormap,
+                options,
+                block_size,
+                &mut write,
+                &progress,
+            )
+            .map(|stats| (stats, None))
+        };
+        drop(progress);
+        let (mut stats, incremental) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                return error!("Error during generation: {}", err);
+            }
+        };
+
+        if let Some(stream) = stream {
+            let write_start = Instant::now();
+            if let Err(err) = stream.finish() {
+                return error!("Error writing output: {}", err);
+            }
+            stats.record_phase("write output", write_start);
+        }
+
+        if let (Some((cache, cleared)), false) = (incremental, dry_run) {
+            if let Some(delta_file) = delta_file {
+                info!("Writing {} regions to clear to {}", cleared.len(), delta_file);
+                if let Err(err) = File::create(delta_file)
+                    .map_err(|e| e.to_string())
+                    .and_then(|f| serde_json::to_writer_pretty(f, &cleared).map_err(|e| e.to_string()))
+                {
+                    return error!("Error writing regions: {}", err);
+                }
+            }
+            if let Some(cache_file) = cache_file {
+                info!("Writing cache {}", cache_file);
+                if let Err(err) = cache.write(cache_file) {
+                    return error!("Error writing cache: {}", err);
+                }
+            }
+        }
+        stats
+    } else {
+        // Generate optimized bricks from the heightmap and colormap
+        // Progress is shown until the bricks are generated, the operation is never cancelled
+        let progress = log.progress();
+        let result = match max_bricks {
+            Some(max_bricks) => gen_within_budget(&*heightmap, &colormap, &options, max_bricks, &progress),
+            None => gen_opt_heightmap_with_stats(&*heightmap, &colormap, options, &progress),
+        };
+        drop(progress);
+        let (bricks, mut stats) = match result {
+            Ok(result) => result,
             Err(err) => {
-                return error!("Error reading colormap: {:?}", err);
+                return error!("Error during generation: {}", err);
             }
+        };
+
+        // Write the generated bricks to a Brickadia save file or mesh, based on the extension
+        if !dry_run {
+            info!("Writing {} to {}", OutputFormat::from_path(&out_file), out_file);
+            let write_start = Instant::now();
+            let progress = log.progress();
+            let written = write_bricks(&out_file, bricks, owner_id, owner_name, &progress);
+            drop(progress);
+            if let Err(err) = written {
+                return error!("Error writing output: {}", err);
+            }
+            stats.record_phase("write output", write_start);
+        }
+        stats
+    };
+    stats.phases.insert(
+        0,
+        PhaseTiming {
+            name: "read images".to_string(),
+            seconds: read_time,
         },
-        Some(ext) => {
-            return error!("Unsupported colormap format '{}'", ext);
+    );
+
+    // Print the report table, and write it as JSON if requested
+    log.merged(&stats);
+    log.report("finished", &stats);
+    if dry_run {
+        return match report_file {
+            Some(report_file) => info!("Dry run, nothing was written to {} or {}", out_file, report_file),
+            None => info!("Dry run, nothing was written to {}", out_file),
+        };
+    }
+    if let Some(report_file) = report_file {
+        info!("Writing report to {}", report_file);
+        if let Err(err) = File::create(report_file)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::to_writer_pretty(f, &stats).map_err(|e| e.to_string()))
+        {
+            return error!("Error writing report: {}", err);
+        }
+    }
+    info!("Done!");
+}
+
+/// Run the inspect subcommand: describe the maps and predict the brick count, writing nothing but the report
+fn inspect(matches: &ArgMatches, dry_run: bool, log: &'static CliLog) {
+    let job = match job_from_args(matches) {
+        Ok(job) => job,
+        Err(err) => return error!("{}", err),
+    };
+    // The job's report is for generating, this one has the map info too, so it's only given here
+    let report_file = matches.value_of("report");
+    let (colormap, heightmap) = match read_job_maps(&job, &log.progress()) {
+        Ok(maps) => maps,
+        Err(err) => return error!("Error reading images: {}", err),
+    };
+
+    let info = MapInfo::new(&*heightmap, &colormap);
+    log.report("map", &info);
+
+    // The brick count is predicted by generating the bricks and dropping them
+    let progress = log.progress();
+    let result = gen_opt_heightmap_with_stats(&*heightmap, &colormap, job.options, &progress);
+    drop(progress);
+    let stats = match result {
+        Ok((_, stats)) => stats,
+        Err(err) => return error!("Error during generation: {}", err),
+    };
+    log.merged(&stats);
+    log.report("finished", &stats);
+    info!("Predicted {} bricks", stats.bricks);
+
+    if let Some(report_file) = report_file {
+        if dry_run {
+            return info!("Dry run, nothing was written to {}", report_file);
         }
-        None => {
-            return error!("Missing colormap format for '{}'", colormap_file);
+        info!("Writing report to {}", report_file);
+        if let Err(err) = File::create(report_file)
+            .map_err(|e| e.to_string())
+            .and_then(|f| {
+                serde_json::to_writer_pretty(f, &serde_json::json!({ "map": info, "generation": stats }))
+                    .map_err(|e| e.to_string())
+            })
+        {
+            error!("Error writing report: {}", err);
+        }
+    }
+}
+
+/// Run the preview subcommand: generate the bricks and render them from above into a PNG
+fn preview(matches: &ArgMatches, dry_run: bool, log: &'static CliLog) {
+    let job = match job_from_args(matches) {
+        Ok(job) => job,
+        Err(err) => return error!("{}", err),
+    };
+    // The job's output is where its save goes, so the preview only goes where it's asked to
+    let out_file = matches.value_of("output").unwrap_or("preview.png");
+    let (colormap, heightmap) = match read_job_maps(&job, &log.progress()) {
+        Ok(maps) => maps,
+        Err(err) => return error!("Error reading images: {}", err),
+    };
+
+    let raster = RasterOptions {
+        size: job.options.size,
+        scale: job.options.scale,
+        lrgb: job.options.lrgb,
+    };
+    let progress = log.progress();
+    let result = gen_opt_heightmap(&*heightmap, &colormap, job.options, &progress);
+    drop(progress);
+    let bricks = match result {
+        Ok(bricks) => bricks,
+        Err(err) => return error!("Error during generation: {}", err),
+    };
+    info!("Rendering {} bricks", bricks.len());
+    let save = bricks_to_save(bricks, DEFAULT_OWNER_ID.to_string(), "Generator".to_string());
+    let image = match preview_image(&save, &raster) {
+        Ok(image) => image,
+        Err(err) => return error!("Error rendering preview: {}", err),
+    };
+
+    if dry_run {
+        return info!("Dry run, nothing was written to {}", out_file);
+    }
+    info!("Writing {}x{} preview to {}", image.width(), image.height(), out_file);
+    if let Err(err) = image.save(out_file) {
+        return error!("Error writing preview: {}", err);
+    }
+    info!("Done!");
+}
+
+/// Run the convert subcommand: write a save or brick dump in another format
+fn convert(matches: &ArgMatches, dry_run: bool) {
+    convert_file(
+        matches.value_of("INPUT").unwrap(),
+        matches.value_of("output").unwrap_or("./out.brs"),
+        matches.value_of("owner_id").map(String::from),
+        matches.value_of("owner").map(String::from),
+        dry_run,
+    )
+}
+
+/// Read the bricks of a save or brick dump and write them to another format
+/// The input's owner is kept unless another one is given
+fn convert_file(in_file: &str, out_file: &str, owner_id: Option<String>, owner: Option<String>, dry_run: bool) {
+    let bricks = if DumpFormat::from_path(in_file).is_some() {
+        info!("Reading brick dump {}", in_file);
+        read_dump(in_file).and_then(|records| {
+            let (id, name) = records_owner(&records)?
+                .unwrap_or_else(|| (DEFAULT_OWNER_ID.to_string(), "Generator".to_string()));
+            Ok((records_to_bricks(&records)?, id, name))
+        })
+    } else {
+        info!("Reading save {}", in_file);
+        read_save(in_file).and_then(save_to_bricks)
+    };
+    let (bricks, input_owner_id, input_owner) = match bricks {
+        Ok(bricks) => bricks,
+        Err(err) => {
+            return error!("Error reading {}: {}", in_file, err);
         }
     };
 
+    if dry_run {
+        return info!("Dry run, {} bricks were not written to {}", bricks.len(), out_file);
+    }
+    info!("Writing {} bricks to {}", bricks.len(), out_file);
+    let owner_id = owner_id.unwrap_or(input_owner_id);
+    let owner = owner.unwrap_or(input_owner);
+    if let Err(err) = write_bricks(out_file, bricks, owner_id, owner, &NoProgress) {
+        return error!("Error writing output: {}", err);
+    }
+    info!("Done!");
+}
+
+/// Run the batch subcommand: read the jobs, run them and print a summary
+/// Exits with an error status if any job failed, so scripts can tell
+fn batch(matches: &ArgMatches, dry_run: bool) {
+    let manifest = matches.value_of("MANIFEST").unwrap();
+    let jobs = if ConfigFormat::from_path(manifest).is_some() {
+        info!("Reading manifest {}", manifest);
+        read_manifest(manifest)
+    } else {
+        // Pattern jobs share the options of one job file
+        let defaults = match matches.value_of("config").map(JobConfig::read).transpose() {
+            Ok(defaults) => defaults.unwrap_or_default(),
+            Err(err) => return error!("Error reading config: {}", err),
+        };
+        glob_jobs(manifest, matches.value_of("colormap"), matches.value_of("output"), &defaults)
+    };
+    let jobs = match jobs {
+        Ok(jobs) => jobs,
+        Err(err) => return error!("Error reading jobs: {}", err),
+    };
+    if jobs.is_empty() {
+        return error!("No jobs to run in {}", manifest);
+    }
+
+    let parallel = match parse_arg::<usize>(matches, "jobs", "Jobs") {
+        Ok(parallel) => parallel.unwrap_or(1),
+        Err(err) => return error!("{}", err),
+    };
+    info!("Running {} jobs", jobs.len());
+    let summary = run_batch(&jobs, parallel, |job| run_job(job, dry_run));
+
+    info!("{}", summary);
+    if let Some(summary_file) = matches.value_of("summary") {
+        info!("Writing summary to {}", summary_file);
+        if let Err(err) = File::create(summary_file)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::to_writer_pretty(f, &summary).map_err(|e| e.to_string()))
+        {
+            error!("Error writing summary: {}", err);
+        }
+    }
+    if summary.failures() > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Generate one batch job and write its output (and report, if it has one)
+///
+/// # Returns
+/// * `Ok(usize)` with the number of bricks written (or that would be, in a dry run)
+/// * `Err(GenError)` with the reason the job failed
+fn run_job(job: &JobConfig, dry_run: bool) -> Result<usize, GenError> {
+    let out_file = job.output.clone().unwrap_or_else(|| "./out.brs".to_string());
+    let owner_id = job.owner_id.clone().unwrap_or_else(|| DEFAULT_OWNER_ID.to_string());
+    let owner_name = job.owner.clone().unwrap_or_else(|| "Generator".to_string());
+
+    info!("Generating {}", out_file);
+    let (colormap, heightmap) = read_job_maps(job, &NoProgress)?;
+    let (bricks, stats) = gen_opt_heightmap_with_stats(&*heightmap, &colormap, job.options.clone(), &NoProgress)?;
+    if dry_run {
+        return Ok(stats.bricks);
+    }
+    write_bricks(&out_file, bricks, owner_id, owner_name, &NoProgress)?;
+
+    if let Some(report_file) = &job.report {
+        let file = File::create(report_file).map_err(|e| GenError::io(report_file, e))?;
+        serde_json::to_writer_pretty(file, &stats).map_err(|e| GenError::io(report_file, e.into()))?;
+    }
+    Ok(stats.bricks)
+}
+
+/// Read the images of a job, coloring it with its first heightmap if it has no colormap
+/// `progress` receives each image read, see `read_maps`
+fn read_job_maps(job: &JobConfig, progress: &dyn Progress) -> Result<(ColormapPNG, Box<dyn Heightmap + Sync>), GenError> {
+    let heightmap_files = job.inputs.iter().map(String::as_str).collect::<Vec<&str>>();
+    let colormap_file = match (&job.colormap, heightmap_files.first()) {
+        (Some(colormap), _) => colormap.as_str(),
+        (None, Some(heightmap)) => heightmap,
+        (None, None) => {
+            return Err(GenError::Invalid(
+                "No input files, give them on the command line or as inputs in a --config file".to_string(),
+            ))
+        }
+    };
+    read_maps(heightmap_files, colormap_file, &job.options, progress)
+}
+
+/// Read the colormap and heightmap images
+///
+/// # Arguments
+/// * `heightmap_files` - Heightmap PNG images, stacked if there's more than one, or `-` for standard input
+/// * `colormap_file` - Colormap PNG image, or `-` for standard input
+/// * `options` - Generation options, for `img`, `hdmap`, `lrgb` and `scale`
+/// * `progress` - Receives the number of images read, the colormap and then the heightmaps
+///
+/// # Returns
+/// * `Ok((ColormapPNG, Box<dyn Heightmap + Sync>))` if every image could be read
+/// * `Err(GenError)` with the reason otherwise
+fn read_maps(
+    heightmap_files: Vec<&str>,
+    colormap_file: &str,
+    options: &GenOptions,
+    progress: &dyn Progress,
+) -> Result<(ColormapPNG, Box<dyn Heightmap + Sync>), GenError> {
+    let progress = ProgressScope::new(progress);
+    let files = heightmap_files.len() as u64 + 1;
+    progress.report(Phase::Reading, 0, files, 0.0)?;
+
+    // Standard input can only be read once, so it is buffered for the heightmap and colormap to share
+    let reads_stdin = heightmap_files.contains(&STDIO_PATH) || colormap_file == STDIO_PATH;
+    if reads_stdin && heightmap_files.len() > 1 {
+        return Err(GenError::Invalid("Stacked heightmaps can't be read from standard input".to_string()));
+    }
+    let mut stdin = vec![];
+    if reads_stdin {
+        io::stdin().read_to_end(&mut stdin).map_err(|e| GenError::io("standard input", e))?;
+    }
+
+    // Parse the colormap file to determine brick colors
+    // The colormap provides RGB color values for each pixel position
+    let colormap = match file_ext(&colormap_file.to_lowercase()) {
+        _ if colormap_file == STDIO_PATH => ColormapPNG::from_reader(Cursor::new(&stdin), options.lrgb)?,
+        Some("png") => ColormapPNG::new(colormap_file, options.lrgb)?,
+        Some(ext) => return Err(GenError::Unsupported(format!("Unsupported colormap format '{}'", ext))),
+        None => return Err(GenError::Unsupported(format!("Missing colormap format for '{}'", colormap_file))),
+    };
+    progress.report(Phase::Reading, 1, files, 1.0 / files as f32)?;
+
     // Parse the heightmap file(s) to determine brick heights
     // Heightmaps use grayscale or RGBA values to encode elevation data
-    let heightmap: Box<dyn Heightmap> =
-        if heightmap_files.iter().all(|f| file_ext(f) == Some("png")) {
-            if options.img {
-                // Create a flat heightmap for image rendering (no height variation)
-                Box::new(HeightmapFlat::new(colormap.size(), options.scale).unwrap())
-            } else {
-                // Load PNG heightmap(s) with optional high-detail RGBA encoding
-                match HeightmapPNG::new(heightmap_files, options.hdmap) {
-                    Ok(map) => Box::new(map),
-                    Err(error) => {
-                        return error!("Error reading heightmap: {:?}", error);
-                    }
-                }
-            }
+    let heightmap: Box<dyn Heightmap + Sync> = if heightmap_files.iter().all(|f| *f == STDIO_PATH || file_ext(f) == Some("png")) {
+        if options.img {
+            // Create a flat heightmap for image rendering (no height variation)
+            Box::new(HeightmapFlat::new(colormap.size(), options.scale).unwrap())
+        } else if heightmap_files == [STDIO_PATH] {
+            // Standard input holds a single encoded image
+            Box::new(HeightmapPNG::from_readers(vec![Cursor::new(&stdin)], options.hdmap)?)
         } else {
-            return error!("Unsupported heightmap format");
-        };
+            // Load PNG heightmap(s) with optional high-detail RGBA encoding
+            Box::new(HeightmapPNG::new(heightmap_files, options.hdmap)?)
+        }
+    } else {
+        return Err(GenError::Unsupported("Unsupported heightmap format".to_string()));
+    };
+    progress.report(Phase::Reading, files, files, 1.0)?;
 
-    // Generate optimized bricks from the heightmap and colormap
-    // The callback function |_| true means we never cancel the operation
-    let bricks = gen_opt_heightmap(&*heightmap, &colormap, options, |_| true)
-        .expect("error during generation");
-
-    // Write the generated bricks to a Brickadia save file
-    info!("Writing Save to {}", out_file);
-    let data = bricks_to_save(bricks, owner_id, owner_name);
-    SaveWriter::new(File::create(out_file).unwrap(), data)
-        .write()
-        .expect("Failed to write file!");
-    info!("Done!");
+    Ok((colormap, heightmap))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::App;
+    use std::{fs, process};
+
+    /// Build the job of `generate` arguments, with a job file holding `config`
+    fn job(config: &str, args: &[&str]) -> Result<JobConfig, GenError> {
+        let path = env::temp_dir().join(format!("heightmap-test-{}-cli.toml", process::id()));
+        fs::write(&path, config).unwrap();
+        let mut all = vec!["generate", "--config", path.to_str().unwrap()];
+        all.extend(args);
+        let matches = App::new("generate").args(&map_args()).get_matches_from(all);
+        let job = job_from_args(&matches);
+        fs::remove_file(&path).ok();
+        job
+    }
+
+    #[test]
+    fn flags_override_the_job_file() {
+        let config = "inputs = [\"config.png\"]\n\n[options]\nsize = 10\nscale = 7\ncull = true\n\n[options.subsoil]\ncolor = [60, 40, 20, 255]\n";
+
+        // Without flags the job file is used as it is
+        let from_file = job(config, &[]).unwrap();
+        assert_eq!(from_file.inputs, ["config.png"]);
+        assert_eq!((from_file.options.size, from_file.options.scale, from_file.options.cull), (10, 7, true));
+        assert_eq!(from_file.options.subsoil.map(|s| s.color), Some([60, 40, 20, 255]));
+
+        // Flags replace the values they give and add to the rest
+        let merged = job(config, &["cli.png", "-v", "3", "--stud", "--glow", "--subsoil", "0a141e"]).unwrap();
+        assert_eq!(merged.inputs, ["cli.png"]);
+        assert_eq!(merged.options.kind, BrickKind::Stud);
+        assert_eq!((merged.options.size, merged.options.scale), (10, 3));
+        assert!(merged.options.cull && merged.options.glow);
+        assert_eq!(merged.options.subsoil.map(|s| s.color), Some(to_linear_rgb([10, 20, 30, 255])));
+
+        // Sizes are given in studs on the command line, and the result is checked as a whole
+        assert_eq!(job(config, &["-s", "4"]).unwrap().options.size, 20);
+        assert!(matches!(job(config, &["--img"]), Err(GenError::Invalid(_))));
+        assert!(matches!(job(config, &["-v", "x"]), Err(GenError::Invalid(_))));
+    }
 }
@@ -1,13 +1,18 @@
 // External crate imports for byte ordering and image handling
 use byteorder::{BigEndian, ByteOrder}; // For reading multi-byte values from image data
-use image::RgbaImage;                   // RGBA image format from the image crate
-use std::result::Result;                // Standard Result type for error handling
+use image::{DynamicImage, ImageBuffer, ImageError, Luma, Rgba, RgbaImage}; // Image formats from the image crate
+use std::{
+    cmp::min,
+    io::{BufRead, Seek}, // For decoding images from readers
+    result::Result,      // Standard Result type for error handling
+};
 
-// Import color conversion utility from our util module
-use crate::util::to_linear_rgb;
+// Import color conversion utility from our util module, and our error type
+use crate::{error::GenError, util::to_linear_rgb};
 
 /// Generic trait for heightmaps that return elevation values at specific coordinates
 /// Heightmaps define the vertical structure of the terrain
+/// Generation reads maps from several threads, so its functions take maps that are also `Sync`
 pub trait Heightmap {
     /// Get the height value at the given x,y coordinates
     /// Returns a u32 representing elevation (higher values = higher terrain)
@@ -19,6 +24,7 @@ pub trait Heightmap {
 
 /// Generic trait for colormaps that return RGBA colors at specific coordinates
 /// Colormaps define the visual appearance of each terrain point
+/// Generation reads maps from several threads, so its functions take maps that are also `Sync`
 pub trait Colormap {
     /// Get the RGBA color at the given x,y coordinates
     /// Returns [r, g, b, a] where each component is 0-255
@@ -30,11 +36,13 @@ pub trait Colormap {
 
 /// PNG-based heightmap implementation that can load multiple images
 /// Supports both grayscale and RGBA-encoded heightmaps for high precision
+/// Images are decoded whole, 4 bytes per pixel, even when bricks are generated in blocks
 pub struct HeightmapPNG {
     /// Vector of loaded RGBA images representing height data
     maps: Vec<RgbaImage>,
     /// Whether this heightmap uses RGBA encoding for high precision heights
     /// If true, all 4 RGBA channels encode a single 32-bit height value
+    /// (16-bit grayscale images are converted to this encoding when loaded)
     /// If false, only the red channel is used as an 8-bit height value
     rgba_encoded: bool,
 }
@@ -70,23 +78,57 @@ impl HeightmapPNG {
     /// # Arguments
     /// * `images` - Vector of file paths to PNG images
     /// * `rgba_encoded` - Whether to interpret RGBA channels as 32-bit height values
+    ///   (16-bit grayscale images are read at full precision in this mode)
     /// 
     /// # Returns
     /// * `Ok(HeightmapPNG)` if all images loaded successfully and have matching dimensions
-    /// * `Err(String)` if no images provided, files couldn't be opened, or dimensions don't match
-    pub fn new(images: Vec<&str>, rgba_encoded: bool) -> Result<Self, String> {
+    /// * `Err(GenError)` if no images provided, files couldn't be read, or dimensions don't match
+    pub fn new(images: Vec<&str>, rgba_encoded: bool) -> Result<Self, GenError> {
+        let images = images.into_iter().map(open_image).collect::<Result<Vec<_>, _>>()?;
+        Self::from_images(images, rgba_encoded)
+    }
+
+    /// Create a new PNG heightmap from readers of encoded images, like stdin or in-memory files
+    /// The image format is guessed from the data
+    ///
+    /// # Arguments
+    /// * `readers` - Vector of readers, each holding one encoded image
+    /// * `rgba_encoded` - Whether to interpret RGBA channels as 32-bit height values
+    ///
+    /// # Returns
+    /// * `Ok(HeightmapPNG)` if all images decoded successfully and have matching dimensions
+    /// * `Err(GenError)` if no readers provided, an image couldn't be decoded, or dimensions don't match
+    pub fn from_readers<R: BufRead + Seek>(readers: Vec<R>, rgba_encoded: bool) -> Result<Self, GenError> {
+        let images = readers
+            .into_iter()
+            .map(|reader| decode_image(reader, "heightmap"))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_images(images, rgba_encoded)
+    }
+
+    /// Create a new PNG heightmap from images already in memory, without touching the filesystem
+    ///
+    /// # Arguments
+    /// * `images` - Vector of decoded images, stacked if there's more than one
+    /// * `rgba_encoded` - Whether to interpret RGBA channels as 32-bit height values
+    ///   (16-bit grayscale images are kept at full precision in this mode)
+    ///
+    /// # Returns
+    /// * `Ok(HeightmapPNG)` if all images have matching dimensions
+    /// * `Err(GenError)` if no images provided or dimensions don't match
+    pub fn from_images(images: Vec<DynamicImage>, rgba_encoded: bool) -> Result<Self, GenError> {
         if images.is_empty() {
-            return Err("HeightmapPNG requires at least one image".to_string());
+            return Err(GenError::Invalid("HeightmapPNG requires at least one image".to_string()));
         }
 
-        // Load all image files into RGBA format
+        // Convert all images into RGBA format
         let mut maps: Vec<RgbaImage> = vec![];
-        for file in images {
-            if let Ok(img) = image::open(file) {
+        for image in images {
+            match image {
+                // 16-bit grayscale heightmaps keep their full precision in high detail mode
+                DynamicImage::ImageLuma16(img) if rgba_encoded => maps.push(widen_luma16(&img)),
                 // Convert any image format to RGBA8 for consistent processing
-                maps.push(img.to_rgba8());
-            } else {
-                return Err(format!("Could not open PNG {}", file));
+                img => maps.push(img.to_rgba8()),
             }
         }
 
@@ -96,7 +138,10 @@ impl HeightmapPNG {
         let width = maps[0].width();
         for m in &maps {
             if m.height() != height || m.width() != width {
-                return Err("Mismatched heightmap sizes".to_string());
+                return Err(GenError::DimensionMismatch {
+                    expected: (width, height),
+                    found: (m.width(), m.height()),
+                });
             }
         }
 
@@ -105,6 +150,39 @@ impl HeightmapPNG {
     }
 }
 
+/// Open an image file, telling apart files that couldn't be read from ones that couldn't be decoded
+fn open_image(file: &str) -> Result<DynamicImage, GenError> {
+    image::open(file).map_err(|e| image_error(file, e))
+}
+
+/// Decode an image from a reader, guessing its format from the data
+/// `name` describes the image in errors, since there is no file name
+fn decode_image<R: BufRead + Seek>(reader: R, name: &str) -> Result<DynamicImage, GenError> {
+    image::io::Reader::new(reader)
+        .with_guessed_format()
+        .map_err(|e| GenError::io(name, e))?
+        .decode()
+        .map_err(|e| image_error(name, e))
+}
+
+/// Turn an image error into our error type, naming the file or image it came from
+fn image_error(name: &str, e: ImageError) -> GenError {
+    match e {
+        ImageError::IoError(e) => GenError::io(name, e),
+        ImageError::Unsupported(e) => GenError::Unsupported(format!("{}: {}", name, e)),
+        e => GenError::decode(name, e),
+    }
+}
+
+/// Store a 16-bit grayscale image in the RGBA encoding used by high detail heightmaps
+/// The 16-bit value goes in the blue and alpha channels so it reads back as a big endian u32
+fn widen_luma16(img: &ImageBuffer<Luma<u16>, Vec<u16>>) -> RgbaImage {
+    RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        let [hi, lo] = img.get_pixel(x, y).0[0].to_be_bytes();
+        Rgba([0, 0, hi, lo])
+    })
+}
+
 /// A completely flat heightmap with uniform elevation
 /// Used for image rendering mode where no height variation is desired
 pub struct HeightmapFlat {
@@ -140,7 +218,7 @@ impl HeightmapFlat {
     /// 
     /// # Returns
     /// * `Ok(HeightmapFlat)` - Always succeeds since flat heightmaps are simple
-    pub fn new((width, height): (u32, u32), scale: u32) -> Result<Self, String> {
+    pub fn new((width, height): (u32, u32), scale: u32) -> Result<Self, GenError> {
         // return a reference to save on memory
         Ok(HeightmapFlat { width, height, scale })
     }
@@ -148,6 +226,7 @@ impl HeightmapFlat {
 
 /// PNG-based colormap implementation for reading color data from image files
 /// Supports both linear RGB and sRGB color spaces
+/// The image is decoded whole, 4 bytes per pixel, even when bricks are generated in blocks
 pub struct ColormapPNG {
     /// The source RGBA image containing color data
     source: RgbaImage,
@@ -184,16 +263,467 @@ impl ColormapPNG {
     /// 
     /// # Returns
     /// * `Ok(ColormapPNG)` if the image loaded successfully
-    /// * `Err(String)` if the image file couldn't be opened
-    pub fn new(file: &str, lrgb: bool) -> Result<Self, String> {
-        if let Ok(img) = image::open(file) {
-            Ok(ColormapPNG {
-                // Convert any image format to RGBA8 for consistent processing
-                source: img.to_rgba8(),
-                lrgb,
-            })
+    /// * `Err(GenError)` if the image file couldn't be read or decoded
+    pub fn new(file: &str, lrgb: bool) -> Result<Self, GenError> {
+        Ok(ColormapPNG {
+            // Convert any image format to RGBA8 for consistent processing
+            source: open_image(file)?.to_rgba8(),
+            lrgb,
+        })
+    }
+
+    /// Create a new PNG colormap from a reader of an encoded image, like stdin or an in-memory file
+    /// The image format is guessed from the data
+    ///
+    /// # Arguments
+    /// * `reader` - Reader holding the encoded image
+    /// * `lrgb` - Whether the input image is in linear RGB (true) or sRGB (false) color space
+    ///
+    /// # Returns
+    /// * `Ok(ColormapPNG)` if the image decoded successfully
+    /// * `Err(GenError)` if the image couldn't be read or decoded
+    pub fn from_reader<R: BufRead + Seek>(reader: R, lrgb: bool) -> Result<Self, GenError> {
+        Ok(ColormapPNG {
+            source: decode_image(reader, "colormap")?.to_rgba8(),
+            lrgb,
+        })
+    }
+
+    /// Create a new PNG colormap from an image already in memory, without touching the filesystem
+    ///
+    /// # Arguments
+    /// * `image` - The decoded image
+    /// * `lrgb` - Whether the image is in linear RGB (true) or sRGB (false) color space
+    pub fn from_image(image: DynamicImage, lrgb: bool) -> Self {
+        ColormapPNG {
+            source: image.to_rgba8(),
+            lrgb,
+        }
+    }
+}
+
+/// Heightmap built from a buffer of heights, for terrain that is already in memory
+/// Heights are stored row by row, starting at the top left
+pub struct HeightmapGrid {
+    /// One height per pixel
+    heights: Vec<u32>,
+    /// Width and height in pixels
+    size: (u32, u32),
+}
+
+impl Heightmap for HeightmapGrid {
+    fn at(&self, x: u32, y: u32) -> u32 {
+        self.heights[(y * self.size.0 + x) as usize]
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+impl HeightmapGrid {
+    /// Create a heightmap from heights stored row by row
+    ///
+    /// # Arguments
+    /// * `heights` - One height per pixel, `width * height` in total
+    /// * `(width, height)` - Dimensions in pixels
+    ///
+    /// # Returns
+    /// * `Ok(HeightmapGrid)` if there is one height per pixel
+    /// * `Err(GenError)` if the buffer is empty or its length doesn't match the dimensions
+    ///
+    /// # Examples
+    /// ```
+    /// use heightmap::{map::*, progress::NoProgress, quad::gen_opt_heightmap, util::GenOptions};
+    ///
+    /// let heightmap = HeightmapGrid::new(vec![4; 16], (4, 4)).unwrap();
+    /// let colormap = ColormapGrid::new(vec![[80, 160, 60, 255]; 16], (4, 4), false).unwrap();
+    /// let bricks = gen_opt_heightmap(&heightmap, &colormap, GenOptions::default(), &NoProgress).unwrap();
+    /// assert_eq!(bricks.len(), 1);
+    /// ```
+    pub fn new(heights: Vec<u32>, (width, height): (u32, u32)) -> Result<Self, GenError> {
+        check_grid_len(heights.len(), (width, height))?;
+        Ok(HeightmapGrid {
+            heights,
+            size: (width, height),
+        })
+    }
+}
+
+/// Colormap built from a buffer of RGBA colors, for terrain that is already in memory
+/// Colors are stored row by row, starting at the top left
+pub struct ColormapGrid {
+    /// One color per pixel
+    colors: Vec<[u8; 4]>,
+    /// Width and height in pixels
+    size: (u32, u32),
+    /// Whether the colors are in linear RGB (true) or sRGB (false) color space
+    lrgb: bool,
+}
+
+impl Colormap for ColormapGrid {
+    fn at(&self, x: u32, y: u32) -> [u8; 4] {
+        let color = self.colors[(y * self.size.0 + x) as usize];
+        if self.lrgb {
+            color
         } else {
-            Err(format!("Could not open PNG {}", file))
+            to_linear_rgb(color)
         }
     }
+
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+impl ColormapGrid {
+    /// Create a colormap from RGBA colors stored row by row
+    ///
+    /// # Arguments
+    /// * `colors` - One color per pixel, `width * height` in total
+    /// * `(width, height)` - Dimensions in pixels
+    /// * `lrgb` - Whether the colors are in linear RGB (true) or sRGB (false) color space
+    ///
+    /// # Returns
+    /// * `Ok(ColormapGrid)` if there is one color per pixel
+    /// * `Err(GenError)` if the buffer is empty or its length doesn't match the dimensions
+    pub fn new(colors: Vec<[u8; 4]>, (width, height): (u32, u32), lrgb: bool) -> Result<Self, GenError> {
+        check_grid_len(colors.len(), (width, height))?;
+        Ok(ColormapGrid {
+            colors,
+            size: (width, height),
+            lrgb,
+        })
+    }
+}
+
+/// Check that a grid buffer has one value per pixel
+fn check_grid_len(len: usize, (width, height): (u32, u32)) -> Result<(), GenError> {
+    if width == 0 || height == 0 {
+        return Err(GenError::Invalid("Grid maps must be at least 1x1".to_string()));
+    }
+    if len != width as usize * height as usize {
+        return Err(GenError::Invalid(format!(
+            "A {}x{} grid needs {} values, got {}",
+            width,
+            height,
+            width as usize * height as usize,
+            len
+        )));
+    }
+    Ok(())
+}
+
+/// Heightmap adapter that downsamples another heightmap by an integer factor
+/// Each output pixel samples the center of a `factor`x`factor` block of the source
+pub struct HeightmapScaled<'a> {
+    /// The full resolution source heightmap
+    source: &'a (dyn Heightmap + Sync),
+    /// Number of source pixels per output pixel along each axis
+    factor: u32,
+}
+
+/// Implementation of the Heightmap trait for downsampled heightmaps
+impl Heightmap for HeightmapScaled<'_> {
+    fn at(&self, x: u32, y: u32) -> u32 {
+        let (w, h) = self.source.size();
+        // Sample the block center, clamped for partial blocks at the edges
+        self.source.at(
+            min(x * self.factor + self.factor / 2, w - 1),
+            min(y * self.factor + self.factor / 2, h - 1),
+        )
+    }
+
+    fn size(&self) -> (u32, u32) {
+        let (w, h) = self.source.size();
+        // Partial blocks at the edges still get a pixel
+        (w.div_ceil(self.factor), h.div_ceil(self.factor))
+    }
+}
+
+impl<'a> HeightmapScaled<'a> {
+    /// Create a downsampled view of a heightmap
+    ///
+    /// # Arguments
+    /// * `source` - The heightmap to downsample
+    /// * `factor` - Number of source pixels per output pixel (1 = unchanged)
+    pub fn new(source: &'a (dyn Heightmap + Sync), factor: u32) -> Self {
+        HeightmapScaled { source, factor: factor.max(1) }
+    }
+}
+
+/// Heightmap adapter that rounds heights down to a multiple of a step
+/// Coarser heights make more neighboring tiles equal, so they merge into fewer bricks
+pub struct HeightmapQuantized<'a> {
+    /// The source heightmap
+    source: &'a (dyn Heightmap + Sync),
+    /// Height step, heights are rounded down to a multiple of this
+    step: u32,
+}
+
+/// Implementation of the Heightmap trait for quantized heightmaps
+impl Heightmap for HeightmapQuantized<'_> {
+    fn at(&self, x: u32, y: u32) -> u32 {
+        self.source.at(x, y) / self.step * self.step
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.source.size()
+    }
+}
+
+impl<'a> HeightmapQuantized<'a> {
+    /// Create a quantized view of a heightmap
+    ///
+    /// # Arguments
+    /// * `source` - The heightmap to quantize
+    /// * `step` - Height step (1 = unchanged)
+    pub fn new(source: &'a (dyn Heightmap + Sync), step: u32) -> Self {
+        HeightmapQuantized { source, step: step.max(1) }
+    }
+}
+
+/// Colormap adapter that downsamples another colormap by an integer factor
+/// Samples the same pixels as `HeightmapScaled` so heights and colors stay aligned
+pub struct ColormapScaled<'a> {
+    /// The full resolution source colormap
+    source: &'a (dyn Colormap + Sync),
+    /// Number of source pixels per output pixel along each axis
+    factor: u32,
+}
+
+/// Implementation of the Colormap trait for downsampled colormaps
+impl Colormap for ColormapScaled<'_> {
+    fn at(&self, x: u32, y: u32) -> [u8; 4] {
+        let (w, h) = self.source.size();
+        self.source.at(
+            min(x * self.factor + self.factor / 2, w - 1),
+            min(y * self.factor + self.factor / 2, h - 1),
+        )
+    }
+
+    fn size(&self) -> (u32, u32) {
+        let (w, h) = self.source.size();
+        (w.div_ceil(self.factor), h.div_ceil(self.factor))
+    }
+}
+
+impl<'a> ColormapScaled<'a> {
+    /// Create a downsampled view of a colormap
+    ///
+    /// # Arguments
+    /// * `source` - The colormap to downsample
+    /// * `factor` - Number of source pixels per output pixel (1 = unchanged)
+    pub fn new(source: &'a (dyn Colormap + Sync), factor: u32) -> Self {
+        ColormapScaled { source, factor: factor.max(1) }
+    }
+}
+
+/// Colormap adapter that rounds color channels to a multiple of a step
+/// Fewer distinct colors lets more neighboring tiles merge into fewer bricks
+pub struct ColormapQuantized<'a> {
+    /// The source colormap
+    source: &'a (dyn Colormap + Sync),
+    /// Channel step, the red, green and blue channels are rounded to a multiple of this
+    step: u8,
+}
+
+/// Implementation of the Colormap trait for quantized colormaps
+impl Colormap for ColormapQuantized<'_> {
+    fn at(&self, x: u32, y: u32) -> [u8; 4] {
+        let [r, g, b, a] = self.source.at(x, y);
+        let step = self.step as u32;
+        // Round to the nearest step, alpha is left alone so culling still works
+        let round = |c: u8| min((c as u32 + step / 2) / step * step, 255) as u8;
+        [round(r), round(g), round(b), a]
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.source.size()
+    }
+}
+
+impl<'a> ColormapQuantized<'a> {
+    /// Create a quantized view of a colormap
+    ///
+    /// # Arguments
+    /// * `source` - The colormap to quantize
+    /// * `step` - Channel step (1 = unchanged)
+    pub fn new(source: &'a (dyn Colormap + Sync), step: u8) -> Self {
+        ColormapQuantized { source, step: step.max(1) }
+    }
+}
+
+/// Heightmap adapter that exposes a rectangular window of another heightmap
+/// Used to generate large maps one block at a time
+pub struct HeightmapWindow<'a> {
+    /// The full source heightmap
+    source: &'a (dyn Heightmap + Sync),
+    /// Top-left corner of the window in the source
+    origin: (u32, u32),
+    /// Width and height of the window
+    size: (u32, u32),
+}
+
+/// Implementation of the Heightmap trait for heightmap windows
+impl Heightmap for HeightmapWindow<'_> {
+    fn at(&self, x: u32, y: u32) -> u32 {
+        self.source.at(self.origin.0 + x, self.origin.1 + y)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+impl<'a> HeightmapWindow<'a> {
+    /// Create a view of part of a heightmap
+    /// The window is clipped to the edges of the source
+    ///
+    /// # Arguments
+    /// * `source` - The heightmap to view
+    /// * `origin` - Top-left corner of the window
+    /// * `size` - Width and height of the window
+    pub fn new(source: &'a (dyn Heightmap + Sync), origin: (u32, u32), size: (u32, u32)) -> Self {
+        let (w, h) = source.size();
+        let size = (
+            min(size.0, w.saturating_sub(origin.0)),
+            min(size.1, h.saturating_sub(origin.1)),
+        );
+        HeightmapWindow { source, origin, size }
+    }
+}
+
+/// Colormap adapter that exposes a rectangular window of another colormap
+/// Covers the same pixels as a `HeightmapWindow` with the same origin and size
+pub struct ColormapWindow<'a> {
+    /// The full source colormap
+    source: &'a (dyn Colormap + Sync),
+    /// Top-left corner of the window in the source
+    origin: (u32, u32),
+    /// Width and height of the window
+    size: (u32, u32),
+}
+
+/// Implementation of the Colormap trait for colormap windows
+impl Colormap for ColormapWindow<'_> {
+    fn at(&self, x: u32, y: u32) -> [u8; 4] {
+        self.source.at(self.origin.0 + x, self.origin.1 + y)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+impl<'a> ColormapWindow<'a> {
+    /// Create a view of part of a colormap
+    /// The window is clipped to the edges of the source
+    ///
+    /// # Arguments
+    /// * `source` - The colormap to view
+    /// * `origin` - Top-left corner of the window
+    /// * `size` - Width and height of the window
+    pub fn new(source: &'a (dyn Colormap + Sync), origin: (u32, u32), size: (u32, u32)) -> Self {
+        let (w, h) = source.size();
+        let size = (
+            min(size.0, w.saturating_sub(origin.0)),
+            min(size.1, h.saturating_sub(origin.1)),
+        );
+        ColormapWindow { source, origin, size }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{progress::NoProgress, quad::gen_opt_heightmap, util::GenOptions};
+    use image::{GrayImage, ImageOutputFormat};
+    use std::{env, fs, io::Cursor, process};
+
+    /// Encode an image as PNG in memory
+    fn png(image: &DynamicImage) -> Vec<u8> {
+        let mut bytes = vec![];
+        image.write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png).unwrap();
+        bytes
+    }
+
+    /// Every height and color of a pair of maps, row by row
+    fn pixels(heightmap: &dyn Heightmap, colormap: &dyn Colormap) -> Vec<(u32, [u8; 4])> {
+        let (width, height) = heightmap.size();
+        assert_eq!(colormap.size(), (width, height));
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| (heightmap.at(x, y), colormap.at(x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn memory_maps_read_like_png_files() {
+        let (width, height) = (11, 6);
+        let heights: Vec<u32> = (0..width * height).map(|i| 5 + (i * 7) % 60).collect();
+        let colors: Vec<[u8; 4]> = (0..width * height).map(|i| [(i * 3) as u8, 200, (i * 11) as u8, 255]).collect();
+        let height_image = DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            Luma([heights[(y * width + x) as usize] as u8])
+        }));
+        let color_image = DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            Rgba(colors[(y * width + x) as usize])
+        }));
+
+        let dir = env::temp_dir();
+        let height_path = dir.join(format!("heightmap-test-{}-height.png", process::id()));
+        let color_path = dir.join(format!("heightmap-test-{}-color.png", process::id()));
+        fs::write(&height_path, png(&height_image)).unwrap();
+        fs::write(&color_path, png(&color_image)).unwrap();
+        let from_files = (
+            HeightmapPNG::new(vec![height_path.to_str().unwrap()], false),
+            ColormapPNG::new(color_path.to_str().unwrap(), false),
+        );
+        fs::remove_file(&height_path).ok();
+        fs::remove_file(&color_path).ok();
+        let from_files = (from_files.0.unwrap(), from_files.1.unwrap());
+
+        // Files, readers, decoded images and grids all give the same pixels
+        let expected = pixels(&from_files.0, &from_files.1);
+        let from_images = (
+            HeightmapPNG::from_images(vec![height_image.clone()], false).unwrap(),
+            ColormapPNG::from_image(color_image.clone(), false),
+        );
+        let from_readers = (
+            HeightmapPNG::from_readers(vec![Cursor::new(png(&height_image))], false).unwrap(),
+            ColormapPNG::from_reader(Cursor::new(png(&color_image)), false).unwrap(),
+        );
+        let grids = (
+            HeightmapGrid::new(heights.clone(), (width, height)).unwrap(),
+            ColormapGrid::new(colors.clone(), (width, height), false).unwrap(),
+        );
+        assert_eq!(pixels(&from_images.0, &from_images.1), expected);
+        assert_eq!(pixels(&from_readers.0, &from_readers.1), expected);
+        assert_eq!(pixels(&grids.0, &grids.1), expected);
+        assert_eq!(expected.iter().map(|p| p.0).collect::<Vec<_>>(), heights);
+
+        // So they generate the same bricks
+        let generate = |heightmap: &(dyn Heightmap + Sync), colormap: &(dyn Colormap + Sync)| {
+            format!("{:?}", gen_opt_heightmap(heightmap, colormap, GenOptions::default(), &NoProgress).unwrap())
+        };
+        assert_eq!(generate(&grids.0, &grids.1), generate(&from_files.0, &from_files.1));
+    }
+
+    #[test]
+    fn high_detail_images_keep_16_bit_heights() {
+        let heights: Vec<u32> = (0..12).map(|i| 1000 + i * 4099).collect();
+        let image = ImageBuffer::from_fn(4, 3, |x, y| Luma([heights[(y * 4 + x) as usize] as u16]));
+        let heightmap = HeightmapPNG::from_images(vec![DynamicImage::ImageLuma16(image)], true).unwrap();
+        let grid = HeightmapGrid::new(heights, (4, 3)).unwrap();
+        let colormap = ColormapGrid::new(vec![[255; 4]; 12], (4, 3), true).unwrap();
+        assert_eq!(pixels(&heightmap, &colormap), pixels(&grid, &colormap));
+
+        // Mismatched sizes are rejected
+        let small = DynamicImage::ImageLuma8(GrayImage::new(2, 2));
+        let large = DynamicImage::ImageLuma8(GrayImage::new(3, 2));
+        assert!(matches!(HeightmapPNG::from_images(vec![small, large], false), Err(GenError::DimensionMismatch { .. })));
+        assert!(HeightmapPNG::from_images(vec![], false).is_err());
+        assert!(HeightmapGrid::new(vec![1; 5], (2, 3)).is_err());
+        assert!(ColormapGrid::new(vec![], (0, 0), true).is_err());
+    }
 }
@@ -0,0 +1,255 @@
+// Import our error type for cancellation
+use crate::error::GenError;
+// Import serde for writing progress events as JSON
+use serde::Serialize;
+// Import standard library items
+use std::{
+    cmp::max,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering}, // For cancelling from another thread
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Steps of a generation that progress is reported for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "phase", content = "n")]
+pub enum Phase {
+    /// Reading the input images
+    Reading,
+    /// Finding the full layer heights of a map generated in blocks
+    FindingLayers,
+    /// Building the quadtree of tiles
+    BuildingTree,
+    /// Merging squares of tiles of this width (1, 2, 4, ...) in the quadtree
+    QuadLevel(u32),
+    /// Merging the largest rectangles with the greedy optimizer
+    GreedyMerge,
+    /// Merging lines of tiles, counting passes from 1
+    LinePass(u32),
+    /// Turning tiles into bricks
+    EmittingBricks,
+    /// Writing the output
+    Writing,
+}
+
+/// Phase names for progress bars and log messages
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Phase::Reading => write!(f, "reading images"),
+            Phase::FindingLayers => write!(f, "finding layer heights"),
+            Phase::BuildingTree => write!(f, "building quadtree"),
+            Phase::QuadLevel(size) => write!(f, "merging {}x tiles", size),
+            Phase::GreedyMerge => write!(f, "merging rectangles"),
+            Phase::LinePass(pass) => write!(f, "line pass {}", pass),
+            Phase::EmittingBricks => write!(f, "emitting bricks"),
+            Phase::Writing => write!(f, "writing output"),
+        }
+    }
+}
+
+/// One progress report, sent as a generation moves through its phases
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ProgressUpdate {
+    /// Phase being worked on
+    #[serde(flatten)]
+    pub phase: Phase,
+    /// Items of the phase done so far (pixels, levels, passes, blocks or bricks)
+    pub done: u64,
+    /// Items in the phase, 0 if it isn't known up front
+    pub total: u64,
+    /// Block being generated and the number of blocks, for maps generated in blocks
+    pub block: Option<(u32, u32)>,
+    /// Fraction of the whole generation done, from 0 to 1
+    pub fraction: f32,
+    /// Time since the generation started
+    #[serde(skip)]
+    pub elapsed: Duration,
+}
+
+impl ProgressUpdate {
+    /// Estimated time left, from the time taken so far and the fraction done
+    /// None until enough is done to tell
+    pub fn eta(&self) -> Option<Duration> {
+        if self.fraction < 0.01 {
+            return None;
+        }
+        let left = (1.0 - self.fraction.min(1.0)) / self.fraction;
+        Some(self.elapsed.mul_f32(left))
+    }
+}
+
+/// Receives progress updates from a generation, and can ask it to stop
+/// Closures taking a `&ProgressUpdate` are progress reporters that never cancel
+pub trait Progress {
+    /// Called when a phase starts or gets further
+    fn update(&self, update: &ProgressUpdate);
+
+    /// Checked after every update, generation stops with `GenError::Cancelled` once this is true
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+impl<F: Fn(&ProgressUpdate)> Progress for F {
+    fn update(&self, update: &ProgressUpdate) {
+        self(update)
+    }
+}
+
+/// Progress reporter that ignores every update and never cancels
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn update(&self, _update: &ProgressUpdate) {}
+}
+
+/// Token that cancels a generation from another thread
+/// Clones share the same flag, so one can be kept to cancel while another is passed as the progress
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a token that isn't cancelled yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask every generation using this token to stop at its next progress update
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether `cancel` has been called
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Progress for CancelToken {
+    fn update(&self, _update: &ProgressUpdate) {}
+
+    fn is_cancelled(&self) -> bool {
+        CancelToken::is_cancelled(self)
+    }
+}
+
+/// Reports the progress of one part of a generation as a share of the whole,
+/// so nested steps like blocks, budget attempts and optimizer runs add up to one bar
+#[derive(Clone, Copy)]
+pub struct ProgressScope<'a> {
+    /// Where updates go
+    progress: &'a dyn Progress,
+    /// When the whole generation started
+    start: Instant,
+    /// Fraction of the whole generation done when this part starts
+    offset: f32,
+    /// Fraction of the whole generation this part takes
+    share: f32,
+    /// Block this part belongs to, and the number of blocks
+    block: Option<(u32, u32)>,
+}
+
+impl<'a> ProgressScope<'a> {
+    /// Start reporting a whole generation
+    pub fn new(progress: &'a dyn Progress) -> Self {
+        ProgressScope {
+            progress,
+            start: Instant::now(),
+            offset: 0.0,
+            share: 1.0,
+            block: None,
+        }
+    }
+
+    /// The part of this scope from `from` to `to`, as fractions of this scope
+    pub fn part(&self, from: f32, to: f32) -> Self {
+        ProgressScope {
+            offset: self.offset + from * self.share,
+            share: (to - from) * self.share,
+            ..*self
+        }
+    }
+
+    /// Block `index` (from 0) of `count` equal blocks of this scope
+    pub fn block(&self, index: u32, count: u32) -> Self {
+        let count = count.max(1);
+        ProgressScope {
+            block: Some((index + 1, count)),
+            ..self.part(index as f32 / count as f32, (index + 1) as f32 / count as f32)
+        }
+    }
+
+    /// Report that `done` of `total` items of a phase are done, `fraction` of the way through this scope
+    ///
+    /// # Returns
+    /// * `Ok(())` to carry on
+    /// * `Err(GenError::Cancelled)` if the progress reporter asked to stop
+    pub fn report(&self, phase: Phase, done: u64, total: u64, fraction: f32) -> Result<(), GenError> {
+        self.progress.update(&ProgressUpdate {
+            phase,
+            done,
+            total,
+            block: self.block,
+            fraction: self.offset + fraction.clamp(0.0, 1.0) * self.share,
+            elapsed: self.start.elapsed(),
+        });
+        if self.progress.is_cancelled() {
+            return Err(GenError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// Progress of one generation counted in the items it processes: pixels read while finding
+/// layers and building tiles, then tiles visited by each merging pass and by emitting bricks
+/// Items that are still to come are estimated from what the generation knows so far,
+/// and the estimate is corrected as it finds out more (layers, merging passes that aren't needed)
+pub(crate) struct WorkProgress<'a> {
+    /// Part of the progress the generation takes
+    scope: ProgressScope<'a>,
+    /// Items processed so far
+    done: u64,
+    /// Items expected in total, including the ones processed
+    total: u64,
+    /// Fraction last reported, so a raised estimate holds the bar instead of moving it back
+    reported: f32,
+}
+
+impl<'a> WorkProgress<'a> {
+    /// Start counting the items of a generation that is expected to process `total` of them
+    pub(crate) fn new(scope: ProgressScope<'a>, total: u64) -> Self {
+        WorkProgress {
+            scope,
+            done: 0,
+            total,
+            reported: 0.0,
+        }
+    }
+
+    /// Count `items` more as processed
+    pub(crate) fn processed(&mut self, items: u64) {
+        self.done += items;
+        self.total = max(self.total, self.done);
+    }
+
+    /// Expect `left` more items after the ones processed so far
+    pub(crate) fn expect(&mut self, left: u64) {
+        self.total = self.done + left;
+    }
+
+    /// Report that `done` of `total` items of a phase are done, with the fraction from the items processed
+    ///
+    /// # Returns
+    /// * `Ok(())` to carry on
+    /// * `Err(GenError::Cancelled)` if the progress reporter asked to stop
+    pub(crate) fn report(&mut self, phase: Phase, done: u64, total: u64) -> Result<(), GenError> {
+        let fraction = self.done as f32 / max(self.total, 1) as f32;
+        self.reported = self.reported.max(fraction);
+        self.scope.report(phase, done, total, self.reported)
+    }
+}
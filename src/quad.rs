@@ -1,5 +1,8 @@
 // Import our map and utility modules
+use crate::error::*; // Error type
 use crate::map::*;   // Heightmap and Colormap traits and implementations
+use crate::progress::*; // Progress reporting and cancellation
+use crate::stats::*; // Generation statistics
 use crate::util::*;  // Generation options and utility functions
 // Import Brickadia save file structures
 use brickadia::save::{Brick, BrickColor, Collision, Color, Size};
@@ -8,836 +11,1721 @@ use log::info;
 // Import standard library items
 use std::{
     cmp::{max, min},      // For finding minimum and maximum values
-    collections::{HashMap, HashSet}, // For storing unique neighbor height values and height-color mappings
+    collections::{BTreeMap, BTreeSet},   // For unique heights, height-color mappings and box groups, in order
+    mem,                  // For splitting mutable slices into strips
+    num::NonZeroUsize,    // For the number of available CPU cores
+    convert::Infallible,  // For running jobs that never stop early
+    sync::{mpsc, Mutex},  // For sharing the job queue and sending back results between worker threads
+    thread,               // For running strips on several threads
+    time::Instant,        // For timing each generation phase
 };
 
-/// Represents a single tile in the quadtree optimization structure
+/// Narrowest column strip used by line optimization, in tiles
+const MIN_LINE_STRIP: u32 = 64;
+
+/// One layer of tiles in the quadtree optimization structure, stored as a struct of arrays
+/// Tiles are kept in column-major order and only occupied tiles are stored,
+/// so sparse height layers take memory for their tiles rather than the whole image
 /// Tiles can be merged with adjacent similar tiles to reduce brick count
-#[derive(Debug, Default)]
-struct Tile {
-    /// Unique index of this tile in the tiles array
-    index: usize,
-    /// Center position (x, y) of this tile in the heightmap
-    center: (u32, u32),
-    /// Size (width, height) of this tile in heightmap units
+#[derive(Debug, Clone, Default)]
+struct TileLayer {
+    /// Index of the first tile of each column, with one extra entry past the last tile
+    col_start: Vec<usize>,
+    /// Row (y position) of each tile, its column (x position) is found from `col_start`
+    row: Vec<u32>,
+    /// Size (width, height) of each tile in heightmap units
     /// Can be larger than (1,1) if tiles have been merged
+    size: Vec<(u16, u16)>,
+    /// RGBA color values for each tile [r, g, b, a]
+    color: Vec<[u8; 4]>,
+    /// Height value for each tile (elevation)
+    height: Vec<u32>,
+    /// Whether each tile has been merged into another tile
+    merged: Vec<bool>,
+}
+
+/// Mutable view of a range of columns in a `TileLayer`
+/// Strips don't overlap, so several of them can be optimized at the same time
+struct TileStrip<'a> {
+    /// Number of rows in the full grid
+    rows: u32,
+    /// Number of columns in this strip
+    width: u32,
+    /// `col_start` entries of this strip's columns (one extra at the end)
+    col_start: &'a [usize],
+    /// Tile data for this strip's columns, see `TileLayer`
+    row: &'a [u32],
+    color: &'a [[u8; 4]],
+    height: &'a [u32],
+    size: &'a mut [(u16, u16)],
+    merged: &'a mut [bool],
+}
+
+/// Underground boxes below one tile, stacked on the grid from the floor up
+#[derive(Debug, Clone)]
+struct CellRect {
+    /// Top-left pixel of the tile
+    corner: (u32, u32),
+    /// Width and depth of the tile in pixels
     size: (u32, u32),
-    /// RGBA color values for this tile [r, g, b, a]
+    /// Color of the boxes
     color: [u8; 4],
-    /// Height value for this tile (elevation)
-    height: u32,
-    /// Set of height values from neighboring tiles
-    /// Used to calculate relative height differences for brick sizing
-    neighbors: HashSet<u32>,
-    /// Index of parent tile if this tile has been merged into another
-    /// None if this tile is still active (not merged)
-    parent: Option<usize>,
+    /// Number of grid cells from the floor up
+    levels: u32,
 }
 
 /// QuadTree structure for optimizing brick placement
 /// Contains a grid of tiles that can be merged to reduce total brick count
+#[derive(Clone)]
 pub struct QuadTree {
-    /// Boxed array of all tiles in the quadtree
-    /// Using Box<[Tile]> for memory efficiency with large grids
-    tiles: Box<[Tile]>,
-    /// Additional tile vectors for different height layers
-    /// Each vector represents tiles at a specific height level
-    height_layers: Vec<Box<[Tile]>>,
-    /// Sorted heights used for layer generation
-    /// Used to calculate z_adjustment for each layer
-    sorted_heights: Vec<u32>,
-    /// Colors that were found at height 0
-    /// Used to determine height adjustment for layers
-    height_0_colors: HashSet<[u8; 4]>,
-    /// Mapping from height to color for filtered heights
-    /// Used to determine which color corresponds to each height
-    filtered_heights: HashMap<u32, [u8; 4]>,
+    /// Tiles of the main layer, one per pixel
+    tiles: TileLayer,
+    /// Additional tile layers for different height levels
+    /// Each layer only holds the tiles that reach its height
+    height_layers: Vec<TileLayer>,
+    /// Heights that get full layers, found over the whole map
+    layers: LayerHeights,
     /// Width of the original heightmap/grid
     width: u32,
     /// Height of the original heightmap/grid
     height: u32,
+    /// Number of threads used for optimization
+    threads: usize,
 }
 
-impl Tile {
-    /// Check if another tile is similar enough to be merged in quadtree optimization
-    /// Tiles must have identical size, color, height, and both must be unmarged (no parent)
-    fn similar_quad(&self, other: &Self) -> bool {
-        self.size == other.size           // Same dimensions
-            && self.color == other.color  // Same RGBA color
-            && self.height == other.height // Same elevation
-            && self.parent.is_none()      // This tile not already merged
-            && other.parent.is_none()     // Other tile not already merged
-    }
-
-    /// Check if another tile can be merged in a line (horizontal or vertical)
-    /// Tiles must be aligned and have matching color/height, but can differ in one dimension
-    fn similar_line(&self, other: &Self) -> bool {
-        let is_vertical = self.center.0 == other.center.0;   // Same X coordinate
-        let is_horizontal = self.center.1 == other.center.1; // Same Y coordinate
-
-        // Must be aligned in one direction and have matching size in the other direction
-        (is_vertical && self.size.0 == other.size.0 || is_horizontal && self.size.1 == other.size.1)
-            && self.color == other.color  // Same RGBA color
-            && self.height == other.height // Same elevation
-            && self.parent.is_none()      // This tile not already merged
-            && other.parent.is_none()     // Other tile not already merged
-    }
-
-    /// Merge four adjacent tiles into this tile for quadtree optimization
-    /// This creates a single larger tile that replaces four smaller ones
-    fn merge_quad(
-        &mut self,
-        top_right: &mut Self,
-        bottom_left: &mut Self,
-        bottom_right: &mut Self,
-    ) {
-        // Double the size since we're merging 4 tiles into 1
-        self.size = (self.size.0 * 2, self.size.1 * 2);
-
-        // Combine neighbor height sets from all merged tiles
-        // This preserves information about surrounding heights for brick sizing
-        self.neighbors.extend(&top_right.neighbors);
-        self.neighbors.extend(&bottom_left.neighbors);
-        self.neighbors.extend(&bottom_right.neighbors);
-
-        // Mark the merged tiles as children of this tile
-        top_right.parent = Some(self.index);
-        bottom_left.parent = Some(self.index);
-        bottom_right.parent = Some(self.index);
-    }
+/// Heights (and their colors) that get full layers with `gen_full_layers_above_height`
+/// These are found over the whole map, so blocks of a map can share the same layers
+#[derive(Debug, Clone, Default)]
+pub struct LayerHeights {
+    /// Sorted heights used for layer generation, empty when full layers are off
+    /// Used to calculate z_adjustment for each layer
+    sorted_heights: Vec<u32>,
+    /// Colors that were found at height 0
+    /// Used to determine height adjustment for layers
+    height_0_colors: BTreeSet<[u8; 4]>,
+    /// Mapping from height to color for filtered heights
+    /// Used to determine which color corresponds to each height
+    filtered_heights: BTreeMap<u32, [u8; 4]>,
 }
 
-impl QuadTree {
-    /// Create a new quadtree from heightmap and colormap data
-    /// Initializes a grid of tiles, one per pixel in the input images
-    /// 
+impl LayerHeights {
+    /// Find the heights that get full layers over a whole map
+    /// Returns no layers if `gen_full_layers_above_height` is 0
+    ///
     /// # Arguments
     /// * `heightmap` - Source of elevation data
     /// * `colormap` - Source of color data
     /// * `gen_full_layers_above_height` - Height threshold above which to generate full layers
-    /// 
+    /// * `threads` - Number of threads to use (0 = one per CPU core)
+    ///
     /// # Returns
-    /// * `Ok(QuadTree)` if images have matching dimensions
-    /// * `Err(String)` if dimensions don't match
-    pub fn new(heightmap: &dyn Heightmap, colormap: &dyn Colormap, gen_full_layers_above_height: u32) -> Result<Self, String> {
+    /// * `Ok(LayerHeights)` if images have matching dimensions
+    /// * `Err(GenError)` if dimensions don't match
+    pub fn new(
+        heightmap: &(dyn Heightmap + Sync),
+        colormap: &(dyn Colormap + Sync),
+        gen_full_layers_above_height: u32,
+        threads: usize,
+    ) -> Result<Self, GenError> {
+        let mut work = WorkProgress::new(ProgressScope::new(&NoProgress), 0);
+        Self::find(heightmap, colormap, gen_full_layers_above_height, threads, &mut work)
+    }
+
+    /// Find the heights that get full layers over a whole map, reporting each strip of pixels read
+    /// See `LayerHeights::new` for details
+    pub(crate) fn find(
+        heightmap: &(dyn Heightmap + Sync),
+        colormap: &(dyn Colormap + Sync),
+        gen_full_layers_above_height: u32,
+        threads: usize,
+        work: &mut WorkProgress,
+    ) -> Result<Self, GenError> {
         let (width, height) = heightmap.size();
+        let threads = resolve_threads(threads);
 
         // Validate that both input images have matching dimensions
         if colormap.size() != heightmap.size() {
-            return Err("Heightmap and colormap must have same dimensions".to_string());
+            return Err(GenError::DimensionMismatch {
+                expected: heightmap.size(),
+                found: colormap.size(),
+            });
+        }
+        if gen_full_layers_above_height == 0 {
+            return Ok(Self::default());
         }
 
-        // First pass: collect all possible heights and their colors in the heightmap
-        let mut all_heights = HashMap::new();
-        let mut height_0_colors = HashSet::new();
-        for x in 0..width {
-            for y in 0..height {
-                let height = heightmap.at(x, y);
-                let color = colormap.at(x, y);
-                if height == 0 {
-                    height_0_colors.insert(color);
+        // Collect all possible heights and their colors in the heightmap
+        // Each strip collects its own, then they're merged in order so later pixels win as before
+        let mut all_heights = BTreeMap::new();
+        let mut height_0_colors = BTreeSet::new();
+        let area = width as u64 * height as u64;
+        let mut read = 0;
+        let strips = build_strips(width, threads);
+        let strip_heights = run_jobs_reporting(strips, threads, |(x0, w)| {
+            let mut strip_heights = BTreeMap::new();
+            let mut strip_0_colors = BTreeSet::new();
+            for x in x0..x0 + w {
+                for y in 0..height {
+                    let height = heightmap.at(x, y);
+                    let color = colormap.at(x, y);
+                    if height == 0 {
+                        strip_0_colors.insert(color);
+                    }
+                    strip_heights.insert(height, color);
                 }
-                all_heights.insert(height, color);
             }
+            (strip_heights, strip_0_colors, w as u64 * height as u64)
+        }, |&(_, _, pixels)| {
+            read += pixels;
+            work.processed(pixels);
+            work.report(Phase::FindingLayers, read, area)
+        })?;
+        for (strip_heights, strip_0_colors, _) in strip_heights {
+            all_heights.extend(strip_heights);
+            height_0_colors.extend(strip_0_colors);
         }
 
         // Filter heights: keep all heights above gen_full_layers_above_height,
         // and only the highest height at or below gen_full_layers_above_height
-        let filtered_heights: HashMap<u32, [u8; 4]> = if gen_full_layers_above_height > 0 {
-            let mut heights_at_or_below: Vec<u32> = all_heights
-                .keys()
-                .cloned()
-                .filter(|&h| h <= gen_full_layers_above_height)
-                .collect();
-            heights_at_or_below.sort();
-            
-            let mut result = HashMap::new();
-            
-            // Add all heights above the threshold
-            for (&height, &color) in &all_heights {
-                if height > gen_full_layers_above_height {
-                    result.insert(height, color);
-                }
+        let heights_at_or_below: Vec<u32> = all_heights
+            .keys()
+            .cloned()
+            .filter(|&h| h <= gen_full_layers_above_height)
+            .collect();
+
+        let mut filtered_heights = BTreeMap::new();
+
+        // Add all heights above the threshold
+        for (&height, &color) in &all_heights {
+            if height > gen_full_layers_above_height {
+                filtered_heights.insert(height, color);
             }
-            
-            // Add only the highest height at or below the threshold
-            if let Some(&highest_at_or_below) = heights_at_or_below.last() {
-                if let Some(&color) = all_heights.get(&highest_at_or_below) {
-                    result.insert(highest_at_or_below, color);
-                }
-            }
-            
-            result
-        } else {
-            // If gen_full_layers_above_height is 0, keep all heights
-            all_heights
-        };
+        }
 
-        if gen_full_layers_above_height > 0 && !filtered_heights.is_empty() {
-            // Get minimum height from filtered_heights for capping
-            let min_filtered_height = *filtered_heights.keys().min().unwrap();
-            
-            // Create a sorted vector of filtered heights for consistent ordering
-            let mut sorted_heights: Vec<u32> = filtered_heights.keys().cloned().collect();
-            sorted_heights.sort();
-            
-            // Create tiles vector for the first layer (capped heights)
-            let mut first_layer_tiles = Vec::with_capacity((width * height) as usize);
-            
-            // Create one tile for each pixel in the heightmap
-            // Using i32 for loop variables to allow negative values in neighbor calculations
-            for x in 0..width as i32 {
-                for y in 0..height as i32 {
-                    let original_height = heightmap.at(x as u32, y as u32);
-                    // For first layer: keep original height if it's <= min_filtered_height,
-                    // otherwise cap it to min_filtered_height
-                    let capped_height = if original_height > min_filtered_height {
-                        min_filtered_height
-                    } else {
-                        original_height
-                    };
-                    
-                    first_layer_tiles.push(Tile {
-                        // Calculate unique index for this tile in the flattened grid
-                        index: (x + y * height as i32) as usize,
-                        // Store the center coordinates of this tile
-                        center: (x as u32, y as u32),
-                        // Collect height values from all valid neighboring pixels
-                        // These are used later to calculate relative height differences
-                        neighbors: vec![(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
-                            .into_iter()
-                            // Filter out neighbors that are outside the image bounds
-                            .filter(|(x, y)| {
-                                *x >= 0 && *x < width as i32 && *y >= 0 && *y < height as i32
-                            })
-                            // Get height value for each valid neighbor
-                            .map(|(x, y)| heightmap.at(x as u32, y as u32))
-                            // Collect unique height values into a HashSet
-                            .fold(HashSet::new(), |mut set, height| {
-                                set.insert(height);
-                                set
-                            }),
-                        // Start with size 1x1 (single pixel)
-                        size: (1, 1),
-                        // Get color from colormap at this position
-                        color: if capped_height == min_filtered_height {
-                            filtered_heights[&min_filtered_height]
-                        } else {
-                            colormap.at(x as u32, y as u32)
-                        },
-                        // Use capped height for this layer
-                        height: capped_height,
-                        // Initially no parent (not merged)
-                        parent: None,
-                    })
-                }
-            }
-            
-            // Create additional layers for remaining heights
-            let mut height_layers = Vec::new();
-            for &layer_height in &sorted_heights[1..] {  // Skip first height as it's already in main tiles
-                let mut layer_tiles = Vec::with_capacity((width * height) as usize);
-                let layer_color = filtered_heights[&layer_height];  // Get the stored color for this height
-                let is_lake_layer = height_0_colors.contains(&layer_color);
-
-                // check layer color against ocean
-                // if layer color is ocean and current color != layer color, set height to 0
-                
-                for x in 0..width as i32 {
-                    for y in 0..height as i32 {
-                        let original_height = heightmap.at(x as u32, y as u32);
-                        let pixel_color = colormap.at(x as u32, y as u32);
-
-                        // Set tile height based on whether we're working on a lake or not
-                        let tile_height = if is_lake_layer {
-                            if pixel_color == layer_color && original_height == layer_height {
-                                layer_height
-                            } else {
-                                0
-                            }
-                        } else {
-                            if original_height >= layer_height {
-                                layer_height
-                            } else {
-                                0
-                            }
-                        };
-                        
-                        // Set tile height based on correspondence and original height
-                        // let tile_height = if color_corresponds_to_height_0 && original_height >= layer_height {
-                        //     layer_height
-                        // } else {
-                        //     0
-                        // };
-                        
-                        layer_tiles.push(Tile {
-                            // Calculate unique index for this tile in the flattened grid
-                            index: (x + y * height as i32) as usize,
-                            // Store the center coordinates of this tile
-                            center: (x as u32, y as u32),
-                            // Collect height values from all valid neighboring pixels
-                            neighbors: vec![(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
-                                .into_iter()
-                                // Filter out neighbors that are outside the image bounds
-                                .filter(|(x, y)| {
-                                    *x >= 0 && *x < width as i32 && *y >= 0 && *y < height as i32
-                                })
-                                // Get height value for each valid neighbor
-                                .map(|(x, y)| heightmap.at(x as u32, y as u32))
-                                // Collect unique height values into a HashSet
-                                .fold(HashSet::new(), |mut set, height| {
-                                    set.insert(height);
-                                    set
-                                }),
-                            // Start with size 1x1 (single pixel)
-                            size: (1, 1),
-                            // Use the color that was stored for this height instead of querying colormap
-                            color: layer_color,
-                            // Use layer height only if original matches, otherwise 0
-                            height: tile_height,
-                            // Initially no parent (not merged)
-                            parent: None,
-                        })
-                    }
-                }
-                height_layers.push(layer_tiles.into_boxed_slice());
-            }
-            
-            // Convert vector to boxed slice for memory efficiency and immutability
-            Ok(QuadTree {
-                tiles: first_layer_tiles.into_boxed_slice(),
-                height_layers,
-                sorted_heights,
-                height_0_colors,
-                filtered_heights,
-                width,
-                height,
-            })
-        } else {
-            // Original behavior when gen_full_layers_above_height is 0
-            // Pre-allocate vector with exact capacity for efficiency
-            let mut tiles = Vec::with_capacity((width * height) as usize);
-
-            // Create one tile for each pixel in the heightmap
-            // Using i32 for loop variables to allow negative values in neighbor calculations
-            for x in 0..width as i32 {
-                for y in 0..height as i32 {
-                    tiles.push(Tile {
-                        // Calculate unique index for this tile in the flattened grid
-                        index: (x + y * height as i32) as usize,
-                        // Store the center coordinates of this tile
-                        center: (x as u32, y as u32),
-                        // Collect height values from all valid neighboring pixels
-                        // These are used later to calculate relative height differences
-                        neighbors: vec![(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
-                            .into_iter()
-                            // Filter out neighbors that are outside the image bounds
-                            .filter(|(x, y)| {
-                                *x >= 0 && *x < width as i32 && *y >= 0 && *y < height as i32
-                            })
-                            // Get height value for each valid neighbor
-                            .map(|(x, y)| heightmap.at(x as u32, y as u32))
-                            // Collect unique height values into a HashSet
-                            .fold(HashSet::new(), |mut set, height| {
-                                set.insert(height);
-                                set
-                            }),
-                        // Start with size 1x1 (single pixel)
-                        size: (1, 1),
-                        // Get color from colormap at this position
-                        color: colormap.at(x as u32, y as u32),
-                        // Get elevation from heightmap at this position
-                        height: heightmap.at(x as u32, y as u32),
-                        // Initially no parent (not merged)
-                        parent: None,
-                    })
-                }
+        // Add only the highest height at or below the threshold
+        if let Some(&highest_at_or_below) = heights_at_or_below.last() {
+            if let Some(&color) = all_heights.get(&highest_at_or_below) {
+                filtered_heights.insert(highest_at_or_below, color);
             }
-
-            // Convert vector to boxed slice for memory efficiency and immutability
-            Ok(QuadTree {
-                tiles: tiles.into_boxed_slice(),
-                height_layers: Vec::new(),
-                sorted_heights: Vec::new(),
-                height_0_colors: HashSet::new(),
-                filtered_heights: HashMap::new(),
-                width,
-                height,
-            })
         }
-    }
-
-    /// Perform quadtree optimization at a specific level
-    /// Attempts to merge 2x2 groups of tiles at the given scale level
-    /// 
-    /// # Arguments
-    /// * `level` - The scale level (0 = 1x1, 1 = 2x2, 2 = 4x4, etc.)
-    /// 
-    /// # Returns
-    /// * Number of tiles that were successfully merged
-    pub fn quad_optimize_level(&mut self, level: u32) -> usize {
-        let mut count = 0;
-
-        // Calculate spacing and step amounts for this level
-        let space = 2_u32.pow(level);        // Size of tiles at this level (1, 2, 4, 8, ...)
-        let step_amt = space as usize * 2;   // Step between tile groups (skip already merged tiles)
-
-        // Optimize main tiles vector
-        count += Self::quad_optimize_tiles(&mut self.tiles, self.width, self.height, space, step_amt);
 
-        // Optimize each height layer if they exist
-        for layer in &mut self.height_layers {
-            count += Self::quad_optimize_tiles(layer, self.width, self.height, space, step_amt);
-        }
+        // The map's keys are already sorted
+        let sorted_heights: Vec<u32> = filtered_heights.keys().cloned().collect();
 
-        count
+        Ok(LayerHeights {
+            sorted_heights,
+            height_0_colors,
+            filtered_heights,
+        })
     }
 
-    /// Helper function to perform quadtree optimization on a specific tiles array
-    /// 
-    /// # Arguments
-    /// * `tiles` - The tiles array to optimize (either main tiles or a height layer)
-    /// * `width` - Width of the tile grid
-    /// * `height` - Height of the tile grid
-    /// * `space` - Size of tiles at this level
-    /// * `step_amt` - Step between tile groups
-    /// 
-    /// # Returns
-    /// * Number of tiles that were successfully merged in this array
-    fn quad_optimize_tiles(tiles: &mut [Tile], width: u32, height: u32, space: u32, step_amt: usize) -> usize {
-        let mut count = 0;
-
-        // Iterate through the grid in steps, checking 2x2 tile groups for merging
-        for x in (0..width - space).step_by(step_amt) {
-            for y in (0..height - space).step_by(step_amt) {
-                // Use complex array slicing to get mutable references to 4 adjacent tiles
-                // This is needed because Rust's borrow checker doesn't allow multiple
-                // mutable references to the same array normally
-                
-                // Split the tiles array vertically at x+space boundary
-                let (left, right) = tiles
-                    .split_at_mut(((x + space) * height) as usize);
-
-                // Split left and right columns horizontally at y+space boundary  
-                let (top_left, bottom_left) =
-                    left.split_at_mut((y + space + x * height) as usize);
-                let (top_right, bottom_right) = right.split_at_mut((y + space) as usize);
-
-                // Extract the specific tile we want from each slice
-                let top_left = &mut top_left[(y + x * height) as usize];
-                let bottom_left = &mut bottom_left[0];
-                let top_right = &mut top_right[y as usize];
-                let bottom_right = &mut bottom_right[0];
-
-                // Check if all 4 tiles can be merged together
-                // They must all be the same size and have matching properties
-                if top_left.size.0 != space
-                    || !top_left.similar_quad(top_right)
-                    || !top_left.similar_quad(bottom_left)
-                    || !top_left.similar_quad(bottom_right)
-                {
-                    continue; // Skip this group if tiles can't be merged
-                }
+    /// Number of tile layers a quadtree built with these heights has, counting the main tiles
+    pub(crate) fn count(&self) -> u64 {
+        max(self.sorted_heights.len(), 1) as u64
+    }
+}
 
-                // Count 3 tiles eliminated (4 tiles become 1, net reduction of 3)
-                count += 3;
+impl TileLayer {
+    /// Add a 1x1 tile at the end of the current column
+    fn push(&mut self, row: u32, height: u32, color: [u8; 4]) {
+        self.row.push(row);
+        self.size.push((1, 1));
+        self.color.push(color);
+        self.height.push(height);
+        self.merged.push(false);
+    }
 
-                // Perform the merge, combining all 4 tiles into the top-left tile
-                top_left.merge_quad(top_right, bottom_left, bottom_right);
-            }
+    /// Append the columns of another layer after this layer's columns
+    fn append(&mut self, mut other: TileLayer) {
+        let offset = self.row.len();
+        if self.col_start.is_empty() {
+            self.col_start.push(0);
         }
-
-        count
+        self.col_start.extend(other.col_start.iter().skip(1).map(|i| i + offset));
+        self.row.append(&mut other.row);
+        self.size.append(&mut other.size);
+        self.color.append(&mut other.color);
+        self.height.append(&mut other.height);
+        self.merged.append(&mut other.merged);
     }
 
-    /// Merge tiles that are arranged in a horizontal or vertical line
-    /// This optimization reduces brick count by combining adjacent similar tiles
-    /// 
+    /// Split this layer into non-overlapping strips of columns
+    ///
     /// # Arguments
-    /// * `tiles` - The tiles array to operate on
-    /// * `start_i` - Index of the first tile in the line (becomes the parent)
-    /// * `children` - Vector of indices of tiles to merge into the first tile
-    fn merge_line(tiles: &mut [Tile], start_i: usize, children: Vec<usize>) {
-        // Early return if no tiles to merge
-        if children.is_empty() {
-            return;
-        }
-
-        // Collect neighbor sets from all tiles being merged
-        let mut new_neighbors = vec![];
-
-        // Determine if this is a vertical or horizontal line merge
-        // Vertical: same X coordinate (tiles stacked vertically)
-        // Horizontal: same Y coordinate (tiles arranged horizontally) 
-        let is_vertical = tiles[children[0]].center.0 == tiles[start_i].center.0;
-
-        // Process each child tile: set parent and accumulate size
-        let new_size = children.iter().fold(0, |sum, &i| {
-            let t = &mut tiles[i];
-            // Mark this tile as merged into the parent
-            t.parent = Some(start_i);
-            // Collect neighbor heights for the parent tile
-            new_neighbors.push(t.neighbors.clone());
-
-            // Add this tile's size to the total in the merge direction
-            sum + if is_vertical { t.size.1 } else { t.size.0 }
-        });
+    /// * `strips` - (first column, width) of each strip, covering every column in order
+    /// * `rows` - Number of rows in the grid
+    fn strips(&mut self, strips: &[(u32, u32)], rows: u32) -> Vec<TileStrip<'_>> {
+        let TileLayer { col_start, row, color, height, .. } = self;
+        let mut size = &mut self.size[..];
+        let mut merged = &mut self.merged[..];
+
+        let mut tile_strips = Vec::with_capacity(strips.len());
+        for &(x0, w) in strips {
+            let col_start = &col_start[x0 as usize..=(x0 + w) as usize];
+            let (from, to) = (col_start[0], col_start[w as usize]);
+            let len = to - from;
+
+            // Peel this strip's tiles off the front of each mutable array
+            let (strip_size, rest) = mem::take(&mut size).split_at_mut(len);
+            size = rest;
+            let (strip_merged, rest) = mem::take(&mut merged).split_at_mut(len);
+            merged = rest;
+
+            tile_strips.push(TileStrip {
+                rows,
+                width: w,
+                col_start,
+                row: &row[from..to],
+                color: &color[from..to],
+                height: &height[from..to],
+                size: strip_size,
+                merged: strip_merged,
+            });
+        }
+        tile_strips
+    }
+}
 
-        // Update the parent tile with merged information
-        let start = &mut tiles[start_i];
+impl TileStrip<'_> {
+    /// Range of indices (within this strip) of the tiles in a column of this strip
+    fn column(&self, x: u32) -> std::ops::Range<usize> {
+        let base = self.col_start[0];
+        self.col_start[x as usize] - base..self.col_start[x as usize + 1] - base
+    }
 
-        // Combine neighbor height sets from all merged tiles
-        for n in new_neighbors {
-            start.neighbors.extend(&n);
+    /// Find the index of the tile at (x, y), with x relative to the start of the strip
+    /// Returns None if there is no tile stored there
+    fn find(&self, x: u32, y: u32) -> Option<usize> {
+        let column = self.column(x);
+        if column.len() == self.rows as usize {
+            // Full columns have a tile in every row
+            Some(column.start + y as usize)
+        } else {
+            // Sparse columns are sorted by row
+            let start = column.start;
+            self.row[column].binary_search(&y).ok().map(|i| start + i)
         }
+    }
 
-        // Increase the parent tile's size in the appropriate dimension
-        if is_vertical {
-            start.size.1 += new_size  // Extend height for vertical merge
-        } else {
-            start.size.0 += new_size  // Extend width for horizontal merge
+    /// Check if two tiles have the same color and height and neither has been merged
+    fn similar(&self, a: usize, b: usize) -> bool {
+        self.color[a] == self.color[b]        // Same RGBA color
+            && self.height[a] == self.height[b] // Same elevation
+            && !self.merged[a]                // This tile not already merged
+            && !self.merged[b]                // Other tile not already merged
+    }
+
+    /// Mark tiles as merged into another tile, which has already been resized to cover them
+    fn absorb(&mut self, tiles: &[usize]) {
+        for &i in tiles {
+            self.merged[i] = true;
         }
     }
 
-    /// Optimize the quadtree by merging tiles arranged in lines
-    /// This finds and merges adjacent tiles with similar properties in horizontal/vertical lines
-    /// 
-    /// # Arguments  
-    /// * `tile_scale` - Scale factor for tile sizing (used to enforce size limits)
-    /// 
+    /// Merge 2x2 groups of tiles of the given size into their top-left tile
+    /// Groups on the right and bottom edges of the map are clipped to the map, so edge tiles
+    /// (narrower or shorter than `space`) merge just like the rest when the map size isn't a power of two
+    ///
+    /// # Arguments
+    /// * `space` - Size of tiles at this level
+    /// * `step_amt` - Step between tile groups
+    ///
     /// # Returns
-    /// * Number of tiles that were merged
-    pub fn line_optimize(&mut self, tile_scale: u32) -> usize {
+    /// * Number of tiles that were successfully merged in this strip
+    fn quad_optimize(&mut self, space: u32, step_amt: usize) -> usize {
         let mut count = 0;
+        let step = step_amt as u32;
+        // Size of a tile at this level starting at (x, y), clipped to the edges of the map
+        let clipped = |strip: &Self, x: u32, y: u32, len: u32| {
+            (min(len, strip.width - x) as u16, min(len, strip.rows - y) as u16)
+        };
+
+        // Check the tiles that could be the top-left of a 2x2 group
+        for x in (0..self.width).step_by(step_amt) {
+            for top_left in self.column(x) {
+                let y = self.row[top_left];
+                if !y.is_multiple_of(step) || self.size[top_left] != clipped(self, x, y, space) {
+                    continue;
+                }
+
+                // Every tile of the group that's inside the map must exist to be merged
+                let corners: Vec<(u32, u32)> = [(x + space, y), (x, y + space), (x + space, y + space)]
+                    .iter()
+                    .copied()
+                    .filter(|&(tx, ty)| tx < self.width && ty < self.rows)
+                    .collect();
+
+                // They must all fill their part of the group and have matching properties
+                let tiles: Vec<usize> = corners
+                    .iter()
+                    .map_while(|&(tx, ty)| {
+                        self.find(tx, ty)
+                            .filter(|&i| self.size[i] == clipped(self, tx, ty, space) && self.similar(top_left, i))
+                    })
+                    .collect();
+                if tiles.len() != corners.len() {
+                    continue; // Skip this group if tiles can't be merged
+                }
 
-        // Optimize main tiles vector
-        count += Self::line_optimize_tiles(&mut self.tiles, self.width, self.height, tile_scale);
+                // Count the tiles eliminated (3 for a full group of 4)
+                count += tiles.len();
 
-        // Optimize each height layer if they exist
-        for layer in &mut self.height_layers {
-            count += Self::line_optimize_tiles(layer, self.width, self.height, tile_scale);
+                // Grow to fill the whole group, clipped to the map
+                self.size[top_left] = clipped(self, x, y, space * 2);
+                self.absorb(&tiles);
+            }
         }
 
         count
     }
 
-    /// Helper function to perform line optimization on a specific tiles array
-    /// 
+    /// Merge tiles that are arranged in horizontal or vertical lines
+    ///
     /// # Arguments
-    /// * `tiles` - The tiles array to optimize (either main tiles or a height layer)
-    /// * `width` - Width of the tile grid
-    /// * `height` - Height of the tile grid
-    /// * `tile_scale` - Scale factor for tile sizing (used to enforce size limits)
-    /// 
+    /// * `max_tiles` - Longest side of a brick in tiles (from the asset's size limits)
+    ///
     /// # Returns
-    /// * Number of tiles that were merged in this array
-    fn line_optimize_tiles(tiles: &mut [Tile], width: u32, height: u32, tile_scale: u32) -> usize {
+    /// * Number of tiles that were merged in this strip
+    fn line_optimize(&mut self, max_tiles: u32) -> usize {
         let mut count = 0;
-        // Check every tile in the grid as a potential start of a line merge
-        for x in 0..width {
-            for y in 0..height {
-                let start_i = (y + x * height) as usize;  // Calculate index inline
-                let start = &tiles[start_i];
+        // Check every tile in the strip as a potential start of a line merge
+        for x in 0..self.width {
+            for start in self.column(x) {
                 // Skip tiles that have already been merged into other tiles
-                if start.parent.is_some() {
+                if self.merged[start] {
                     continue;
                 }
+                let y = self.row[start];
 
                 // Get the current tile size for calculating merge boundaries
-                let shift = start.size;
-                let mut sx = shift.0;        // Current width for horizontal merging
+                let shift = self.size[start];
+                let mut sx = shift.0 as u32;  // Current width for horizontal merging
                 let mut horiz_tiles = vec![]; // Tiles to merge horizontally
-                let mut sy = shift.1;        // Current height for vertical merging  
-                let mut vert_tiles = vec![]; // Tiles to merge vertically
+                let mut sy = shift.1 as u32;  // Current height for vertical merging
+                let mut vert_tiles = vec![];  // Tiles to merge vertically
 
                 // Find the longest possible horizontal merge from this position
-                while x + sx < width {
-                    let i = (y + (x + sx) * height) as usize;
-                    let t = &tiles[i];
+                while x + sx < self.width {
+                    let i = match self.find(x + sx, y) {
+                        Some(i) => i,
+                        None => break,
+                    };
+                    let size = self.size[i];
                     // Stop if the resulting brick would be too large or tiles aren't similar
-                    if (sx + t.size.0) * tile_scale > 500 || !start.similar_line(t) {
+                    if sx + size.0 as u32 > max_tiles
+                        || size.1 != shift.1
+                        || !self.similar(start, i)
+                    {
                         break;
                     }
                     horiz_tiles.push(i);
-                    sx += t.size.0;  // Extend the total width
+                    sx += size.0 as u32;  // Extend the total width
                 }
 
                 // Find the longest possible vertical merge from this position
-                while y + sy < height {
-                    let i = (y + sy + x * height) as usize;
-                    let t = &tiles[i];
+                while y + sy < self.rows {
+                    let i = match self.find(x, y + sy) {
+                        Some(i) => i,
+                        None => break,
+                    };
+                    let size = self.size[i];
                     // Stop if the resulting brick would be too large or tiles aren't similar
-                    if (sy + t.size.1) * tile_scale > 500 || !start.similar_line(t) {
+                    if sy + size.1 as u32 > max_tiles
+                        || size.0 != shift.0
+                        || !self.similar(start, i)
+                    {
                         break;
                     }
                     vert_tiles.push(i);
-                    sy += t.size.1;  // Extend the total height
+                    sy += size.1 as u32;  // Extend the total height
                 }
 
-                // Count the number of tiles we'll merge (choose the longer line)
-                count += max(horiz_tiles.len(), vert_tiles.len());
-
-                // Perform the merge for whichever direction gives more reduction
-                Self::merge_line(
-                    tiles,
-                    start_i,
-                    if horiz_tiles.len() > vert_tiles.len() {
-                        horiz_tiles  // Merge horizontally if it's longer
-                    } else {
-                        vert_tiles   // Otherwise merge vertically
-                    },
-                );
+                // Merge whichever direction gives more reduction
+                if horiz_tiles.len() > vert_tiles.len() {
+                    count += horiz_tiles.len();
+                    self.size[start].0 = sx as u16;  // Extend width for horizontal merge
+                    self.absorb(&horiz_tiles);
+                } else {
+                    count += vert_tiles.len();
+                    self.size[start].1 = sy as u16;  // Extend height for vertical merge
+                    self.absorb(&vert_tiles);
+                }
             }
         }
 
         count
     }
 
-    /// Convert the optimized quadtree into a vector of Brickadia bricks
-    /// This is the final step that creates the actual brick objects for the save file
-    /// 
+    /// Check that every tile in a block of columns and rows can be merged into the tile at `start`
+    ///
     /// # Arguments
-    /// * `options` - Generation options controlling brick properties
-    /// 
-    /// # Returns
-    /// * Vector of Brick objects ready for writing to a save file
-    pub fn into_bricks(&self, options: GenOptions) -> Vec<Brick> {
-        let mut all_bricks = Vec::new();
-        
-        // Process main tiles vector
-        let main_bricks = Self::tiles_to_bricks(&self.tiles, &options, 0);
-        all_bricks.extend(main_bricks);
-        
-        // Process each height layer vector
-        for (i, layer) in self.height_layers.iter().enumerate() {
-            // Calculate height_adjustment based on height_0_colors logic
-            // Layer i corresponds to sorted_heights[i+1]
-            let height_adjustment = if self.sorted_heights.is_empty() {
-                0
-            } else {
-                let current_height_index = i;
-                if current_height_index < self.sorted_heights.len() {
-                    let current_height = self.sorted_heights[current_height_index];
-                    // Check if height_0_colors contains the color for this height
-                    if let Some(&color) = self.filtered_heights.get(&current_height) {
-                        if self.height_0_colors.contains(&color) {
-                            // If height_0_colors contains the color for sorted_heights[i], use sorted_heights[i-1]
-                            if current_height_index > 0 {
-                                self.sorted_heights[current_height_index - 1]
-                            } else {
-                                0
-                            }
-                        } else {
-                            // Use the current height as before
-                            self.sorted_heights[i]
-                        }
-                    } else {
-                        // Fallback to previous behavior
-                        self.sorted_heights[i]
-                    }
-                } else {
-                    self.sorted_heights[i]
-                }
-            };
-            let layer_bricks = Self::tiles_to_bricks(layer, &options, height_adjustment);
-            all_bricks.extend(layer_bricks);
+    /// * `start` - Index of the top-left tile of the rectangle
+    /// * `x` - Column of the top-left tile
+    /// * `columns` - Columns to check, relative to `x`
+    /// * `rows` - Rows to check, relative to the top-left tile
+    fn mergeable(
+        &self,
+        start: usize,
+        x: u32,
+        columns: std::ops::Range<u32>,
+        rows: std::ops::Range<u32>,
+    ) -> bool {
+        let y = self.row[start];
+        if x + columns.end > self.width || y + rows.end > self.rows {
+            return false;
         }
-        
-        all_bricks
+        columns.into_iter().all(|dx| {
+            rows.clone().all(|dy| match self.find(x + dx, y + dy) {
+                Some(i) => self.similar(start, i),
+                None => false,
+            })
+        })
     }
-    
-    /// Helper function to convert a tiles vector into bricks
-    /// 
+
+    /// Merge regions of equal height and color into maximal rectangles
+    /// Each unmerged tile, in column order, grows the larger of a column-first and a
+    /// row-first rectangle, capped at the asset's largest brick
+    /// Expects tiles that haven't been merged yet (all 1x1)
+    ///
     /// # Arguments
-    /// * `tiles` - The tiles vector to convert
-    /// * `options` - Generation options controlling brick properties
-    /// * `height_adjustment` - Value to adjust the height of bricks by
-    /// 
+    /// * `max_len` - Longest side of a brick in tiles (from the asset's size limits)
+    ///
     /// # Returns
-    /// * Vector of Brick objects created from the tiles
-    fn tiles_to_bricks(tiles: &[Tile], options: &GenOptions, height_adjustment: u32) -> Vec<Brick> {
-        let pos_adjust = if height_adjustment == 0 {
-            0
-        } else {
-            4
-        };
+    /// * Number of tiles that were merged in this strip
+    fn greedy_optimize(&mut self, max_len: u32) -> usize {
+        let mut count = 0;
 
-        tiles
-            .iter()
-            .flat_map(|t| {
-                // Skip tiles that have been merged or should be culled
-                if t.parent.is_some()  // Skip merged tiles
-                    || options.cull && (t.color[3] == 0)  // Skip transparent tiles if culling enabled
+        for x in 0..self.width {
+            for start in self.column(x) {
+                if self.merged[start] {
+                    continue;
+                }
+
+                // Column first: as tall as possible, then widen while every row still matches
+                let mut column_first = (1, 1);
+                while column_first.1 < max_len
+                    && self.mergeable(start, x, 0..1, column_first.1..column_first.1 + 1)
+                {
+                    column_first.1 += 1;
+                }
+                while column_first.0 < max_len
+                    && self.mergeable(start, x, column_first.0..column_first.0 + 1, 0..column_first.1)
+                {
+                    column_first.0 += 1;
+                }
+
+                // Row first: as wide as possible, then lengthen while every column still matches
+                let mut row_first = (1, 1);
+                while row_first.0 < max_len && self.mergeable(start, x, row_first.0..row_first.0 + 1, 0..1) {
+                    row_first.0 += 1;
+                }
+                while row_first.1 < max_len
+                    && self.mergeable(start, x, 0..row_first.0, row_first.1..row_first.1 + 1)
                 {
-                    return vec![];
+                    row_first.1 += 1;
                 }
 
-                // Calculate the Z position (vertical placement) of this brick
-                let mut z = if t.height == 0 {
-                    0
+                let (w, h) = if row_first.0 * row_first.1 > column_first.0 * column_first.1 {
+                    row_first
                 } else {
-                    (options.scale * t.height) as i32
+                    column_first
                 };
-
-                // Calculate brick height based on height difference with neighbors
-                // This creates natural-looking terrain with varying brick heights
-                let raw_height = max(
-                    t.height as i32 - height_adjustment as i32 + 1,
-                    2,  // Minimum height of 2 units
-                );
-                // Apply scaling and ensure minimum height
-                let mut desired_height = max(raw_height * options.scale as i32 / 2, 2);
-
-                // Snap brick positions and heights to the Brickadia grid if enabled
-                // Brickadia uses a 4-unit grid system for precise brick alignment
-                if options.snap {
-                    z += 4 - z % 4;                    // Round Z position up to next grid line
-                    desired_height += 4 - desired_height % 4;  // Round height up to grid multiple
+                if w * h == 1 {
+                    continue;
                 }
 
-                let mut bricks = vec![];
-                // Create multiple bricks if needed to reach the desired height
-                // Brickadia has a maximum brick height of 250 units
-                while desired_height > 0 {
-                    // Calculate height for this individual brick
-
-                    // Enforce minimum and maximum height constraints
-                    let height =
-                        min(max(desired_height, if options.stud { 5 } else { 2 }), 250) as u32;
-                    // Ensure height is a multiple of the minimum unit (5 for studs, 2 for regular)
-                    let height = height + height % (if options.stud { 5 } else { 2 });
-
-                    // Create a new brick with the calculated properties
-                    bricks.push(Brick {
-                        // Reference to the brick asset type (cube, tile, micro, stud)
-                        asset_name_index: options.asset,
-                        // Set brick dimensions (width, depth, height)
-                        size: Size::Procedural(
-                            t.size.0 * options.size,  // Width based on tile size
-                            t.size.1 * options.size,  // Depth based on tile size
-                            // For micro brick images, use uniform cube size
-                            if options.img && options.micro {
-                                options.size
-                            } else {
-                                height - pos_adjust as u32  // Otherwise use calculated height
-                            },
-                        ),
-                        // Calculate brick position in 3D space
-                        position: (
-                            ((t.center.0 * 2 + t.size.0) * options.size) as i32,  // X position (centered on tile)
-                            ((t.center.1 * 2 + t.size.1) * options.size) as i32,  // Y position (centered on tile)
-                            z - height as i32 + pos_adjust as i32 + 4 as i32,  // Z position (bottom of brick at terrain level)
-                        ),
-                        // Set collision properties based on options
-                        collision: Collision {
-                            player: !options.nocollide,      // Player collision enabled unless disabled
-                            weapon: !options.nocollide,      // Weapon collision enabled unless disabled
-                            interaction: !options.nocollide, // Interaction enabled unless disabled
-                            tool: true,                       // Always allow tool interaction
-                        },
-                        // Set brick color from the colormap
-                        color: BrickColor::Unique(Color {
-                            r: t.color[0],  // Red channel
-                            g: t.color[1],  // Green channel
-                            b: t.color[2],  // Blue channel  
-                            a: t.color[3],  // Alpha (transparency)
-                        }),
-                        owner_index: 1,  // Reference to owner in the save file
-                        material_intensity: 0,  // No special material effects
-                        material_index: u32::from(options.glow),  // Glow material if enabled
-                        ..Default::default()  // Use default values for remaining fields
-                    });
-
-                    // Update for next brick iteration
-                    desired_height -= height as i32;  // Reduce remaining height needed
-                    z -= height as i32 * 2;          // Move Z position down for next brick
-                }
-                bricks  // Return all bricks created for this tile
-            })
-            .collect()  // Flatten all brick vectors into a single vector
+                // Every tile of the rectangle but the top-left one is absorbed
+                let y = self.row[start];
+                let tiles: Vec<usize> = (0..w)
+                    .flat_map(|dx| (0..h).map(move |dy| (dx, dy)))
+                    .skip(1)
+                    .filter_map(|(dx, dy)| self.find(x + dx, y + dy))
+                    .collect();
+                count += tiles.len();
+                self.size[start] = (w as u16, h as u16);
+                self.absorb(&tiles);
+            }
+        }
+
+        count
     }
 }
 
-/// Generate an optimized brick heightmap with quadtree and line optimizations
-/// This is the main function that orchestrates the entire brick generation process
-/// 
-/// # Arguments
-/// * `heightmap` - Source of elevation data
-/// * `colormap` - Source of color data  
-/// * `options` - Configuration options for brick generation
-/// * `progress_f` - Callback function for progress reporting (returns true to continue)
-/// 
-/// # Returns
-/// * `Ok(Vec<Brick>)` - Vector of optimized bricks ready for save file
-/// * `Err(String)` - Error message if generation fails or is cancelled
-pub fn gen_opt_heightmap<F: Fn(f32) -> bool>(
-    heightmap: &dyn Heightmap,
-    colormap: &dyn Colormap,
-    options: GenOptions,
-    progress_f: F,
-) -> Result<Vec<Brick>, String> {
-    // Define a macro for progress reporting with early termination
-    // This allows the generation to be cancelled by returning false from progress_f
-    macro_rules! progress {
-        ($e:expr) => {
-            if !progress_f($e) {
-                return Err("Stopped by user".to_string());
-            }
-        };
+impl QuadTree {
+    /// Create a new quadtree from heightmap and colormap data
+    /// Initializes a grid of tiles, one per pixel in the input images
+    /// Height layers only get tiles where the terrain reaches the layer
+    /// Tiles are built in column strips spread over `threads` threads
+    ///
+    /// # Arguments
+    /// * `heightmap` - Source of elevation data
+    /// * `colormap` - Source of color data
+    /// * `gen_full_layers_above_height` - Height threshold above which to generate full layers
+    /// * `threads` - Number of threads to use (0 = one per CPU core)
+    ///
+    /// # Returns
+    /// * `Ok(QuadTree)` if images have matching dimensions
+    /// * `Err(GenError)` if dimensions don't match
+    pub fn new(
+        heightmap: &(dyn Heightmap + Sync),
+        colormap: &(dyn Colormap + Sync),
+        gen_full_layers_above_height: u32,
+        threads: usize,
+    ) -> Result<Self, GenError> {
+        let layers = LayerHeights::new(heightmap, colormap, gen_full_layers_above_height, threads)?;
+        Self::with_layers(heightmap, colormap, layers, threads)
     }
-    progress!(0.0);  // Report 0% progress at start
 
-    info!("Building initial quadtree");
-    let (width, height) = heightmap.size();
-    let area = width * height;  // Total number of pixels/potential bricks
-    let mut quad = QuadTree::new(heightmap, colormap, options.gen_full_layers_above_height)?;  // Create initial 1:1 tile grid
-    progress!(0.2);  // Report 20% progress after quadtree initialization
+    /// Create a new quadtree using layer heights that were already found
+    /// Used to build each block of a map with the layers of the whole map
+    ///
+    /// # Arguments
+    /// * `heightmap` - Source of elevation data
+    /// * `colormap` - Source of color data
+    /// * `layers` - Heights that get full layers
+    /// * `threads` - Number of threads to use (0 = one per CPU core)
+    ///
+    /// # Returns
+    /// * `Ok(QuadTree)` if images have matching dimensions
+    /// * `Err(GenError)` if dimensions don't match
+    pub fn with_layers(
+        heightmap: &(dyn Heightmap + Sync),
+        colormap: &(dyn Colormap + Sync),
+        layers: LayerHeights,
+        threads: usize,
+    ) -> Result<Self, GenError> {
+        let mut work = WorkProgress::new(ProgressScope::new(&NoProgress), 0);
+        Self::build(heightmap, colormap, layers, threads, &mut work)
+    }
 
-    // Determine progress tracking based on whether quadtree optimization is enabled
-    let (prog_offset, prog_scale) = if options.quadtree {
-        info!("Optimizing quadtree");
-        let mut scale = 0;  // Start with 1x1 tiles, scale up to 2x2, 4x4, etc.
+    /// Create a new quadtree using layer heights that were already found, reporting each strip
+    /// of pixels read for every layer
+    /// See `QuadTree::with_layers` for details
+    pub(crate) fn build(
+        heightmap: &(dyn Heightmap + Sync),
+        colormap: &(dyn Colormap + Sync),
+        layers: LayerHeights,
+        threads: usize,
+        work: &mut WorkProgress,
+    ) -> Result<Self, GenError> {
+        let (width, height) = heightmap.size();
+        let threads = resolve_threads(threads);
 
-        // Perform quadtree optimization at increasing scales
-        // Stop when bricks would exceed Brickadia's 500-unit size limit
-        while 2_i32.pow(scale + 1) * (options.size as i32) < 500 {
-            // Report progress proportional to scale level
-            progress!(0.2 + 0.5 * (scale as f32 / (500.0 / (options.size as f32)).log2()));
-            let count = quad.quad_optimize_level(scale);
-            if count == 0 {
-                break;  // No more tiles merged at this scale
-            } else {
-                info!("  Removed {:?} {}x bricks", count, 2_i32.pow(scale));
+        // Validate that both input images have matching dimensions
+        if colormap.size() != heightmap.size() {
+            return Err(GenError::DimensionMismatch {
+                expected: heightmap.size(),
+                found: colormap.size(),
+            });
+        }
+
+        let strips = build_strips(width, threads);
+
+        // Every layer reads every pixel
+        let total = width as u64 * height as u64 * layers.count();
+        let mut read = 0;
+        let mut on_strip = |pixels| {
+            read += pixels;
+            work.processed(pixels);
+            work.report(Phase::BuildingTree, read, total)
+        };
+
+        if let Some(&min_filtered_height) = layers.sorted_heights.first() {
+            // Lowest of the filtered heights, used for capping the first layer
+            let filtered_heights = &layers.filtered_heights;
+
+            // Create tiles for the first layer (capped heights)
+            let first_layer_tiles = build_tiles(heightmap, &strips, threads, &mut on_strip, |x, y, original_height| {
+                // For first layer: keep original height if it's <= min_filtered_height,
+                // otherwise cap it to min_filtered_height
+                Some(if original_height >= min_filtered_height {
+                    (min_filtered_height, filtered_heights[&min_filtered_height])
+                } else {
+                    (original_height, colormap.at(x, y))
+                })
+            })?;
+
+            // Create additional layers for remaining heights
+            let mut height_layers = Vec::new();
+            for &layer_height in &layers.sorted_heights[1..] {  // Skip first height as it's already in main tiles
+                let layer_color = filtered_heights[&layer_height];  // Get the stored color for this height
+                let is_lake_layer = layers.height_0_colors.contains(&layer_color);
+
+                // Lake layers only cover pixels of the lake's color at exactly its height,
+                // other layers cover every pixel at or above their height
+                // Pixels the layer doesn't cover get no tile at all
+                let layer_tiles = build_tiles(heightmap, &strips, threads, &mut on_strip, |x, y, original_height| {
+                    let covered = if is_lake_layer {
+                        original_height == layer_height && colormap.at(x, y) == layer_color
+                    } else {
+                        original_height >= layer_height
+                    };
+
+                    // Use the color that was stored for this height instead of querying colormap
+                    if covered {
+                        Some((layer_height, layer_color))
+                    } else {
+                        None
+                    }
+                })?;
+                height_layers.push(layer_tiles);
+            }
+
+            Ok(QuadTree {
+                tiles: first_layer_tiles,
+                height_layers,
+                layers,
+                width,
+                height,
+                threads,
+            })
+        } else {
+            // Original behavior when gen_full_layers_above_height is 0
+            // Each tile gets the height and color of its pixel
+            let tiles = build_tiles(heightmap, &strips, threads, &mut on_strip, |x, y, original_height| {
+                Some((original_height, colormap.at(x, y)))
+            })?;
+
+            Ok(QuadTree {
+                tiles,
+                height_layers: Vec::new(),
+                layers,
+                width,
+                height,
+                threads,
+            })
+        }
+    }
+
+    /// Perform quadtree optimization at a specific level
+    /// Attempts to merge 2x2 groups of tiles at the given scale level
+    ///
+    /// # Arguments
+    /// * `level` - The scale level (0 = 1x1, 1 = 2x2, 2 = 4x4, etc.)
+    ///
+    /// # Returns
+    /// * Number of tiles that were successfully merged
+    pub fn quad_optimize_level(&mut self, level: u32) -> usize {
+        // Calculate spacing and step amounts for this level
+        let space = 2_u32.pow(level);        // Size of tiles at this level (1, 2, 4, 8, ...)
+        let step_amt = space as usize * 2;   // Step between tile groups (skip already merged tiles)
+
+        // Quads never cross a strip whose width is a multiple of the step,
+        // so the strips can be merged independently with the same result
+        let step = step_amt as u32;
+        let strip = max(self.width.div_ceil(self.threads as u32 * 4).div_ceil(step), 1) * step;
+        let strips = column_strips(self.width, strip, strip);
+
+        // Optimize the main tiles and each height layer if they exist
+        let threads = self.threads;
+        run_jobs(self.strip_jobs(&strips), threads, |mut strip| {
+            strip.quad_optimize(space, step_amt)
+        })
+        .into_iter()
+        .sum()
+    }
+
+    /// Optimize the quadtree by merging tiles arranged in lines
+    /// This finds and merges adjacent tiles with similar properties in horizontal/vertical lines
+    ///
+    /// Columns are split into strips that are optimized in parallel, once with the strips
+    /// offset by half a strip so merges across the first seams get a chance too.
+    /// Half a strip is wider than the largest brick, so when this returns 0 no merge is left anywhere.
+    /// The strips don't depend on the thread count, so the output is the same for any number of threads.
+    ///
+    /// # Arguments
+    /// * `max_tiles` - Longest side of a brick in tiles, see `GenOptions::max_tiles`
+    ///
+    /// # Returns
+    /// * Number of tiles that were merged
+    pub fn line_optimize(&mut self, max_tiles: u32) -> usize {
+        let half = max(MIN_LINE_STRIP, (max_tiles + 2).next_power_of_two());
+        let threads = self.threads;
+
+        [2 * half, half]
+            .iter()
+            .map(|&first| {
+                // Optimize the main tiles and each height layer if they exist
+                let strips = column_strips(self.width, first, 2 * half);
+                run_jobs(self.strip_jobs(&strips), threads, |mut strip| {
+                    strip.line_optimize(max_tiles)
+                })
+                .into_iter()
+                .sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// Merge regions of equal height and color into maximal rectangles
+    /// Rectangles can be any size up to the brick size limit, unlike quadtree merges
+    /// Each layer is merged as a single strip, so layers run in parallel but columns don't
+    ///
+    /// # Arguments
+    /// * `max_tiles` - Longest side of a brick in tiles, see `GenOptions::max_tiles`
+    ///
+    /// # Returns
+    /// * Number of tiles that were merged
+    pub fn greedy_optimize(&mut self, max_tiles: u32) -> usize {
+        let threads = self.threads;
+        let strips = [(0, self.width)];
+        run_jobs(self.strip_jobs(&strips), threads, |mut strip| {
+            strip.greedy_optimize(max_tiles)
+        })
+        .into_iter()
+        .sum()
+    }
+
+    /// Number of tiles in the main tiles and every height layer, merged or not
+    fn tile_count(&self) -> u64 {
+        std::iter::once(&self.tiles)
+            .chain(&self.height_layers)
+            .map(|layer| layer.row.len() as u64)
+            .sum()
+    }
+
+    /// Split the main tiles and every height layer into column strips
+    ///
+    /// # Arguments
+    /// * `strips` - (first column, width) of each strip, covering every column in order
+    ///
+    /// # Returns
+    /// * The strips of every layer
+    fn strip_jobs(&mut self, strips: &[(u32, u32)]) -> Vec<TileStrip<'_>> {
+        let rows = self.height;
+        std::iter::once(&mut self.tiles)
+            .chain(self.height_layers.iter_mut())
+            .flat_map(|layer| layer.strips(strips, rows))
+            .collect()
+    }
+
+    /// Convert the optimized quadtree into a vector of Brickadia bricks
+    /// This is the final step that creates the actual brick objects for the save file
+    /// 
+    /// # Arguments
+    /// * `options` - Generation options controlling brick properties
+    /// 
+    /// # Returns
+    /// * Vector of Brick objects ready for writing to a save file
+    pub fn into_bricks(&self, options: GenOptions) -> Vec<Brick> {
+        self.into_layer_bricks(options).into_iter().flatten().collect()
+    }
+
+    /// Convert the optimized quadtree into Brickadia bricks, keeping each layer separate
+    ///
+    /// # Arguments
+    /// * `options` - Generation options controlling brick properties
+    ///
+    /// # Returns
+    /// * One vector of bricks per layer, starting with the main tiles
+    pub fn into_layer_bricks(&self, options: GenOptions) -> Vec<Vec<Brick>> {
+        self.into_layer_bricks_with_stats(options, &mut GenStats::default())
+    }
+
+    /// Convert the optimized quadtree into Brickadia bricks, keeping each layer separate
+    /// and recording the merged underground cells in `stats`
+    /// See `into_layer_bricks` for details
+    pub fn into_layer_bricks_with_stats(
+        &self,
+        options: GenOptions,
+        stats: &mut GenStats,
+    ) -> Vec<Vec<Brick>> {
+        let mut all_bricks = Vec::new();
+        
+        // Process main tiles vector
+        let main_bricks = Self::tiles_to_bricks(&self.tiles, &options, 0, self.height, stats);
+        all_bricks.push(main_bricks);
+        
+        // Process each height layer vector
+        for (i, layer) in self.height_layers.iter().enumerate() {
+            // Calculate height_adjustment based on height_0_colors logic
+            // Layer i corresponds to sorted_heights[i+1]
+            let height_adjustment = if self.layers.sorted_heights.is_empty() {
+                0
+            } else {
+                let current_height_index = i;
+                if current_height_index < self.layers.sorted_heights.len() {
+                    let current_height = self.layers.sorted_heights[current_height_index];
+                    // Check if height_0_colors contains the color for this height
+                    if let Some(&color) = self.layers.filtered_heights.get(&current_height) {
+                        if self.layers.height_0_colors.contains(&color) {
+                            // If height_0_colors contains the color for sorted_heights[i], use sorted_heights[i-1]
+                            if current_height_index > 0 {
+                                self.layers.sorted_heights[current_height_index - 1]
+                            } else {
+                                0
+                            }
+                        } else {
+                            // Use the current height as before
+                            self.layers.sorted_heights[i]
+                        }
+                    } else {
+                        // Fallback to previous behavior
+                        self.layers.sorted_heights[i]
+                    }
+                } else {
+                    self.layers.sorted_heights[i]
+                }
+            };
+            let layer_bricks = Self::tiles_to_bricks(layer, &options, height_adjustment, self.height, stats);
+            all_bricks.push(layer_bricks);
+        }
+        
+        all_bricks
+    }
+    
+    /// Helper function to convert a tile layer into bricks
+    /// Columns too tall for one brick are split on a grid shared by the whole layer, so the
+    /// underground cells of neighboring columns line up and can be merged into larger boxes
+    /// 
+    /// # Arguments
+    /// * `tiles` - The tile layer to convert
+    /// * `options` - Generation options controlling brick properties
+    /// * `height_adjustment` - Value to adjust the height of bricks by
+    /// * `rows` - Number of rows in the grid
+    /// * `stats` - Statistics to record column merges in
+    /// 
+    /// # Returns
+    /// * Vector of Brick objects created from the tiles
+    fn tiles_to_bricks(
+        tiles: &TileLayer,
+        options: &GenOptions,
+        height_adjustment: u32,
+        rows: u32,
+        stats: &mut GenStats,
+    ) -> Vec<Brick> {
+        let pos_adjust = if height_adjustment == 0 {
+            0
+        } else {
+            4
+        };
+        let limits = options.limits();
+        let step = limits.z_step;
+
+        // Top of a tile's column and the height it should be filled down by
+        let column = |i: usize| {
+            let tile_height = tiles.height[i];
+
+            // Calculate the Z position (vertical placement) of this brick
+            let mut z = if tile_height == 0 {
+                0
+            } else {
+                (options.scale * tile_height) as i32
+            };
+
+            // Calculate brick height based on height difference with neighbors
+            // This creates natural-looking terrain with varying brick heights
+            let raw_height = max(
+                tile_height as i32 - height_adjustment as i32 + 1,
+                2,  // Minimum height of 2 units
+            );
+            // Apply scaling and ensure minimum height
+            let mut desired_height = max(raw_height * options.scale as i32 / 2, 2);
+
+            // Snap brick positions and heights to the Brickadia grid if enabled
+            // Brickadia uses a 4-unit grid system for precise brick alignment
+            if options.snap {
+                z += 4 - z % 4;                    // Round Z position up to next grid line
+                desired_height += 4 - desired_height % 4;  // Round height up to grid multiple
+            }
+            (z, desired_height)
+        };
+
+        // Heights of the bricks stacked to fill a column, from the top down
+        // Each brick is at most the asset's maximum height
+        let stack = |mut desired_height: i32| {
+            let mut heights = vec![];
+            while desired_height > 0 {
+                // Enforce minimum and maximum height constraints
+                let height = min(max(desired_height as u32, step), limits.max_z);
+                // Round up to the asset's height grid, staying under the maximum
+                let height = min(height.div_ceil(step) * step, limits.max_z / step * step);
+                heights.push(height);
+                desired_height -= height as i32;  // Reduce remaining height needed
+            }
+            heights
+        };
+
+        // Pair each tile index with its column, skipping tiles that have been merged or should be culled
+        let visible = || {
+            tiles
+                .col_start
+                .windows(2)
+                .enumerate()
+                .flat_map(|(x, column)| (column[0]..column[1]).map(move |i| (x as u32, i)))
+                .filter(|&(_, i)| {
+                    !(tiles.merged[i]  // Skip merged tiles
+                        || options.cull && tiles.color[i][3] == 0)  // Skip transparent tiles if culling enabled
+                })
+        };
+
+        // Underground fill, only for the main layer's terrain
+        let subsoil = options.subsoil.filter(|_| pos_adjust == 0 && !options.img);
+        let subsoil_limits = subsoil.and_then(|s| s.kind).map_or(limits, |kind| ASSET_LIMITS[kind.asset() as usize]);
+        // Height of one grid cell, and the height grid that fits both assets
+        let cell_height = min(limits.max_z, subsoil_limits.max_z);
+        let cell = 2 * cell_height as i32;
+        let fill_step = lcm(step, subsoil_limits.z_step);
+        // A column's cells start on the first line at or below the floor that its top is a whole number
+        // of surface bricks above, so the bricks between the cells and the top fill it without overlapping
+        let column_floor = |floor: i32, top: i32| floor - (floor - top).rem_euclid(2 * step as i32);
+        // Height at the top of each column that keeps the surface color, on the height grid of both assets
+        // Subsoil starts on a grid this far apart, so columns of similar heights share their subsoil
+        let band = subsoil.map(|s| max(s.depth, 1).div_ceil(2 * fill_step) as i32 * 2 * fill_step as i32);
+
+        // Whether a column is split on the grid: when it has subsoil, or without subsoil when it's
+        // too tall for one brick (so it would be split anyway)
+        let gridded = |top: i32, bottom: i32, bricks: usize| match band {
+            Some(band) => top - bottom > 2 * band,
+            None => bricks > 1,
+        };
+
+        // Split columns all reach down to the lowest of their bottoms, which the grid starts from
+        // Layers and flat images are left as they are
+        let floor = if pos_adjust == 0 && !options.img {
+            visible()
+                .filter_map(|(_, i)| {
+                    let (z, desired_height) = column(i);
+                    let heights = stack(desired_height);
+                    let bottom = z + 4 - 2 * heights.iter().sum::<u32>() as i32;
+                    gridded(z + 4, bottom, heights.len()).then_some(bottom)
+                })
+                .min()
+                .map(|floor| if options.snap { floor - floor.rem_euclid(4) } else { floor })
+        } else {
+            None
+        };
+
+        // Underground cells on the grid by the line they start from, and subsoil boxes between the grid
+        // and the surface by their bottom and top
+        let mut cells: BTreeMap<i32, Vec<CellRect>> = BTreeMap::new();
+        let mut pieces: BTreeMap<(i32, i32), Vec<CellRect>> = BTreeMap::new();
+
+        let mut bricks: Vec<Brick> = visible()
+            .flat_map(|(x, i)| {
+                // Gather this tile's properties
+                let corner = (x, tiles.row[i]);
+                let size = (tiles.size[i].0 as u32, tiles.size[i].1 as u32);
+                let color = tiles.color[i];
+                let (mut z, desired_height) = column(i);
+                let heights = stack(desired_height);
+                let top = z + 4;
+                let bottom = top - 2 * heights.iter().sum::<u32>() as i32;
+
+                let heights = match (floor.map(|floor| column_floor(floor, top)), subsoil, band) {
+                    (Some(floor), Some(subsoil), Some(band)) if gridded(top, bottom, heights.len()) => {
+                        // Subsoil is filled up to the highest band line at least `band` below the top,
+                        // as whole cells from the floor up and a box between them and the band line
+                        let fill_top = floor + (top - band - floor) / band * band;
+                        let count = (fill_top - floor) / cell;
+                        if fill_top > floor + count * cell {
+                            pieces
+                                .entry((floor + count * cell, fill_top))
+                                .or_default()
+                                .push(CellRect { corner, size, color: subsoil.color, levels: 1 });
+                        }
+                        cells.entry(floor).or_default().push(CellRect {
+                            corner,
+                            size,
+                            color: subsoil.color,
+                            levels: count as u32,
+                        });
+
+                        // The rest keeps the surface color
+                        stack((top - fill_top) / 2)
+                    }
+                    (Some(floor), None, _) if gridded(top, bottom, heights.len()) => {
+                        // Whole cells from the floor up go underground, the top brick fills the rest
+                        // exactly, as the top is on the height grid from the column's floor
+                        let count = (top - floor - 1) / cell;
+                        let height = ((top - floor - count * cell) / 2) as u32;
+                        cells.entry(floor).or_default().push(CellRect { corner, size, color, levels: count as u32 });
+                        return vec![column_brick(options, corner, size, (top - height as i32, height), color)];
+                    }
+                    _ => heights,
+                };
+
+                // Create multiple bricks if needed to reach the desired height
+                heights
+                    .into_iter()
+                    .map(|height| {
+                        let brick = column_brick(
+                            options,
+                            corner,
+                            size,
+                            (
+                                z - height as i32 + pos_adjust + 4,  // Z position (bottom of brick at terrain level)
+                                // For micro brick images, use uniform cube size
+                                if options.img && options.kind == BrickKind::Micro {
+                                    options.size
+                                } else {
+                                    // Otherwise use calculated height, kept on the grid when raised for layers
+                                    max(height.saturating_sub(pos_adjust as u32), step).div_ceil(step) * step
+                                },
+                            ),
+                            color,
+                        );
+                        z -= height as i32 * 2;  // Move Z position down for next brick
+                        brick
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Subsoil bricks use their own asset and collision, and are never glowing
+        let underground = |brick: Brick| match subsoil {
+            Some(subsoil) => Brick {
+                asset_name_index: subsoil.kind.unwrap_or(options.kind).asset(),
+                collision: Collision {
+                    player: subsoil.collide,
+                    weapon: subsoil.collide,
+                    interaction: subsoil.collide,
+                    tool: true,
+                },
+                material_index: 0,
+                ..brick
+            },
+            None => brick,
+        };
+
+        // Merge each level of underground cells like the surface tiles
+        for (floor, cells) in &cells {
+            let levels = cells.iter().map(|c| c.levels).max().unwrap_or(0);
+            for level in 0..levels {
+                let z = (floor + cell * level as i32 + cell_height as i32, cell_height);
+                let level_cells: Vec<&CellRect> = cells.iter().filter(|c| c.levels > level).collect();
+                stats.column_merges += merge_boxes(&level_cells, rows, options.max_tiles(), |corner, size, color| {
+                    bricks.push(underground(column_brick(options, corner, size, z, color)))
+                });
             }
-            scale += 1;  // Move to next scale level (2x2 -> 4x4 -> 8x8, etc.)
         }
-        progress!(0.7);  // 70% complete after quadtree optimization
+        for ((bottom, top), boxes) in &pieces {
+            let z = ((bottom + top) / 2, ((top - bottom) / 2) as u32);
+            let boxes: Vec<&CellRect> = boxes.iter().collect();
+            stats.column_merges += merge_boxes(&boxes, rows, options.max_tiles(), |corner, size, color| {
+                bricks.push(underground(column_brick(options, corner, size, z, color)))
+            });
+        }
+
+        bricks
+    }
+}
 
-        (0.7, 0.25)  // Remaining work starts at 70%, uses 25% of progress bar
+/// Merge boxes of the same height into larger bricks where they have the same color
+/// The boxes are split into pixels and merged with the greedy and line optimizations
+///
+/// # Arguments
+/// * `boxes` - Footprints of the boxes, which don't overlap
+/// * `rows` - Number of rows in the grid
+/// * `max_tiles` - Longest side of a brick in pixels
+/// * `emit` - Called with the corner, size and color of each merged brick
+///
+/// # Returns
+/// * Number of boxes that were merged away
+fn merge_boxes<F>(boxes: &[&CellRect], rows: u32, max_tiles: u32, mut emit: F) -> usize
+where
+    F: FnMut((u32, u32), (u32, u32), [u8; 4]),
+{
+    // Only the columns the boxes cover are needed
+    let (from, to) = match (
+        boxes.iter().map(|b| b.corner.0).min(),
+        boxes.iter().map(|b| b.corner.0 + b.size.0).max(),
+    ) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return 0,
+    };
+    let mut columns: Vec<Vec<&CellRect>> = vec![vec![]; (to - from) as usize];
+    for &b in boxes {
+        for x in b.corner.0..b.corner.0 + b.size.0 {
+            columns[(x - from) as usize].push(b);
+        }
+    }
+
+    let mut layer = TileLayer {
+        col_start: vec![0],
+        ..Default::default()
+    };
+    for column in &mut columns {
+        column.sort_unstable_by_key(|b| b.corner.1);
+        for b in column.iter() {
+            for y in b.corner.1..b.corner.1 + b.size.1 {
+                layer.push(y, 0, b.color);
+            }
+        }
+        layer.col_start.push(layer.row.len());
+    }
+
+    for mut strip in layer.strips(&[(0, to - from)], rows) {
+        let mut merged = strip.greedy_optimize(max_tiles);
+        while merged > 0 {
+            merged = strip.line_optimize(max_tiles);
+        }
+    }
+
+    let mut count = 0;
+    for (x, column) in layer.col_start.windows(2).enumerate() {
+        for i in (column[0]..column[1]).filter(|&i| !layer.merged[i]) {
+            let size = (layer.size[i].0 as u32, layer.size[i].1 as u32);
+            emit((from + x as u32, layer.row[i]), size, layer.color[i]);
+            count += 1;
+        }
+    }
+    boxes.len().saturating_sub(count)
+}
+
+/// Least common multiple of two height steps
+fn lcm(a: u32, b: u32) -> u32 {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x.max(1) * b
+}
+
+/// Create one brick of a column
+///
+/// # Arguments
+/// * `options` - Generation options controlling brick properties
+/// * `corner` - Top-left pixel of the brick
+/// * `size` - Width and depth of the brick in pixels
+/// * `z` - Center and height (half extent) of the brick in Brickadia units
+/// * `color` - RGBA color of the brick
+fn column_brick(
+    options: &GenOptions,
+    corner: (u32, u32),
+    size: (u32, u32),
+    z: (i32, u32),
+    color: [u8; 4],
+) -> Brick {
+    Brick {
+        // Reference to the brick asset type (cube, tile, micro, stud)
+        asset_name_index: options.kind.asset(),
+        // Set brick dimensions (width, depth, height)
+        size: Size::Procedural(
+            size.0 * options.size,  // Width based on tile size
+            size.1 * options.size,  // Depth based on tile size
+            z.1,
+        ),
+        // Calculate brick position in 3D space
+        position: (
+            ((corner.0 * 2 + size.0) * options.size) as i32,  // X position (centered on tile)
+            ((corner.1 * 2 + size.1) * options.size) as i32,  // Y position (centered on tile)
+            z.0,
+        ),
+        // Set collision properties based on options
+        collision: Collision {
+            player: !options.nocollide,      // Player collision enabled unless disabled
+            weapon: !options.nocollide,      // Weapon collision enabled unless disabled
+            interaction: !options.nocollide, // Interaction enabled unless disabled
+            tool: true,                       // Always allow tool interaction
+        },
+        // Set brick color from the colormap
+        color: BrickColor::Unique(Color {
+            r: color[0],  // Red channel
+            g: color[1],  // Green channel
+            b: color[2],  // Blue channel  
+            a: color[3],  // Alpha (transparency)
+        }),
+        owner_index: 1,  // Reference to owner in the save file
+        material_intensity: 0,  // No special material effects
+        material_index: u32::from(options.glow),  // Glow material if enabled
+        ..Default::default()  // Use default values for remaining fields
+    }
+}
+
+
+/// Get the number of threads to use, where 0 means one per CPU core
+fn resolve_threads(threads: usize) -> usize {
+    if threads > 0 {
+        threads
     } else {
-        (0.2, 0.75)  // Skip quadtree, remaining work starts at 20%, uses 75% of progress bar
+        thread::available_parallelism().map(NonZeroUsize::get).unwrap_or(1)
+    }
+}
+
+/// Split `width` columns into strips for building tiles
+/// There are a few strips per thread so uneven strips balance out
+fn build_strips(width: u32, threads: usize) -> Vec<(u32, u32)> {
+    let strip = max(width.div_ceil(threads as u32 * 4), 1);
+    column_strips(width, strip, strip)
+}
+
+/// Split `width` columns into strips, the first `first` columns wide and the rest `strip` wide
+/// The last strip is cut short at the edge of the grid
+///
+/// # Returns
+/// * (first column, width) of each strip
+fn column_strips(width: u32, first: u32, strip: u32) -> Vec<(u32, u32)> {
+    let mut strips = vec![];
+    let mut x = 0;
+    let mut w = first;
+    while x < width {
+        strips.push((x, min(w, width - x)));
+        x += w;
+        w = strip;
+    }
+    strips
+}
+
+/// Run `f` on every job using up to `threads` threads
+/// Jobs are handed out from a shared queue so slow strips don't hold up the others
+///
+/// # Returns
+/// * The result of each job, in the same order as the jobs
+fn run_jobs<J, R, F>(jobs: Vec<J>, threads: usize, f: F) -> Vec<R>
+where
+    J: Send,
+    R: Send,
+    F: Fn(J) -> R + Sync,
+{
+    match run_jobs_reporting(jobs, threads, f, |_| Ok::<(), Infallible>(())) {
+        Ok(results) => results,
+        Err(never) => match never {},
+    }
+}
+
+/// Run `f` on every job like `run_jobs`, calling `done` on this thread with each result as its job finishes
+/// Jobs that haven't started yet are dropped once `done` returns an error
+///
+/// # Returns
+/// * `Ok(Vec<R>)` - The result of each job, in the same order as the jobs
+/// * `Err(E)` - The first error returned by `done`
+fn run_jobs_reporting<J, R, F, D, E>(jobs: Vec<J>, threads: usize, f: F, mut done: D) -> Result<Vec<R>, E>
+where
+    J: Send,
+    R: Send,
+    F: Fn(J) -> R + Sync,
+    D: FnMut(&R) -> Result<(), E>,
+{
+    if threads <= 1 || jobs.len() <= 1 {
+        return jobs
+            .into_iter()
+            .map(|job| {
+                let result = f(job);
+                done(&result)?;
+                Ok(result)
+            })
+            .collect();
+    }
+
+    let count = jobs.len();
+    let queue = Mutex::new(jobs.into_iter().enumerate());
+    let (sender, receiver) = mpsc::channel();
+    let mut error = None;
+    let mut results: Vec<(usize, R)> = thread::scope(|s| {
+        for _ in 0..min(threads, count) {
+            let sender = sender.clone();
+            let (queue, f) = (&queue, &f);
+            s.spawn(move || loop {
+                // Take the lock only long enough to grab the next job
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some((i, job)) => sender.send((i, f(job))).unwrap(),
+                    None => break,
+                }
+            });
+        }
+        drop(sender);
+
+        // Results come in as jobs finish, until every worker has stopped
+        let mut results = vec![];
+        for (i, result) in receiver {
+            if error.is_none() {
+                if let Err(err) = done(&result) {
+                    // Workers stop after their current job once the queue is empty
+                    queue.lock().unwrap().by_ref().for_each(drop);
+                    error = Some(err);
+                }
+            }
+            results.push((i, result));
+        }
+        results
+    });
+    if let Some(err) = error {
+        return Err(err);
+    }
+
+    // Put the results back in job order so the output doesn't depend on scheduling
+    results.sort_by_key(|(i, _)| *i);
+    Ok(results.into_iter().map(|(_, r)| r).collect())
+}
+
+/// Build the tiles of one layer, column by column, with the strips spread over several threads
+///
+/// # Arguments
+/// * `heightmap` - Source of elevation data
+/// * `strips` - (first column, width) of each strip, covering every column in order
+/// * `threads` - Number of threads to use
+/// * `on_strip` - Called with the number of pixels read as each strip is built, can stop building with an error
+/// * `f` - Returns the (height, color) of the tile at (x, y) given the pixel's height,
+///   or None to leave that pixel without a tile
+///
+/// # Returns
+/// * `Ok(TileLayer)` - The layer holding a tile for every pixel `f` accepted
+/// * `Err(GenError)` - The first error returned by `on_strip`
+fn build_tiles<F>(
+    heightmap: &(dyn Heightmap + Sync),
+    strips: &[(u32, u32)],
+    threads: usize,
+    on_strip: &mut dyn FnMut(u64) -> Result<(), GenError>,
+    f: F,
+) -> Result<TileLayer, GenError>
+where
+    F: Fn(u32, u32, u32) -> Option<(u32, [u8; 4])> + Sync,
+{
+    let height = heightmap.size().1;
+
+    let strip_tiles = run_jobs_reporting(strips.to_vec(), threads, |(x0, w)| {
+        let mut tiles = TileLayer {
+            col_start: vec![0],
+            ..Default::default()
+        };
+
+        for x in x0..x0 + w {
+            for y in 0..height {
+                if let Some((tile_height, color)) = f(x, y, heightmap.at(x, y)) {
+                    tiles.push(y, tile_height, color);
+                }
+            }
+            tiles.col_start.push(tiles.row.len());
+        }
+        tiles
+    }, |tiles| on_strip((tiles.col_start.len() - 1) as u64 * height as u64))?;
+
+    // Strips are in column order, so joining them gives the full layer
+    let mut layer = TileLayer::default();
+    for tiles in strip_tiles {
+        layer.append(tiles);
+    }
+    if layer.col_start.is_empty() {
+        layer.col_start.push(0);  // Empty maps still need the end of the (missing) last column
+    }
+    Ok(layer)
+}
+
+/// Optimizers that `Optimizer::Best` runs, in order, keeping the first on ties
+const BEST_CANDIDATES: [Optimizer; 2] = [Optimizer::Quadtree, Optimizer::Greedy];
+
+/// Number of quadtree levels that can merge, before bricks would exceed the asset's size limit
+fn quad_levels(options: &GenOptions) -> u32 {
+    let max_xy = options.limits().max_xy;
+    (0..).take_while(|&scale| 2_u32.pow(scale + 1) * options.size <= max_xy).count() as u32
+}
+
+/// Passes over every tile `optimize_quadtree` is expected to make: the greedy pass or each
+/// quadtree level, then one line pass, as further line passes are only expected once one merges
+fn optimize_passes(options: &GenOptions) -> u64 {
+    let merging = if options.optimizer == Optimizer::Greedy {
+        1
+    } else if options.quadtree {
+        quad_levels(options) as u64
+    } else {
+        0
     };
+    merging + 1
+}
+
+/// Passes over every tile expected from optimizing a quadtree to emitting its bricks,
+/// for every optimizer `optimize_to_bricks` runs
+fn generation_passes(options: &GenOptions) -> u64 {
+    match options.optimizer {
+        Optimizer::Best => BEST_CANDIDATES
+            .iter()
+            .map(|&optimizer| optimize_passes(&GenOptions { optimizer, ..options.clone() }) + 1)
+            .sum(),
+        _ => optimize_passes(options) + 1,
+    }
+}
+
+/// Merge the tiles of a quadtree with the chosen optimizer followed by line optimizations
+/// `Optimizer::Best` is handled by `optimize_to_bricks`, here it's the same as the quadtree optimizer
+///
+/// # Arguments
+/// * `quad` - The quadtree to optimize
+/// * `options` - Configuration options for brick generation
+/// * `stats` - Statistics to record merges and timings in
+/// * `verbose` - Whether to log each optimization pass
+/// * `work` - Items of the generation, every pass counts as visiting every tile
+/// * `after` - Passes over every tile expected after this optimization, for the items still to come
+///
+/// # Returns
+/// * `Ok(())` once no more tiles can be merged
+/// * `Err(GenError)` if generation is cancelled
+fn optimize_quadtree(
+    quad: &mut QuadTree,
+    options: &GenOptions,
+    stats: &mut GenStats,
+    verbose: bool,
+    work: &mut WorkProgress,
+    after: u64,
+) -> Result<(), GenError> {
+    let tiles = quad.tile_count();
+
+    if options.optimizer == Optimizer::Greedy {
+        if verbose {
+            info!("Optimizing greedy rectangles");
+        }
+        work.report(Phase::GreedyMerge, 0, 1)?;
+        let phase = Instant::now();
+        let count = quad.greedy_optimize(options.max_tiles());
+        stats.greedy_merges = Some(count);
+        if verbose {
+            info!("  Removed {} bricks", count);
+        }
+        stats.record_phase("greedy optimization", phase);
+        work.processed(tiles);
+        work.report(Phase::GreedyMerge, 1, 1)?;
+    } else if options.quadtree {
+        if verbose {
+            info!("Optimizing quadtree");
+        }
+        let phase = Instant::now();
+        let mut scale = 0;  // Start with 1x1 tiles, scale up to 2x2, 4x4, etc.
+
+        // Perform quadtree optimization at increasing scales
+        // Stop when bricks would exceed the asset's size limit
+        let levels = quad_levels(options);
+        while scale < levels {
+            work.report(Phase::QuadLevel(2_u32.pow(scale)), scale as u64, levels as u64)?;
+            let count = quad.quad_optimize_level(scale);
+            stats.quad_merges.push(QuadLevelStats {
+                tile_size: 2_u32.pow(scale),
+                merged: count,
+            });
+            work.processed(tiles);
+            if count == 0 {
+                // No more tiles merged at this scale, so the levels left won't run
+                work.expect((1 + after) * tiles);
+                break;
+            } else if verbose {
+                info!("  Removed {:?} {}x bricks", count, 2_i32.pow(scale));
+            }
+            scale += 1;  // Move to next scale level (2x2 -> 4x4 -> 8x8, etc.)
+        }
+        stats.record_phase("quadtree optimization", phase);
+    }
 
     // Perform line optimization to merge adjacent similar tiles
-    info!("Optimizing linear");
+    if verbose {
+        info!("Optimizing linear");
+    }
+    let phase = Instant::now();
     let mut i = 0;
     loop {
         i += 1;
 
-        let count = quad.line_optimize(options.size);
-        // Update progress, capping at 100% after 5 iterations
-        progress!(prog_offset + prog_scale * (i as f32 / 5.0).min(1.0));
+        let count = quad.line_optimize(options.max_tiles());
+        stats.line_merges.push(count);
+        // Any pass that merged something may be followed by another one
+        work.processed(tiles);
+        work.expect((u64::from(count > 0) + after) * tiles);
+        work.report(Phase::LinePass(i), i as u64, 0)?;
 
         if count == 0 {
             break;  // No more tiles merged, optimization complete
         }
-        info!("  Removed {} bricks", count);
+        if verbose {
+            info!("  Removed {} bricks", count);
+        }
+    }
+    stats.record_phase("line optimization", phase);
+    Ok(())
+}
+
+/// Optimize a quadtree with the chosen optimizer and convert it into bricks, grouped by layer
+/// With `Optimizer::Best` both optimizers run on copies of the quadtree and whichever gives
+/// fewer bricks is kept (the quadtree on ties), both are listed in the stats' comparison
+///
+/// # Arguments
+/// * `quad` - The quadtree to optimize
+/// * `options` - Configuration options for brick generation
+/// * `stats` - Statistics to record merges, timings and the comparison in
+/// * `verbose` - Whether to log each optimization pass
+/// * `work` - Items of the generation the quadtree was built for, from building it to emitting bricks
+///
+/// # Returns
+/// * `Ok(Vec<Vec<Brick>>)` - Bricks of the base layer and then each full layer
+/// * `Err(GenError)` if generation is cancelled
+fn optimize_to_bricks(
+    mut quad: QuadTree,
+    options: &GenOptions,
+    stats: &mut GenStats,
+    verbose: bool,
+    work: &mut WorkProgress,
+) -> Result<Vec<Vec<Brick>>, GenError> {
+    let tiles = quad.tile_count();
+    work.expect(generation_passes(options) * tiles);
+
+    stats.optimizer = options.optimizer;
+    if options.optimizer != Optimizer::Best {
+        // Emitting visits every tile once more
+        optimize_quadtree(&mut quad, options, stats, verbose, work, 1)?;
+        work.report(Phase::EmittingBricks, 0, 0)?;
+        let phase = Instant::now();
+        let layers = quad.into_layer_bricks_with_stats(options.clone(), stats);
+        stats.record_phase("emit bricks", phase);
+        let bricks = layers.iter().map(Vec::len).sum::<usize>() as u64;
+        work.processed(tiles);
+        work.report(Phase::EmittingBricks, bricks, bricks)?;
+        return Ok(layers);
     }
 
-    progress!(0.95);  // 95% complete before final brick generation
+    let mut source = Some(quad);
+    let mut best: Option<(Vec<Vec<Brick>>, GenStats)> = None;
+    for (i, &optimizer) in BEST_CANDIDATES.iter().enumerate() {
+        if verbose {
+            info!("Trying the {} optimizer", optimizer);
+        }
+        let start = Instant::now();
+        let options = GenOptions { optimizer, ..options.clone() };
+        let mut run_stats = GenStats::new(stats.input_size);
+
+        run_stats.optimizer = optimizer;
+
+        // The last candidate can use the original quadtree
+        let quad = if i + 1 < BEST_CANDIDATES.len() { source.clone() } else { source.take() };
+        let mut quad = quad.expect("only the last candidate takes the quadtree");
 
-    // Convert the optimized quadtree into actual Brickadia bricks
-    let bricks = quad.into_bricks(options);
+        // This candidate's bricks are emitted before the later candidates run
+        let after = 1 + BEST_CANDIDATES[i + 1..]
+            .iter()
+            .map(|&optimizer| optimize_passes(&GenOptions { optimizer, ..options.clone() }) + 1)
+            .sum::<u64>();
+        optimize_quadtree(&mut quad, &options, &mut run_stats, verbose, work, after)?;
+        let phase = Instant::now();
+        let layers = quad.into_layer_bricks_with_stats(options, &mut run_stats);
+        drop(quad);
+        run_stats.record_phase("emit bricks", phase);
+        work.processed(tiles);
+
+        let bricks = layers.iter().map(Vec::len).sum();
+        if verbose {
+            info!("  {} optimizer: {} bricks", optimizer, bricks);
+        }
+        stats.optimizer_comparison.push(OptimizerRun {
+            optimizer,
+            bricks,
+            seconds: start.elapsed().as_secs_f64(),
+            kept: 0,
+        });
+
+        // Only the fewest bricks so far are kept, the other run only adds its timings
+        match best.take() {
+            Some((kept, kept_stats)) if kept.iter().map(Vec::len).sum::<usize>() <= bricks => {
+                stats.merge_phases(&run_stats);
+                best = Some((kept, kept_stats));
+            }
+            Some((_, dropped_stats)) => {
+                stats.merge_phases(&dropped_stats);
+                best = Some((layers, run_stats));
+            }
+            None => best = Some((layers, run_stats)),
+        }
+    }
+
+    let (layers, run_stats) = best.unwrap_or_default();
+    let bricks = layers.iter().map(Vec::len).sum::<usize>() as u64;
+    work.report(Phase::EmittingBricks, bricks, bricks)?;
+    if let Some(run) = stats.optimizer_comparison.iter_mut().find(|r| r.optimizer == run_stats.optimizer) {
+        run.kept += 1;
+    }
+    stats.merge(run_stats);
+    stats.optimizer = Optimizer::Best;
+    Ok(layers)
+}
+
+/// Items a generation of `pixels` pixels with `layers` tile layers is expected to process:
+/// every pixel read for each layer, then the passes of `generation_passes` over as many tiles
+/// Layers above the main tiles usually have fewer tiles, which is corrected once they're built
+fn expected_items(pixels: u64, layers: u64, options: &GenOptions) -> u64 {
+    pixels * layers * (1 + generation_passes(options))
+}
+
+/// Part of a map generated in blocks that is taken by finding its layer heights,
+/// which reads every pixel once before the blocks are generated
+/// The blocks are expected to be one layer until the layers are found
+///
+/// # Returns
+/// * Fraction of the whole progress, 0 if full layers are off
+pub(crate) fn finding_layers_share(size: (u32, u32), options: &GenOptions) -> f32 {
+    if options.gen_full_layers_above_height == 0 {
+        return 0.0;
+    }
+    let area = size.0 as u64 * size.1 as u64;
+    area as f32 / max(area + expected_items(area, 1, options), 1) as f32
+}
+
+/// Generate an optimized brick heightmap with quadtree and line optimizations
+/// This is the main function that orchestrates the entire brick generation process
+/// 
+/// # Arguments
+/// * `heightmap` - Source of elevation data
+/// * `colormap` - Source of color data  
+/// * `options` - Configuration options for brick generation
+/// * `progress` - Receives the phase and progress of the generation, and can cancel it
+/// 
+/// # Returns
+/// * `Ok(Vec<Brick>)` - Vector of optimized bricks ready for save file
+/// * `Err(GenError)` - Error message if generation fails or is cancelled
+pub fn gen_opt_heightmap(
+    heightmap: &(dyn Heightmap + Sync),
+    colormap: &(dyn Colormap + Sync),
+    options: GenOptions,
+    progress: &dyn Progress,
+) -> Result<Vec<Brick>, GenError> {
+    gen_opt_heightmap_with_stats(heightmap, colormap, options, progress).map(|(bricks, _)| bricks)
+}
+
+/// Generate an optimized brick heightmap, also collecting statistics about the generation
+/// See `gen_opt_heightmap` for details
+///
+/// # Returns
+/// * `Ok((Vec<Brick>, GenStats))` - Optimized bricks and the statistics for this generation
+/// * `Err(GenError)` - Error message if generation fails or is cancelled
+pub fn gen_opt_heightmap_with_stats(
+    heightmap: &(dyn Heightmap + Sync),
+    colormap: &(dyn Colormap + Sync),
+    options: GenOptions,
+    progress: &dyn Progress,
+) -> Result<(Vec<Brick>, GenStats), GenError> {
+    gen_opt_heightmap_scoped(heightmap, colormap, options, ProgressScope::new(progress))
+}
+
+/// Generate an optimized brick heightmap with statistics, reporting progress within part of a larger job
+/// See `gen_opt_heightmap` for details
+pub(crate) fn gen_opt_heightmap_scoped(
+    heightmap: &(dyn Heightmap + Sync),
+    colormap: &(dyn Colormap + Sync),
+    options: GenOptions,
+    progress: ProgressScope,
+) -> Result<(Vec<Brick>, GenStats), GenError> {
+    options.validate()?;
+
+    let mut stats = GenStats::new(heightmap.size());
+
+    info!("Building initial quadtree");
+    let phase = Instant::now();
+    let (width, height) = heightmap.size();
+    let area = width * height;  // Total number of pixels/potential bricks
+    // Finding layers reads every pixel once more, and the tiles are expected to be one layer until then
+    let finding = if options.gen_full_layers_above_height > 0 { area as u64 } else { 0 };
+    let mut work = WorkProgress::new(progress, finding + expected_items(area as u64, 1, &options));
+    let layers = LayerHeights::find(
+        heightmap,
+        colormap,
+        options.gen_full_layers_above_height,
+        options.threads,
+        &mut work,
+    )?;
+    work.expect(expected_items(area as u64, layers.count(), &options));
+    let quad = QuadTree::build(heightmap, colormap, layers, options.threads, &mut work)?;  // Create initial 1:1 tile grid
+    stats.record_phase("build quadtree", phase);
+
+    // Optimize and convert the quadtree into actual Brickadia bricks
+    let layers = optimize_to_bricks(quad, &options, &mut stats, true, &mut work)?;
+
+    stats.record_bricks(&layers);
+    let mut bricks: Vec<Brick> = layers.into_iter().flatten().collect();
+    sort_bricks(&mut bricks);  // Same order no matter how the bricks were found
     let brick_count = bricks.len();
     
     // Report optimization results
@@ -849,6 +1737,691 @@ pub fn gen_opt_heightmap<F: Fn(f32) -> bool>(
         area as i32 - brick_count as i32,                               // Number of bricks saved
     );
 
-    progress!(1.0);  // 100% complete
-    Ok(bricks)       // Return the final optimized brick list
+    Ok((bricks, stats))  // Return the final optimized brick list and its statistics
+}
+
+
+/// Generate the bricks of one block of a map, moved to where the block is in the map
+///
+/// # Arguments
+/// * `heightmap` - Source of elevation data for the whole map
+/// * `colormap` - Source of color data for the whole map
+/// * `options` - Configuration options for brick generation
+/// * `layers` - Full layer heights of the whole map
+/// * `origin` - Top-left pixel of the block
+/// * `block_size` - Width and height of the block in pixels, clipped at the map edges
+/// * `progress` - Part of the progress taken by the block, see `ProgressScope::block`
+///
+/// # Returns
+/// * `Ok((Vec<Vec<Brick>>, GenStats))` - Bricks grouped by layer, and statistics for the block
+/// * `Err(GenError)` - Error message if generation fails or is cancelled
+pub(crate) fn gen_block(
+    heightmap: &(dyn Heightmap + Sync),
+    colormap: &(dyn Colormap + Sync),
+    options: &GenOptions,
+    layers: &LayerHeights,
+    origin: (u32, u32),
+    block_size: u32,
+    progress: ProgressScope,
+) -> Result<(Vec<Vec<Brick>>, GenStats), GenError> {
+    let block_heightmap = HeightmapWindow::new(heightmap, origin, (block_size, block_size));
+    let block_colormap = ColormapWindow::new(colormap, origin, (block_size, block_size));
+    let mut stats = GenStats::new(block_heightmap.size());
+
+    let phase = Instant::now();
+    let (width, height) = block_heightmap.size();
+    let area = width as u64 * height as u64;
+    let mut work = WorkProgress::new(progress, expected_items(area, layers.count(), options));
+    let quad = QuadTree::build(&block_heightmap, &block_colormap, layers.clone(), options.threads, &mut work)?;
+    stats.record_phase("build quadtree", phase);
+
+    // The quadtree is consumed, so its tiles are freed before writing
+    let mut block_layers = optimize_to_bricks(quad, options, &mut stats, false, &mut work)?;
+
+    // Move the bricks from the block's corner to where the block is in the map
+    let offset = (
+        (origin.0 * 2 * options.size) as i32,
+        (origin.1 * 2 * options.size) as i32,
+    );
+    for brick in block_layers.iter_mut().flatten() {
+        brick.position.0 += offset.0;
+        brick.position.1 += offset.1;
+    }
+    stats.record_bricks(&block_layers);
+    Ok((block_layers, stats))
+}
+
+/// Lazily generated bricks of a map, one block at a time, returned by `gen_opt_heightmap_iter`
+/// Each call to `next` generates the next block and yields its bricks, sorted like `sort_bricks`,
+/// so only one block of tiles and bricks is in memory at once
+/// Blocks go left to right, then top to bottom, and the iterator stops after the first error
+pub struct BrickBlocks<'a> {
+    /// Source of elevation data for the whole map
+    heightmap: &'a (dyn Heightmap + Sync),
+    /// Source of color data for the whole map
+    colormap: &'a (dyn Colormap + Sync),
+    /// Configuration options for brick generation
+    options: GenOptions,
+    /// Full layer heights of the whole map, shared by every block
+    layers: LayerHeights,
+    /// Width and height of each block in pixels
+    block_size: u32,
+    /// Number of blocks in each row
+    columns: u32,
+    /// Number of blocks in the map
+    block_count: u32,
+    /// Index of the next block to generate
+    next: u32,
+    /// Statistics of the blocks generated so far
+    stats: GenStats,
+    /// Progress of the blocks, the part of the whole map after finding its layer heights
+    progress: ProgressScope<'a>,
+}
+
+impl BrickBlocks<'_> {
+    /// Number of blocks in the map
+    pub fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    /// Statistics of the blocks generated so far
+    pub fn stats(&self) -> &GenStats {
+        &self.stats
+    }
+
+    /// Take the statistics, for the whole map once every block has been generated
+    pub fn into_stats(self) -> GenStats {
+        self.stats
+    }
+}
+
+impl Iterator for BrickBlocks<'_> {
+    type Item = Result<Vec<Brick>, GenError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.block_count {
+            return None;
+        }
+        let i = self.next;
+        self.next += 1;
+        let origin = ((i % self.columns) * self.block_size, (i / self.columns) * self.block_size);
+
+        // Each block reports progress within its share of the whole map
+        let result = gen_block(
+            self.heightmap,
+            self.colormap,
+            &self.options,
+            &self.layers,
+            origin,
+            self.block_size,
+            self.progress.block(i, self.block_count),
+        );
+        let (block_layers, block_stats) = match result {
+            Ok(block) => block,
+            Err(err) => {
+                // Later blocks aren't generated after an error
+                self.next = self.block_count;
+                return Some(Err(err));
+            }
+        };
+
+        let mut block_bricks: Vec<Brick> = block_layers.into_iter().flatten().collect();
+        sort_bricks(&mut block_bricks);
+        info!("  Block {}/{}: {} bricks", i + 1, self.block_count, block_stats.bricks);
+        self.stats.merge(block_stats);
+
+        if self.next == self.block_count {
+            let (width, height) = self.heightmap.size();
+            let area = width as u64 * height as u64;
+            info!(
+                "Reduced {} to {} ({}%; -{} bricks)",
+                area,
+                self.stats.bricks,
+                (100. - self.stats.bricks as f64 / area as f64 * 100.).floor(),
+                area as i64 - self.stats.bricks as i64,
+            );
+        }
+        Some(Ok(block_bricks))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = (self.block_count - self.next) as usize;
+        (left, Some(left))
+    }
+}
+
+/// Generate an optimized brick heightmap lazily, one block at a time
+/// Nothing but the full layer heights is generated until the blocks are iterated,
+/// and bricks never merge across block edges, like `gen_opt_heightmap_blocks`
+/// Only the bricks are kept per block: finding the layer heights reads every pixel first,
+/// and blocks read their pixels from `heightmap` and `colormap`, so the maps themselves stay in memory
+///
+/// # Arguments
+/// * `heightmap` - Source of elevation data
+/// * `colormap` - Source of color data
+/// * `options` - Configuration options for brick generation
+/// * `block_size` - Width and height of each block in pixels
+/// * `progress` - Receives the phase and progress of each block, and can cancel generation
+///
+/// # Returns
+/// * `Ok(BrickBlocks)` - Iterator over the bricks of each block, see `BrickBlocks`
+/// * `Err(GenError)` - Error message if the options are beyond the brick limits or generation is cancelled
+///
+/// # Examples
+/// ```
+/// use heightmap::{map::*, progress::NoProgress, quad::gen_opt_heightmap_iter, util::GenOptions};
+///
+/// let heightmap = HeightmapGrid::new((0..64).collect(), (8, 8)).unwrap();
+/// let colormap = ColormapGrid::new(vec![[255; 4]; 64], (8, 8), false).unwrap();
+/// let blocks = gen_opt_heightmap_iter(&heightmap, &colormap, GenOptions::default(), 4, &NoProgress).unwrap();
+/// assert_eq!(blocks.block_count(), 4);
+///
+/// // Bricks can be taken one at a time, with only one block in memory
+/// let mut bricks = 0;
+/// for block in blocks {
+///     bricks += block.unwrap().into_iter().count();
+/// }
+/// assert!(bricks > 0);
+/// ```
+pub fn gen_opt_heightmap_iter<'a>(
+    heightmap: &'a (dyn Heightmap + Sync),
+    colormap: &'a (dyn Colormap + Sync),
+    options: GenOptions,
+    block_size: u32,
+    progress: &'a dyn Progress,
+) -> Result<BrickBlocks<'a>, GenError> {
+    options.validate()?;
+    let progress = ProgressScope::new(progress);
+
+    let mut stats = GenStats::new(heightmap.size());
+    let (width, height) = heightmap.size();
+    let block_size = block_size.max(1);
+    let (columns, rows) = (width.div_ceil(block_size), height.div_ceil(block_size));
+    let block_count = columns * rows;
+
+    // Layers have to be the same in every block, so they're picked from the whole map first
+    if options.gen_full_layers_above_height > 0 {
+        info!("Finding layer heights");
+    }
+    let phase = Instant::now();
+    let share = finding_layers_share(heightmap.size(), &options);
+    let layers = LayerHeights::find(
+        heightmap,
+        colormap,
+        options.gen_full_layers_above_height,
+        options.threads,
+        &mut WorkProgress::new(progress.part(0.0, share), width as u64 * height as u64),
+    )?;
+    stats.record_phase("find layer heights", phase);
+
+    info!("Generating {} blocks of {}x{}", block_count, block_size, block_size);
+    Ok(BrickBlocks {
+        heightmap,
+        colormap,
+        options,
+        layers,
+        block_size,
+        columns,
+        block_count,
+        next: 0,
+        stats,
+        progress: progress.part(share, 1.0),
+    })
+}
+
+/// Generate an optimized brick heightmap one block at a time, streaming each block's bricks out
+/// Only one block of tiles is held in memory at once, so the map size isn't limited by memory
+/// Full layer heights are still found over the whole map, but bricks never merge across block edges
+///
+/// # Arguments
+/// * `heightmap` - Source of elevation data
+/// * `colormap` - Source of color data
+/// * `options` - Configuration options for brick generation
+/// * `block_size` - Width and height of each block in pixels
+/// * `write_f` - Called with the bricks of each block, in order, sorted like `sort_bricks`
+/// * `progress` - Receives the phase and progress of each block, and can cancel generation
+///
+/// # Returns
+/// * `Ok(GenStats)` - Statistics for the whole map
+/// * `Err(GenError)` - Error message if generation or writing fails, or generation is cancelled
+pub fn gen_opt_heightmap_blocks<W>(
+    heightmap: &(dyn Heightmap + Sync),
+    colormap: &(dyn Colormap + Sync),
+    options: GenOptions,
+    block_size: u32,
+    mut write_f: W,
+    progress: &dyn Progress,
+) -> Result<GenStats, GenError>
+where
+    W: FnMut(Vec<Brick>) -> Result<(), GenError>,
+{
+    let mut blocks = gen_opt_heightmap_iter(heightmap, colormap, options, block_size, progress)?;
+
+    // Writing is timed separately, then added after the generation phases
+    let mut write_stats = GenStats::new(heightmap.size());
+    let block_count = blocks.block_count();
+    let mut written = 0;
+    while let Some(block_bricks) = blocks.next() {
+        let block_bricks = block_bricks?;
+        let phase = Instant::now();
+        write_f(block_bricks)?;
+        write_stats.record_phase("write output", phase);
+
+        // Writing finishes the block's share of the progress
+        blocks
+            .progress
+            .block(written, block_count)
+            .report(Phase::Writing, written as u64 + 1, block_count as u64, 1.0)?;
+        written += 1;
+    }
+    let mut stats = blocks.into_stats();
+    stats.merge_phases(&write_stats);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Map of tall, uneven columns in two colors, so columns are split on the height grid
+    fn terrain(size: u32) -> (HeightmapGrid, ColormapGrid) {
+        let heights = (0..size * size)
+            .map(|i| {
+                let (x, y) = (i % size, i / size);
+                60 + (x * 37 + y * 11) % 97 + (x * y) % 5
+            })
+            .collect();
+        let colors = (0..size * size)
+            .map(|i| if i % 7 < 4 { [90, 140, 60, 255] } else { [120, 110, 80, 255] })
+            .collect();
+        (
+            HeightmapGrid::new(heights, (size, size)).unwrap(),
+            ColormapGrid::new(colors, (size, size), true).unwrap(),
+        )
+    }
+
+    /// Lowest and highest corner of the box a brick fills
+    fn bounds(brick: &Brick) -> ([i32; 3], [i32; 3]) {
+        let size = match brick.size {
+            Size::Procedural(x, y, z) => [x as i32, y as i32, z as i32],
+            _ => panic!("generated bricks are procedural"),
+        };
+        let pos = [brick.position.0, brick.position.1, brick.position.2];
+        let mut low = [0; 3];
+        let mut high = [0; 3];
+        for axis in 0..3 {
+            low[axis] = pos[axis] - size[axis];
+            high[axis] = pos[axis] + size[axis];
+        }
+        (low, high)
+    }
+
+    /// Panic if any two bricks fill the same space
+    fn assert_no_overlaps(bricks: &[Brick], case: &str) {
+        let boxes: Vec<([i32; 3], [i32; 3])> = bricks.iter().map(bounds).collect();
+        for (i, a) in boxes.iter().enumerate() {
+            for b in &boxes[i + 1..] {
+                let overlap = (0..3).all(|axis| a.0[axis] < b.1[axis] && b.0[axis] < a.1[axis]);
+                assert!(!overlap, "{}: bricks {:?} and {:?} overlap", case, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn split_columns_never_overlap() {
+        let (heightmap, colormap) = terrain(16);
+        let subsoils = [
+            None,
+            Some(Subsoil {
+                color: [60, 40, 20, 255],
+                depth: 30,
+                kind: None,
+                collide: true,
+            }),
+            Some(Subsoil {
+                color: [60, 40, 20, 255],
+                depth: 40,
+                kind: Some(BrickKind::Stud),
+                collide: false,
+            }),
+        ];
+        for scale in [1, 3, 7, 13, 21] {
+            for kind in [BrickKind::Brick, BrickKind::Tile, BrickKind::Stud] {
+                for snap in [false, true] {
+                    for subsoil in &subsoils {
+                        let options = GenOptions::builder()
+                            .scale(scale)
+                            .kind(kind)
+                            .snap(snap)
+                            .subsoil(*subsoil)
+                            .build()
+                            .unwrap();
+                        let case = format!("-v {} {:?} snap {} subsoil {:?}", scale, kind, snap, subsoil);
+                        let bricks = gen_opt_heightmap(&heightmap, &colormap, options, &NoProgress).unwrap();
+                        assert_no_overlaps(&bricks, &case);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bottom, top and color of each brick filling a pixel's column
+    type Column = Vec<(i32, i32, String)>;
+
+    /// Height ranges and colors filling each pixel's column, from the bottom
+    fn columns(bricks: &[Brick], pixel: i32) -> BTreeMap<(i32, i32), Column> {
+        let mut columns: BTreeMap<(i32, i32), Column> = BTreeMap::new();
+        for brick in bricks {
+            let (low, high) = bounds(brick);
+            for x in low[0] / pixel..high[0] / pixel {
+                for y in low[1] / pixel..high[1] / pixel {
+                    columns.entry((x, y)).or_default().push((low[2], high[2], format!("{:?}", brick.color)));
+                }
+            }
+        }
+        columns.values_mut().for_each(|column| column.sort());
+        columns
+    }
+
+    #[test]
+    fn blocks_fill_the_same_columns_as_the_whole_map() {
+        let (heightmap, colormap) = terrain(40);
+        let block_size = 16;
+        let pixel = 2 * GenOptions::default().size as i32;
+        for full_layers in [0, 120] {
+            let options = || GenOptions::builder().full_layers_above(full_layers).build().unwrap();
+            let whole = gen_opt_heightmap(&heightmap, &colormap, options(), &NoProgress).unwrap();
+            let blocks: Vec<Vec<Brick>> = gen_opt_heightmap_iter(&heightmap, &colormap, options(), block_size, &NoProgress)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(blocks.len(), 9, "a 40 pixel map has 3x3 blocks of 16");
+
+            // No brick crosses the edge of its block
+            let edge = block_size as i32 * pixel;
+            for brick in blocks.iter().flatten() {
+                let (low, high) = bounds(brick);
+                for axis in 0..2 {
+                    assert_eq!(low[axis] / edge, (high[axis] - 1) / edge, "{:?} crosses a block edge", (low, high));
+                }
+            }
+
+            // Blocks use the layer heights of the whole map, so every column is split the same way
+            let blocked: Vec<Brick> = blocks.into_iter().flatten().collect();
+            if full_layers == 0 {
+                // Full layers of close heights overlap in the whole map too, which the columns below compare
+                assert_no_overlaps(&blocked, "blocks");
+            }
+            assert_eq!(columns(&blocked, pixel), columns(&whole, pixel), "full layers above {}", full_layers);
+            assert!(blocked.len() >= whole.len());
+        }
+    }
+
+    #[test]
+    fn optimizers_fill_the_same_columns() {
+        // Terraces dotted with single pixel bumps, with rough terrain in one corner
+        let size = 48;
+        let heights = (0..size * size)
+            .map(|i| {
+                let (x, y) = (i % size, i / size);
+                if (x * 7 + y * 5) % 23 == 0 { 40 } else if x < 16 && y < 16 { 60 + (x * 37 + y * 11) % 97 } else { 20 + x / 13 * 15 }
+            })
+            .collect();
+        let colors = (0..size * size)
+            .map(|i| if i % size < 30 { [90, 140, 60, 255] } else { [120, 110, 80, 255] })
+            .collect();
+        let heightmap = HeightmapGrid::new(heights, (size, size)).unwrap();
+        let colormap = ColormapGrid::new(colors, (size, size), true).unwrap();
+        let pixel = 2 * GenOptions::default().size as i32;
+
+        let generate = |optimizer| {
+            let options = GenOptions::builder().optimizer(optimizer).build().unwrap();
+            gen_opt_heightmap_with_stats(&heightmap, &colormap, options, &NoProgress).unwrap()
+        };
+        let (quadtree, _) = generate(Optimizer::Quadtree);
+        let (greedy, greedy_stats) = generate(Optimizer::Greedy);
+        let (best, best_stats) = generate(Optimizer::Best);
+        assert_no_overlaps(&greedy, "greedy");
+        assert_no_overlaps(&best, "best");
+        assert_eq!(columns(&greedy, pixel), columns(&quadtree, pixel));
+        assert_eq!(columns(&best, pixel), columns(&quadtree, pixel));
+
+        // Greedy rectangles need fewer bricks on large flat areas, and best keeps the smaller
+        assert!(greedy_stats.greedy_merges.is_some());
+        assert!(greedy.len() < quadtree.len(), "greedy {} bricks, quadtree {}", greedy.len(), quadtree.len());
+        assert_eq!(best.len(), greedy.len());
+        let counts: Vec<_> = best_stats.optimizer_comparison.iter().map(|run| (run.optimizer, run.bricks)).collect();
+        assert_eq!(counts, [(Optimizer::Quadtree, quadtree.len()), (Optimizer::Greedy, greedy.len())]);
+    }
+
+    #[test]
+    fn bricks_stay_within_their_asset_limits() {
+        // Cliffs hundreds of units tall, next to flat ground wider than the largest brick
+        let size = 64;
+        let heights = (0..size * size).map(|i| if i % size < 8 { 100 + i / size * 7 } else { 30 }).collect();
+        let heightmap = HeightmapGrid::new(heights, (size, size)).unwrap();
+        let colormap = ColormapGrid::new(vec![[90, 140, 60, 255]; (size * size) as usize], (size, size), true).unwrap();
+
+        for (kind, pixel_size) in [(BrickKind::Brick, 1), (BrickKind::Tile, 2), (BrickKind::Micro, 3), (BrickKind::Stud, 1)] {
+            let options = GenOptions::builder().kind(kind).pixel_size(pixel_size).scale(4).build().unwrap();
+            let limits = options.limits();
+            let bricks = gen_opt_heightmap(&heightmap, &colormap, options, &NoProgress).unwrap();
+            assert_no_overlaps(&bricks, &format!("{:?}", kind));
+            for brick in &bricks {
+                let (x, y, z) = match brick.size {
+                    Size::Procedural(x, y, z) => (x, y, z),
+                    _ => panic!("generated bricks are procedural"),
+                };
+                let case = format!("{:?} brick {:?}", kind, (x, y, z));
+                assert!(x <= limits.max_xy && y <= limits.max_xy && z <= limits.max_z, "{} is too large", case);
+                assert!(x.is_multiple_of(limits.xy_step) && y.is_multiple_of(limits.xy_step), "{} is off the grid", case);
+                assert!(z.is_multiple_of(limits.z_step), "{} is off the height grid", case);
+            }
+        }
+
+        // Pixels wider than the largest brick or off the asset's grid are rejected
+        assert!(matches!(GenOptions::builder().pixel_size(101).build(), Err(GenError::LimitExceeded(_))));
+        assert!(GenOptions::builder().kind(BrickKind::Micro).pixel_size(500).build().is_ok());
+        let config: GenOptions = toml::from_str("size = 7").unwrap();
+        assert!(matches!(config.validate(), Err(GenError::LimitExceeded(_))));
+    }
+
+    #[test]
+    fn odd_sized_flat_maps_merge_to_their_edges() {
+        let green = [90, 140, 60, 255];
+        for (width, height) in [(13, 7), (7, 13), (1, 5), (33, 17)] {
+            let area = (width * height) as usize;
+            let heightmap = HeightmapGrid::new(vec![20; area], (width, height)).unwrap();
+            let colormap = ColormapGrid::new(vec![green; area], (width, height), true).unwrap();
+            let options = GenOptions::builder().threads(1).build().unwrap();
+            let (bricks, stats) = gen_opt_heightmap_with_stats(&heightmap, &colormap, options, &NoProgress).unwrap();
+            let case = format!("{}x{}", width, height);
+            assert_no_overlaps(&bricks, &case);
+
+            // Every pixel is covered, and nothing outside the map
+            let pixel = 2 * GenOptions::default().size as i32;
+            let mut covered = BTreeSet::new();
+            for brick in &bricks {
+                let (low, high) = bounds(brick);
+                for x in low[0] / pixel..high[0] / pixel {
+                    for y in low[1] / pixel..high[1] / pixel {
+                        covered.insert((x, y));
+                    }
+                }
+            }
+            let all: BTreeSet<_> = (0..width as i32).flat_map(|x| (0..height as i32).map(move |y| (x, y))).collect();
+            assert_eq!(covered, all, "{}: bricks cover the map", case);
+
+            // The quadtree alone merges the edges into one tile, where it used to leave
+            // 28 tiles of a 13x7 map for the line merges
+            let quad_merged: usize = stats.quad_merges.iter().map(|level| level.merged).sum();
+            assert_eq!(quad_merged, area - 1, "{}: the quadtree merges the map into one tile", case);
+            assert_eq!(bricks.len(), 1, "{}", case);
+        }
+    }
+
+    /// Box of one color for `merge_boxes`
+    fn cell(corner: (u32, u32), size: (u32, u32), color: [u8; 4]) -> CellRect {
+        CellRect {
+            corner,
+            size,
+            color,
+            levels: 1,
+        }
+    }
+
+    /// Corner, size and color of a merged brick
+    type Rect = ((u32, u32), (u32, u32), [u8; 4]);
+
+    /// Color of every pixel covered by the given rectangles, panicking if two overlap
+    fn coverage(rects: &[Rect]) -> BTreeMap<(u32, u32), [u8; 4]> {
+        let mut pixels = BTreeMap::new();
+        for &(corner, size, color) in rects {
+            for x in corner.0..corner.0 + size.0 {
+                for y in corner.1..corner.1 + size.1 {
+                    assert!(pixels.insert((x, y), color).is_none(), "({}, {}) is covered twice", x, y);
+                }
+            }
+        }
+        pixels
+    }
+
+    /// Merge `boxes` and check the bricks cover the same pixels in the same colors, within `max_tiles`
+    ///
+    /// # Returns
+    /// * The merged bricks, and the number of boxes `merge_boxes` says it merged away
+    fn merge(boxes: &[CellRect], max_tiles: u32) -> (Vec<Rect>, usize) {
+        let refs: Vec<&CellRect> = boxes.iter().collect();
+        let mut bricks = vec![];
+        let merged = merge_boxes(&refs, 64, max_tiles, |corner, size, color| bricks.push((corner, size, color)));
+
+        let before: Vec<_> = boxes.iter().map(|b| (b.corner, b.size, b.color)).collect();
+        assert_eq!(coverage(&bricks), coverage(&before));
+        assert!(bricks.iter().all(|&(_, size, _)| size.0 <= max_tiles && size.1 <= max_tiles));
+        assert_eq!(merged, boxes.len() - bricks.len());
+        (bricks, merged)
+    }
+
+    #[test]
+    fn merge_boxes_joins_boxes_of_one_color() {
+        let green = [90, 140, 60, 255];
+        let boxes: Vec<CellRect> = (0..16).map(|i| cell((3 + i % 4, 5 + i / 4), (1, 1), green)).collect();
+        let (bricks, merged) = merge(&boxes, 8);
+        assert_eq!(bricks, vec![((3, 5), (4, 4), green)]);
+        assert_eq!(merged, 15);
+
+        // Boxes of several pixels are split into pixels and merged too
+        let boxes = [cell((0, 0), (2, 3), green), cell((2, 0), (1, 3), green), cell((0, 3), (3, 1), green)];
+        assert_eq!(merge(&boxes, 8).0, vec![((0, 0), (3, 4), green)]);
+    }
+
+    #[test]
+    fn merge_boxes_keeps_colors_apart_and_bricks_within_the_limit() {
+        let (green, brown) = ([90, 140, 60, 255], [120, 110, 80, 255]);
+        let boxes: Vec<CellRect> = (0..20)
+            .map(|i| cell((i % 10, i / 10), (1, 1), if i % 10 < 5 { green } else { brown }))
+            .collect();
+        let (bricks, _) = merge(&boxes, 8);
+        assert_eq!(bricks.len(), 2);
+
+        // A row longer than the limit is split
+        let boxes: Vec<CellRect> = (0..10).map(|x| cell((x, 0), (1, 1), green)).collect();
+        let (bricks, merged) = merge(&boxes, 4);
+        assert_eq!(bricks.len(), 3);
+        assert_eq!(merged, 7);
+
+        // Scattered boxes that don't touch stay as they are
+        let boxes = [cell((0, 0), (1, 1), green), cell((5, 7), (2, 1), green), cell((20, 3), (1, 2), brown)];
+        assert_eq!(merge(&boxes, 8).1, 0);
+        assert_eq!(merge(&[], 8), (vec![], 0));
+    }
+
+    #[test]
+    fn progress_counts_strips_and_only_moves_forward() {
+        let (heightmap, colormap) = terrain(64);
+        for optimizer in [Optimizer::Quadtree, Optimizer::Greedy, Optimizer::Best] {
+            let updates = std::cell::RefCell::new(vec![]);
+            let progress = |update: &ProgressUpdate| updates.borrow_mut().push(*update);
+            let options = GenOptions::builder()
+                .optimizer(optimizer)
+                .full_layers_above(120)
+                .threads(4)
+                .build()
+                .unwrap();
+            gen_opt_heightmap(&heightmap, &colormap, options, &progress).unwrap();
+
+            let updates = updates.into_inner();
+            let building: Vec<_> = updates.iter().filter(|u| u.phase == Phase::BuildingTree).collect();
+            assert!(building.len() > 2, "{}: tiles are reported strip by strip", optimizer);
+            assert!(building.windows(2).all(|w| w[0].done < w[1].done));
+            assert!(
+                updates.windows(2).all(|w| w[0].fraction <= w[1].fraction),
+                "{}: progress went back",
+                optimizer
+            );
+            assert_eq!(updates.last().map(|u| u.fraction), Some(1.0), "{}", optimizer);
+        }
+    }
+
+    #[test]
+    fn layers_only_store_the_tiles_they_cover() {
+        // Low ground with a stepped hill in one corner, only the hill gets layers
+        let size = 48;
+        let heights = (0..size * size)
+            .map(|i| {
+                let (x, y) = (i % size, i / size);
+                match (x < 6 && y < 6, x < 3 && y < 3) {
+                    (_, true) => 30,
+                    (true, false) => 20,
+                    _ => 5 + (x + y) % 3,
+                }
+            })
+            .collect();
+        let heightmap = HeightmapGrid::new(heights, (size, size)).unwrap();
+        let colormap = ColormapGrid::new(vec![[90, 140, 60, 255]; (size * size) as usize], (size, size), true).unwrap();
+
+        let layers = LayerHeights::new(&heightmap, &colormap, 10, 2).unwrap();
+        assert_eq!(layers.sorted_heights, vec![7, 20, 30]);
+        let tree = QuadTree::with_layers(&heightmap, &colormap, layers, 2).unwrap();
+        assert_eq!(tree.tiles.row.len(), (size * size) as usize);
+        let layer_tiles: Vec<usize> = tree.height_layers.iter().map(|layer| layer.row.len()).collect();
+        assert_eq!(layer_tiles, vec![36, 9]);
+        assert!(tree.height_layers.iter().all(|layer| layer.col_start.len() == size as usize + 1));
+    }
+
+    /// Cancels generation once a phase is reported, and keeps every phase it was sent
+    struct CancelAt {
+        phase: Phase,
+        cancel: CancelToken,
+        seen: std::cell::RefCell<Vec<Phase>>,
+    }
+
+    impl Progress for CancelAt {
+        fn update(&self, update: &ProgressUpdate) {
+            self.seen.borrow_mut().push(update.phase);
+            if update.phase == self.phase {
+                self.cancel.cancel();
+            }
+        }
+
+        fn is_cancelled(&self) -> bool {
+            self.cancel.is_cancelled()
+        }
+    }
+
+    #[test]
+    fn cancelling_stops_building_tiles() {
+        let (heightmap, colormap) = terrain(64);
+        let progress = CancelAt {
+            phase: Phase::BuildingTree,
+            cancel: CancelToken::new(),
+            seen: Default::default(),
+        };
+        let options = GenOptions::builder().threads(4).build().unwrap();
+        let result = gen_opt_heightmap(&heightmap, &colormap, options, &progress);
+        assert!(matches!(result, Err(GenError::Cancelled)));
+
+        // Nothing is reported after the first strip of tiles
+        assert_eq!(progress.seen.into_inner(), vec![Phase::BuildingTree]);
+    }
 }
@@ -0,0 +1,222 @@
+// Import Brickadia save file structures
+use brickadia::save::{Brick, BrickColor, Direction, Rotation, SaveData, Size};
+// Import image buffers for the output maps
+use image::{ImageBuffer, Luma, Rgba, RgbaImage};
+
+// Import color conversion utility from our util module, and our error type
+use crate::{error::GenError, util::to_srgb_gamma};
+
+/// 16-bit grayscale heightmap image
+pub type HeightImage = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// Largest image (in pixels) that will be rasterized before asking for a coarser resolution
+const MAX_PIXELS: u64 = 16384 * 16384;
+
+/// Options controlling how a save is rasterized back into images
+/// These mirror the matching `GenOptions` fields so the images regenerate the same terrain
+pub struct RasterOptions {
+    /// Size of each pixel in Brickadia units (half the pixel width, 5 units per stud)
+    pub size: u32,
+    /// Vertical scale divisor for height values
+    pub scale: u32,
+    /// Whether to write linear RGB colors (true) or convert them to sRGB (false)
+    pub lrgb: bool,
+}
+
+/// Get the half-extents of a brick along the world X, Y and Z axes
+/// Returns None for bricks without a procedural size
+fn world_half_extents(brick: &Brick) -> Option<(i64, i64, i64)> {
+    let (x, y, z) = match brick.size {
+        Size::Procedural(x, y, z) => (x as i64, y as i64, z as i64),
+        Size::Empty => return None,
+    };
+
+    // Rotating a quarter turn around the brick's up axis swaps its two other axes
+    let quarter = matches!(brick.rotation, Rotation::Deg90 | Rotation::Deg270);
+    let (x, y) = if quarter { (y, x) } else { (x, y) };
+
+    // Sideways bricks have their up axis pointing along X or Y
+    Some(match brick.direction {
+        Direction::XPositive | Direction::XNegative => (z, y, x),
+        Direction::YPositive | Direction::YNegative => (x, z, y),
+        Direction::ZPositive | Direction::ZNegative => (x, y, z),
+    })
+}
+
+/// Divide rounding towards positive infinity
+fn div_ceil(a: i64, b: i64) -> i64 {
+    -((-a).div_euclid(b))
+}
+
+/// Rasterize the top surface of a save's bricks into a heightmap and colormap
+/// Each pixel takes the height and color of the highest brick covering its center
+///
+/// # Arguments
+/// * `save` - The save to rasterize
+/// * `options` - Pixel size, vertical scale and color space of the output images
+///
+/// # Returns
+/// * `Ok((HeightImage, RgbaImage))` - 16-bit heightmap and RGBA colormap of the same size
+/// * `Err(GenError)` - `Invalid` if the save has no procedural bricks, `LimitExceeded` if the images would be too large
+pub fn save_to_maps(save: &SaveData, options: &RasterOptions) -> Result<(HeightImage, RgbaImage), GenError> {
+    let pixel = options.size.max(1) as i64 * 2;  // Width of a pixel in Brickadia units
+    let scale = options.scale.max(1) as i64;
+
+    // Collect the footprint (min x, min y, max x, max y), top and brick for every usable brick
+    let footprints: Vec<_> = save
+        .bricks
+        .iter()
+        .filter_map(|b| {
+            let (sx, sy, sz) = world_half_extents(b)?;
+            let (px, py, pz) = (b.position.0 as i64, b.position.1 as i64, b.position.2 as i64);
+            Some(((px - sx, py - sy, px + sx, py + sy), pz + sz, b))
+        })
+        .collect();
+
+    if footprints.is_empty() {
+        return Err(GenError::Invalid("Save has no procedural bricks to rasterize".to_string()));
+    }
+
+    // The grid starts at the lowest brick edge, which is 0 for generated saves
+    let (min_x, min_y, max_x, max_y) = footprints.iter().fold(
+        (i64::MAX, i64::MAX, i64::MIN, i64::MIN),
+        |(ax, ay, bx, by), ((x0, y0, x1, y1), _, _)| (ax.min(*x0), ay.min(*y0), bx.max(*x1), by.max(*y1)),
+    );
+    let width = div_ceil(max_x - min_x, pixel).max(1);
+    let height = div_ceil(max_y - min_y, pixel).max(1);
+    if (width * height) as u64 > MAX_PIXELS {
+        return Err(GenError::LimitExceeded(format!(
+            "Rasterized image would be {}x{} pixels, use a larger size",
+            width, height
+        )));
+    }
+
+    let mut tops = vec![i64::MIN; (width * height) as usize];
+    let mut heightmap = HeightImage::new(width as u32, height as u32);
+    let mut colormap = RgbaImage::new(width as u32, height as u32);
+
+    // Pixel range whose centers fall inside [from, to) along one axis
+    let covered = |from: i64, to: i64, origin: i64, len: i64| {
+        let first = div_ceil(from - origin - pixel / 2, pixel).max(0);
+        let last = div_ceil(to - origin - pixel / 2, pixel).min(len);
+        if first < last {
+            first..last
+        } else {
+            // Bricks smaller than a pixel still claim the pixel containing their center
+            let center = (((from + to) / 2 - origin) / pixel).clamp(0, len - 1);
+            center..center + 1
+        }
+    };
+
+    for ((x0, y0, x1, y1), top, brick) in footprints {
+        let color = match &brick.color {
+            BrickColor::Unique(c) => [c.r, c.g, c.b, c.a],
+            BrickColor::Index(i) => save
+                .header2
+                .colors
+                .get(*i as usize)
+                .map(|c| [c.r, c.g, c.b, c.a])
+                .unwrap_or([255, 255, 255, 255]),
+        };
+        let color = if options.lrgb {
+            color
+        } else {
+            [to_srgb_gamma(color[0]), to_srgb_gamma(color[1]), to_srgb_gamma(color[2]), color[3]]
+        };
+
+        // Generated bricks have their top at `scale * height + 4`
+        let value = ((top - 4) / scale).clamp(0, u16::MAX as i64) as u16;
+
+        for x in covered(x0, x1, min_x, width) {
+            for y in covered(y0, y1, min_y, height) {
+                let i = (x + y * width) as usize;
+                if top > tops[i] {
+                    tops[i] = top;
+                    heightmap.put_pixel(x as u32, y as u32, Luma([value]));
+                    colormap.put_pixel(x as u32, y as u32, Rgba(color));
+                }
+            }
+        }
+    }
+
+    Ok((heightmap, colormap))
+}
+
+/// Render a top-down preview of a save, the colors of its top surface shaded by the slope of the terrain
+/// Light comes from the top left, so slopes facing it are brighter and slopes facing away are darker
+///
+/// # Arguments
+/// * `save` - The save to render
+/// * `options` - Pixel size and vertical scale of the save, and the color space of the image
+///
+/// # Returns
+/// * `Ok(RgbaImage)` - One pixel per pixel of the generated map, transparent where there are no bricks
+/// * `Err(GenError)` - If the save can't be rasterized, see `save_to_maps`
+pub fn preview_image(save: &SaveData, options: &RasterOptions) -> Result<RgbaImage, GenError> {
+    let (heightmap, mut colormap) = save_to_maps(save, options)?;
+    let (width, height) = heightmap.dimensions();
+    // Heights in Brickadia units, per unit of horizontal distance
+    let rise = options.scale.max(1) as f32 / (options.size.max(1) as f32 * 2.0);
+    let at = |x: i64, y: i64| {
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        heightmap.get_pixel(x, y).0[0] as f32 * rise
+    };
+    let light = [-1.0f32 / 3f32.sqrt(), -1.0 / 3f32.sqrt(), 1.0 / 3f32.sqrt()];
+
+    for (x, y, pixel) in colormap.enumerate_pixels_mut() {
+        let (x, y) = (x as i64, y as i64);
+        let dx = (at(x + 1, y) - at(x - 1, y)) / 2.0;
+        let dy = (at(x, y + 1) - at(x, y - 1)) / 2.0;
+        let len = (dx * dx + dy * dy + 1.0).sqrt();
+        let normal = [-dx / len, -dy / len, 1.0 / len];
+        let lit = normal.iter().zip(light.iter()).map(|(n, l)| n * l).sum::<f32>();
+        // Flat ground is lit at 1/sqrt(3), keep it at its own color
+        let shade = (lit * 3f32.sqrt()).clamp(0.35, 1.3);
+        for c in pixel.0.iter_mut().take(3) {
+            *c = (*c as f32 * shade).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    Ok(colormap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{map::*, progress::NoProgress, quad::*, util::*};
+
+    #[test]
+    fn generated_saves_rasterize_back_to_their_maps() {
+        let (width, height) = (12, 9);
+        let heights: Vec<u32> = (0..width * height).map(|i| 1 + (i % width * 5 + i / width * 3) % 30).collect();
+        let colors: Vec<[u8; 4]> = (0..width * height).map(|i| [(i * 2) as u8, 90, (i % 5 * 40) as u8, 255]).collect();
+        let heightmap = HeightmapGrid::new(heights.clone(), (width, height)).unwrap();
+        let colormap = ColormapGrid::new(colors.clone(), (width, height), true).unwrap();
+
+        for (kind, pixel_size, scale) in [(BrickKind::Brick, 1, 1), (BrickKind::Brick, 2, 4), (BrickKind::Micro, 3, 2)] {
+            let options = GenOptions::builder().kind(kind).pixel_size(pixel_size).scale(scale).build().unwrap();
+            let raster = RasterOptions {
+                size: options.size,
+                scale,
+                lrgb: true,
+            };
+            let bricks = gen_opt_heightmap(&heightmap, &colormap, options, &NoProgress).unwrap();
+            let save = bricks_to_save(bricks, "0c9c6e35-3a8c-4e4f-a11f-2b7b1c4c6f6e".to_string(), "Raster".to_string());
+            let (height_image, color_image) = save_to_maps(&save, &raster).unwrap();
+
+            let case = format!("{:?} {} -v {}", kind, pixel_size, scale);
+            assert_eq!(height_image.dimensions(), (width, height), "{}", case);
+            let read_heights: Vec<u32> = height_image.pixels().map(|p| p.0[0] as u32).collect();
+            let read_colors: Vec<[u8; 4]> = color_image.pixels().map(|p| p.0).collect();
+            assert_eq!(read_heights, heights, "{}", case);
+            assert_eq!(read_colors, colors, "{}", case);
+            assert_eq!(preview_image(&save, &raster).unwrap().dimensions(), (width, height));
+        }
+
+        // A save without procedural bricks has nothing to rasterize
+        let empty = bricks_to_save(vec![], "0c9c6e35-3a8c-4e4f-a11f-2b7b1c4c6f6e".to_string(), "Raster".to_string());
+        let raster = RasterOptions { size: 5, scale: 1, lrgb: true };
+        assert!(save_to_maps(&empty, &raster).is_err());
+    }
+}
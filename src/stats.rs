@@ -0,0 +1,580 @@
+// Import Brickadia save file structures
+use brickadia::save::{Brick, BrickColor, Size};
+// Import serde for writing JSON reports
+use serde::Serialize;
+// Import standard library items
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    time::Instant,
+};
+
+// Import asset and material name tables from our util module
+use crate::{budget::BudgetChoice, incremental::BlockReuse, map::*, util::*};
+
+/// Number of brick sizes listed in the printed report (the JSON report has all of them)
+const PRINTED_SIZES: usize = 10;
+
+/// Bytes written for the save headers, owner list and name tables (roughly constant)
+const HEADER_BYTES: u64 = 512;
+
+/// Number of merges done by one quadtree optimization level
+#[derive(Debug, Clone, Serialize)]
+pub struct QuadLevelStats {
+    /// Width in tiles of the tiles merged at this level (1, 2, 4, ...)
+    pub tile_size: u32,
+    /// Number of tiles removed by merging
+    pub merged: usize,
+}
+
+/// Time spent in one phase of generation
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseTiming {
+    /// Name of the phase
+    pub name: String,
+    /// Wall clock time in seconds
+    pub seconds: f64,
+}
+
+/// Result of one optimizer when `Optimizer::Best` compares them
+#[derive(Debug, Clone, Serialize)]
+pub struct OptimizerRun {
+    /// Optimizer that was run
+    pub optimizer: Optimizer,
+    /// Number of bricks it generated
+    pub bricks: usize,
+    /// Wall clock time in seconds spent optimizing and emitting bricks
+    pub seconds: f64,
+    /// Number of times its bricks were kept (once per block in block mode)
+    pub kept: usize,
+}
+
+/// Axis-aligned bounds of the generated bricks, in studs
+#[derive(Debug, Clone, Serialize)]
+pub struct Bounds {
+    /// Minimum corner (x, y, z)
+    pub min: [f64; 3],
+    /// Maximum corner (x, y, z)
+    pub max: [f64; 3],
+}
+
+/// Statistics collected while generating bricks
+/// Printed as a table after generation, and optionally written as JSON with `--report`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GenStats {
+    /// Width and height of the input images in pixels
+    pub input_size: (u32, u32),
+    /// Total number of bricks generated
+    pub bricks: usize,
+    /// Brick counts per asset name
+    pub bricks_by_asset: BTreeMap<String, usize>,
+    /// Brick counts per material name
+    pub bricks_by_material: BTreeMap<String, usize>,
+    /// Brick counts per procedural size, keyed as "XxYxZ" in Brickadia units
+    pub bricks_by_size: BTreeMap<String, usize>,
+    /// Brick counts per layer (the base layer first, then each full layer)
+    pub bricks_by_layer: Vec<usize>,
+    /// Tiles removed at each quadtree optimization level
+    pub quad_merges: Vec<QuadLevelStats>,
+    /// Tiles removed by each line optimization pass
+    pub line_merges: Vec<usize>,
+    /// Underground cells of tall columns removed by merging them with their neighbors
+    pub column_merges: usize,
+    /// Tiles removed by the greedy rectangle optimizer, if it was used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub greedy_merges: Option<usize>,
+    /// Optimizer used to merge tiles
+    pub optimizer: Optimizer,
+    /// Brick counts of each optimizer when the best one was picked
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub optimizer_comparison: Vec<OptimizerRun>,
+    /// Number of distinct brick colors
+    pub unique_colors: usize,
+    /// Bounds of all bricks in studs, None if there are no bricks
+    pub bounds: Option<Bounds>,
+    /// Estimated size of the save file in bytes, before compression
+    pub estimated_save_size: u64,
+    /// Time spent in each phase
+    pub phases: Vec<PhaseTiming>,
+    /// Settings chosen by budget mode (`--max-bricks`), if it was used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<BudgetChoice>,
+    /// Blocks that were generated or reused by incremental regeneration, if it was used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub incremental: Option<BlockReuse>,
+    /// Distinct brick colors, kept so stats of separate blocks can be merged
+    #[serde(skip)]
+    colors: HashSet<[u8; 4]>,
+}
+
+/// Number of bits brickadia uses to write `value` as an unsigned int bounded by `max`
+fn uint_bits(value: u32, max: u32) -> u64 {
+    let (mut bits, mut new_value, mut mask) = (0, 0_u32, 1_u32);
+    while new_value.saturating_add(mask) < max && mask != 0 {
+        bits += 1;
+        new_value |= value & mask;
+        mask <<= 1;
+    }
+    bits
+}
+
+/// Number of bits brickadia uses to write `value` as a packed unsigned int (8 bits per 7 bit group)
+fn packed_bits(mut value: u32) -> u64 {
+    let mut bits = 8;
+    while value >= 0x80 {
+        value >>= 7;
+        bits += 8;
+    }
+    bits
+}
+
+/// Number of bytes a brick takes in the (uncompressed) bricks section of a save
+fn brick_bytes(brick: &Brick) -> u64 {
+    let packed_int = |v: i32| packed_bits((v.unsigned_abs() << 1) | u32::from(v >= 0));
+    let mut bits = uint_bits(brick.asset_name_index, BRICK_ASSETS.len().max(2) as u32) + 1;
+    if let Size::Procedural(x, y, z) = brick.size {
+        bits += packed_bits(x) + packed_bits(y) + packed_bits(z);
+    }
+    bits += packed_int(brick.position.0) + packed_int(brick.position.1) + packed_int(brick.position.2);
+    bits += 24 + 4 + 1;  // orientation, collision flags, visibility
+    bits += uint_bits(brick.material_index, MATERIALS.len().max(2) as u32);
+    bits += 1 + 11;  // physical material index (2 entries), material intensity
+    bits += match brick.color {
+        BrickColor::Unique(_) => 1 + 24,
+        BrickColor::Index(i) => 1 + uint_bits(i, 2),
+    };
+    bits += packed_bits(brick.owner_index);
+    // Every brick starts byte aligned
+    bits.div_ceil(8)
+}
+
+/// Add counts element by element, growing `into` if `from` is longer
+fn add_counts(into: &mut Vec<usize>, from: &[usize]) {
+    if into.len() < from.len() {
+        into.resize(from.len(), 0);
+    }
+    for (a, b) in into.iter_mut().zip(from) {
+        *a += b;
+    }
+}
+
+impl GenStats {
+    /// Create empty stats for an input of the given size in pixels
+    pub fn new(input_size: (u32, u32)) -> Self {
+        GenStats {
+            input_size,
+            ..Default::default()
+        }
+    }
+
+    /// Record how long a phase took, measured from `start` until now
+    /// Time is added to an earlier phase with the same name
+    pub fn record_phase(&mut self, name: &str, start: Instant) {
+        self.add_phase(name, start.elapsed().as_secs_f64());
+    }
+
+    /// Add time to a phase, creating it after the existing phases if it's new
+    fn add_phase(&mut self, name: &str, seconds: f64) {
+        match self.phases.iter_mut().find(|p| p.name == name) {
+            Some(phase) => phase.seconds += seconds,
+            None => self.phases.push(PhaseTiming {
+                name: name.to_string(),
+                seconds,
+            }),
+        }
+    }
+
+    /// Add only the phase timings of other stats, for work whose results were thrown away
+    pub fn merge_phases(&mut self, other: &GenStats) {
+        for phase in &other.phases {
+            self.add_phase(&phase.name, phase.seconds);
+        }
+    }
+
+    /// Add the stats of another block of the same map to these stats
+    /// Counts, merges and timings are summed, bounds and colors are combined
+    ///
+    /// # Arguments
+    /// * `other` - Stats of one block, the input size is not changed
+    pub fn merge(&mut self, other: GenStats) {
+        // Each save only has one set of headers
+        let header = if self.estimated_save_size == 0 { 0 } else { HEADER_BYTES };
+
+        self.bricks += other.bricks;
+        for (asset, count) in other.bricks_by_asset {
+            *self.bricks_by_asset.entry(asset).or_default() += count;
+        }
+        for (material, count) in other.bricks_by_material {
+            *self.bricks_by_material.entry(material).or_default() += count;
+        }
+        for (size, count) in other.bricks_by_size {
+            *self.bricks_by_size.entry(size).or_default() += count;
+        }
+        add_counts(&mut self.bricks_by_layer, &other.bricks_by_layer);
+        add_counts(&mut self.line_merges, &other.line_merges);
+        self.column_merges += other.column_merges;
+
+        self.greedy_merges = match (self.greedy_merges, other.greedy_merges) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        self.optimizer = other.optimizer;
+        for run in other.optimizer_comparison {
+            match self.optimizer_comparison.iter_mut().find(|r| r.optimizer == run.optimizer) {
+                Some(r) => {
+                    r.bricks += run.bricks;
+                    r.seconds += run.seconds;
+                    r.kept += run.kept;
+                }
+                None => self.optimizer_comparison.push(run),
+            }
+        }
+
+        // Quadtree levels line up by tile size
+        for level in other.quad_merges {
+            match self.quad_merges.iter_mut().find(|l| l.tile_size == level.tile_size) {
+                Some(l) => l.merged += level.merged,
+                None => self.quad_merges.push(level),
+            }
+        }
+
+        self.colors.extend(other.colors);
+        self.unique_colors = self.colors.len();
+        self.bounds = match (self.bounds.take(), other.bounds) {
+            (Some(a), Some(b)) => Some(Bounds {
+                min: [0, 1, 2].map(|i| a.min[i].min(b.min[i])),
+                max: [0, 1, 2].map(|i| a.max[i].max(b.max[i])),
+            }),
+            (a, b) => a.or(b),
+        };
+        self.estimated_save_size += other.estimated_save_size - header;
+
+        for phase in other.phases {
+            self.add_phase(&phase.name, phase.seconds);
+        }
+    }
+
+    /// Record brick counts, colors, bounds and save size for the generated bricks
+    ///
+    /// # Arguments
+    /// * `layers` - Generated bricks, grouped by layer (base layer first)
+    pub fn record_bricks(&mut self, layers: &[Vec<Brick>]) {
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+
+        self.bricks_by_layer = layers.iter().map(|l| l.len()).collect();
+        self.bricks = self.bricks_by_layer.iter().sum();
+        self.estimated_save_size = HEADER_BYTES;
+
+        for brick in layers.iter().flatten() {
+            let asset = BRICK_ASSETS.get(brick.asset_name_index as usize).unwrap_or(&"Unknown");
+            *self.bricks_by_asset.entry(asset.to_string()).or_default() += 1;
+            let material = MATERIALS.get(brick.material_index as usize).unwrap_or(&"Unknown");
+            *self.bricks_by_material.entry(material.to_string()).or_default() += 1;
+
+            if let Size::Procedural(x, y, z) = brick.size {
+                let half = [x as f64, y as f64, z as f64];
+                *self
+                    .bricks_by_size
+                    .entry(format!("{}x{}x{}", half[0] * 2.0, half[1] * 2.0, half[2] * 2.0))
+                    .or_default() += 1;
+
+                // Brickadia has 10 units per stud
+                let pos = [brick.position.0 as f64, brick.position.1 as f64, brick.position.2 as f64];
+                for axis in 0..3 {
+                    min[axis] = min[axis].min((pos[axis] - half[axis]) / 10.0);
+                    max[axis] = max[axis].max((pos[axis] + half[axis]) / 10.0);
+                }
+            }
+
+            if let BrickColor::Unique(c) = &brick.color {
+                self.colors.insert([c.r, c.g, c.b, c.a]);
+            }
+            self.estimated_save_size += brick_bytes(brick);
+        }
+
+        self.unique_colors = self.colors.len();
+        self.bounds = if min[0] <= max[0] {
+            Some(Bounds { min, max })
+        } else {
+            None
+        };
+    }
+}
+
+/// Print the stats as a table for the log
+impl fmt::Display for GenStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let area = self.input_size.0 as u64 * self.input_size.1 as u64;
+        writeln!(f, "Generation report")?;
+        writeln!(
+            f,
+            "  {:<24}{}x{} ({} pixels)",
+            "Input", self.input_size.0, self.input_size.1, area
+        )?;
+        writeln!(f, "  {:<24}{}", "Bricks", self.bricks)?;
+        writeln!(f, "  {:<24}{}", "Unique colors", self.unique_colors)?;
+        if let Some(b) = &self.bounds {
+            writeln!(
+                f,
+                "  {:<24}{:.1} x {:.1} x {:.1} studs (from {:.1}, {:.1}, {:.1})",
+                "Bounds",
+                b.max[0] - b.min[0],
+                b.max[1] - b.min[1],
+                b.max[2] - b.min[2],
+                b.min[0],
+                b.min[1],
+                b.min[2]
+            )?;
+        }
+        writeln!(
+            f,
+            "  {:<24}{:.1} KiB (uncompressed)",
+            "Estimated save size",
+            self.estimated_save_size as f64 / 1024.0
+        )?;
+
+        if let Some(budget) = &self.budget {
+            writeln!(
+                f,
+                "  {:<24}{}x downsample, color step {}, height step {} ({} attempts, budget {})",
+                "Budget settings",
+                budget.chosen.downsample,
+                budget.chosen.color_step,
+                budget.chosen.height_step,
+                budget.attempts.len(),
+                budget.max_bricks
+            )?;
+        }
+
+        if let Some(reuse) = &self.incremental {
+            writeln!(
+                f,
+                "  {:<24}{} of {} changed, {} reused, {} generated again, {} left out",
+                "Blocks",
+                reuse.changed,
+                reuse.blocks,
+                reuse.reused,
+                reuse.regenerated,
+                reuse.skipped
+            )?;
+        }
+
+        writeln!(f, "  {:<24}{}", "Optimizer", self.optimizer)?;
+        if !self.optimizer_comparison.is_empty() {
+            let runs: usize = self.optimizer_comparison.iter().map(|r| r.kept).sum();
+            writeln!(f, "  Optimizer comparison")?;
+            for run in &self.optimizer_comparison {
+                writeln!(
+                    f,
+                    "    {:<22}{} bricks in {:.3}s, kept {}/{}",
+                    run.optimizer.to_string(),
+                    run.bricks,
+                    run.seconds,
+                    run.kept,
+                    runs
+                )?;
+            }
+        }
+
+        if let Some(merged) = self.greedy_merges {
+            writeln!(f, "  {:<24}{}", "Greedy merges", merged)?;
+        }
+        if !self.quad_merges.is_empty() {
+            writeln!(f, "  Quadtree merges")?;
+            for level in &self.quad_merges {
+                writeln!(f, "    {:<22}{}", format!("{}x", level.tile_size), level.merged)?;
+            }
+        }
+        if self.column_merges > 0 {
+            writeln!(f, "  {:<24}{}", "Column merges", self.column_merges)?;
+        }
+        if !self.line_merges.is_empty() {
+            writeln!(f, "  Line merges")?;
+            for (i, merged) in self.line_merges.iter().enumerate() {
+                writeln!(f, "    {:<22}{}", format!("pass {}", i + 1), merged)?;
+            }
+        }
+
+        writeln!(f, "  Bricks by asset")?;
+        for (asset, count) in &self.bricks_by_asset {
+            writeln!(f, "    {:<22}{}", asset, count)?;
+        }
+        writeln!(f, "  Bricks by material")?;
+        for (material, count) in &self.bricks_by_material {
+            writeln!(f, "    {:<22}{}", material, count)?;
+        }
+        if self.bricks_by_layer.len() > 1 {
+            writeln!(f, "  Bricks by layer")?;
+            for (i, count) in self.bricks_by_layer.iter().enumerate() {
+                let name = if i == 0 { "base".to_string() } else { format!("layer {}", i) };
+                writeln!(f, "    {:<22}{}", name, count)?;
+            }
+        }
+
+        // Only the most common sizes, there can be thousands of them
+        let mut sizes: Vec<_> = self.bricks_by_size.iter().collect();
+        sizes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(
+            f,
+            "  Bricks by size (top {} of {})",
+            sizes.len().min(PRINTED_SIZES),
+            sizes.len()
+        )?;
+        for (size, count) in sizes.into_iter().take(PRINTED_SIZES) {
+            writeln!(f, "    {:<22}{}", size, count)?;
+        }
+
+        writeln!(f, "  Phase timings")?;
+        for phase in &self.phases {
+            writeln!(f, "    {:<22}{:.3}s", phase.name, phase.seconds)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a heightmap and colormap hold, found without generating any bricks
+#[derive(Debug, Clone, Serialize)]
+pub struct MapInfo {
+    /// Width and height of the heightmap in pixels
+    pub heightmap_size: (u32, u32),
+    /// Width and height of the colormap in pixels
+    pub colormap_size: (u32, u32),
+    /// Lowest and highest height value, before the vertical scale
+    pub height_range: (u32, u32),
+    /// Number of distinct colormap colors
+    pub unique_colors: usize,
+    /// Number of fully transparent colormap pixels
+    pub transparent_pixels: u64,
+}
+
+impl MapInfo {
+    /// Read every pixel of a heightmap and colormap
+    pub fn new(heightmap: &dyn Heightmap, colormap: &dyn Colormap) -> Self {
+        let (width, height) = heightmap.size();
+        let height_range = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| heightmap.at(x, y))
+            .fold(None, |range: Option<(u32, u32)>, h| match range {
+                Some((lo, hi)) => Some((lo.min(h), hi.max(h))),
+                None => Some((h, h)),
+            })
+            .unwrap_or((0, 0));
+
+        let (width, height) = colormap.size();
+        let mut colors = HashSet::new();
+        let mut transparent_pixels = 0;
+        for y in 0..height {
+            for x in 0..width {
+                let color = colormap.at(x, y);
+                if color[3] == 0 {
+                    transparent_pixels += 1;
+                }
+                colors.insert(color);
+            }
+        }
+
+        MapInfo {
+            heightmap_size: heightmap.size(),
+            colormap_size: colormap.size(),
+            height_range,
+            unique_colors: colors.len(),
+            transparent_pixels,
+        }
+    }
+}
+
+/// Table printed by `heightmap inspect`
+impl fmt::Display for MapInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Map")?;
+        writeln!(f, "  {:<24}{}x{}", "Heightmap", self.heightmap_size.0, self.heightmap_size.1)?;
+        writeln!(f, "  {:<24}{}x{}", "Colormap", self.colormap_size.0, self.colormap_size.1)?;
+        writeln!(f, "  {:<24}{} to {}", "Heights", self.height_range.0, self.height_range.1)?;
+        writeln!(f, "  {:<24}{}", "Unique colors", self.unique_colors)?;
+        writeln!(f, "  {:<24}{}", "Transparent pixels", self.transparent_pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{progress::NoProgress, quad::gen_opt_heightmap_with_stats};
+
+    /// Terraced map in three colors, with some heights above the full layer height
+    fn terraces(size: u32) -> (HeightmapGrid, ColormapGrid) {
+        let heights = (0..size * size).map(|i| 10 + (i % size) / 4 * 6 + (i / size) % 3).collect();
+        let palette = [[90, 140, 60, 255], [120, 110, 80, 255], [200, 200, 210, 255]];
+        let colors = (0..size * size).map(|i| palette[((i / size) / 5 % 3) as usize]).collect();
+        (
+            HeightmapGrid::new(heights, (size, size)).unwrap(),
+            ColormapGrid::new(colors, (size, size), true).unwrap(),
+        )
+    }
+
+    #[test]
+    fn reports_count_the_generated_bricks() {
+        let (heightmap, colormap) = terraces(24);
+        let options = GenOptions::builder().full_layers_above(20).build().unwrap();
+        let (bricks, stats) = gen_opt_heightmap_with_stats(&heightmap, &colormap, options, &NoProgress).unwrap();
+
+        assert_eq!(stats.input_size, (24, 24));
+        assert_eq!(stats.bricks, bricks.len());
+        assert!(stats.bricks_by_layer.len() > 1, "full layers are counted separately");
+        assert_eq!(stats.bricks_by_layer.iter().sum::<usize>(), bricks.len());
+        for counts in [&stats.bricks_by_asset, &stats.bricks_by_material, &stats.bricks_by_size] {
+            assert_eq!(counts.values().sum::<usize>(), bricks.len());
+        }
+        let colors: HashSet<String> = bricks.iter().map(|brick| format!("{:?}", brick.color)).collect();
+        assert_eq!(stats.unique_colors, colors.len());
+        assert!(stats.quad_merges.iter().any(|level| level.merged > 0));
+        assert!(stats.estimated_save_size > HEADER_BYTES);
+        assert!(stats.phases.iter().all(|phase| phase.seconds >= 0.0));
+
+        // Every brick is inside the bounding box, which is measured in studs
+        let bounds = stats.bounds.unwrap();
+        for brick in &bricks {
+            let pos = [brick.position.0, brick.position.1, brick.position.2];
+            for (axis, &p) in pos.iter().enumerate() {
+                let stud = p as f64 / 10.0;
+                assert!(bounds.min[axis] < stud && stud < bounds.max[axis]);
+            }
+        }
+    }
+
+    #[test]
+    fn merged_block_stats_add_up_to_the_whole() {
+        let (heightmap, colormap) = terraces(16);
+        let bricks = gen_opt_heightmap_with_stats(&heightmap, &colormap, GenOptions::default(), &NoProgress)
+            .unwrap()
+            .0;
+        let (first, second) = bricks.split_at(bricks.len() / 3);
+
+        let mut merged = GenStats::new((16, 16));
+        merged.record_bricks(&[first.to_vec()]);
+        let mut block = GenStats::new((16, 16));
+        block.record_bricks(&[second.to_vec()]);
+        merged.merge(block);
+        let mut whole = GenStats::new((16, 16));
+        whole.record_bricks(&[bricks]);
+
+        assert_eq!(serde_json::to_string(&merged).unwrap(), serde_json::to_string(&whole).unwrap());
+    }
+
+    #[test]
+    fn map_info_reads_every_pixel() {
+        let heightmap = HeightmapGrid::new(vec![7, 3, 12, 3, 9, 40], (3, 2)).unwrap();
+        let colormap = ColormapGrid::new(
+            vec![[1, 2, 3, 255], [0, 0, 0, 0], [1, 2, 3, 255], [9, 9, 9, 255], [0, 0, 0, 0], [1, 2, 3, 128]],
+            (3, 2),
+            true,
+        )
+        .unwrap();
+        let info = MapInfo::new(&heightmap, &colormap);
+        assert_eq!(info.heightmap_size, (3, 2));
+        assert_eq!(info.colormap_size, (3, 2));
+        assert_eq!(info.height_range, (3, 40));
+        assert_eq!(info.unique_colors, 4);
+        assert_eq!(info.transparent_pixels, 2);
+    }
+}
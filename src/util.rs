@@ -1,44 +1,642 @@
 // Import Brickadia save file structures and related types
-use brickadia::save::{Brick, BrickOwner, Header1, Header2, SaveData, User};
+use brickadia::{
+    read::SaveReader, // Reads Brickadia save files (.brs format)
+    save::{Brick, BrickColor, BrickOwner, Header1, Header2, SaveData, User},
+};
+// Import serde for including the optimizer in JSON reports and options in config files
+use serde::{Deserialize, Serialize};
 // Import standard library items for file path handling
 use std::ffi::OsStr;  // OS-specific string slice for file extensions
+use std::{fs::File, io::BufReader}; // For reading saves
 use std::path::Path;  // Cross-platform file path handling
+use std::{fmt, str::FromStr}; // For naming and parsing optimizers
 // Import UUID generation and parsing
 use uuid::Uuid;
 
+// Import our error type
+use crate::error::GenError;
+
+/// Brick asset names written to every save, indexed by `BrickKind::asset`
+pub const BRICK_ASSETS: [&str; 4] = [
+    "PB_DefaultBrick",      // Asset 0: Standard cube brick
+    "PB_DefaultTile",       // Asset 1: Tile brick
+    "PB_DefaultMicroBrick", // Asset 2: Micro brick
+    "PB_DefaultStudded",    // Asset 3: Studded brick
+];
+
+/// Size limits of a procedural brick asset, in Brickadia units
+/// Sizes are half extents, like `Size::Procedural` (a 1x1 stud brick is 5x5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssetLimits {
+    /// Largest size along X and Y
+    pub max_xy: u32,
+    /// Largest size along Z
+    pub max_z: u32,
+    /// X and Y sizes must be a multiple of this
+    pub xy_step: u32,
+    /// Z sizes must be a multiple of this
+    pub z_step: u32,
+}
+
+/// Size limits of each brick asset, indexed like `BRICK_ASSETS`
+/// Bricks beyond these are clamped or rejected when the save is loaded
+// Brickadia clamps every procedural brick to 500 units across and 250 high, the limits the
+// quadtree and height splitting have always used, so only the grid steps differ between assets.
+// Keeping a row per asset lets a brick type with its own limits be added without touching callers.
+pub const ASSET_LIMITS: [AssetLimits; 4] = [
+    AssetLimits { max_xy: 500, max_z: 250, xy_step: 5, z_step: 2 }, // Brick: stud wide, plate high
+    AssetLimits { max_xy: 500, max_z: 250, xy_step: 5, z_step: 2 }, // Tile: same grid as bricks
+    AssetLimits { max_xy: 500, max_z: 250, xy_step: 1, z_step: 2 }, // Micro brick: any width, heights on the plate grid
+    AssetLimits { max_xy: 500, max_z: 250, xy_step: 5, z_step: 5 }, // Studded: stud sized cubes
+];
+
+/// Material names written to every save, indexed by `Brick.material_index`
+pub const MATERIALS: [&str; 2] = [
+    "BMC_Plastic", // Material 0: Plastic
+    "BMC_Glow",    // Material 1: Glow (used with --glow)
+];
+
 /// Configuration options for heightmap to brick generation
 /// This struct contains all the settings that control how bricks are created
+/// Use `GenOptions::builder` to build options that are checked to go together
+/// Fields can only be set through the builder or a config file, so generation can check them first
+/// Missing fields keep their default when read from a config file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GenOptions {
     /// Size of each brick in Brickadia units (typically 5 units per stud)
-    pub size: u32,
+    pub(crate) size: u32,
     /// Vertical scale multiplier for height values
-    pub scale: u32,
-    /// Index of the brick asset to use (0=cube, 1=tile, 2=micro, 3=stud)
-    pub asset: u32,
+    pub(crate) scale: u32,
+    /// Type of brick to generate
+    pub(crate) kind: BrickKind,
     /// Whether to automatically remove bottom-level and transparent bricks
-    pub cull: bool,
-    /// Whether to use tile-shaped bricks instead of cubes
-    pub tile: bool,
-    /// Whether to use micro bricks (1/5 scale)
-    pub micro: bool,
-    /// Whether to use studded cube bricks
-    pub stud: bool,
+    pub(crate) cull: bool,
     /// Whether to snap brick positions to Brickadia's grid system
-    pub snap: bool,
+    pub(crate) snap: bool,
     /// Whether to create a flat image (no height variation)
-    pub img: bool,
+    pub(crate) img: bool,
     /// Whether to make bricks glow with 0 intensity
-    pub glow: bool,
+    pub(crate) glow: bool,
     /// Whether heightmap uses high-detail RGBA encoding
-    pub hdmap: bool,
+    pub(crate) hdmap: bool,
     /// Whether input colors are in linear RGB (true) or sRGB (false)
-    pub lrgb: bool,
+    pub(crate) lrgb: bool,
     /// Whether to disable brick collision
-    pub nocollide: bool,
+    pub(crate) nocollide: bool,
     /// Whether to enable quadtree optimization (recommended)
-    pub quadtree: bool,
+    pub(crate) quadtree: bool,
     /// Height threshold above which to generate full layers
-    pub gen_full_layers_above_height: u32,
+    pub(crate) gen_full_layers_above_height: u32,
+    /// Number of threads used to build and optimize the quadtree (0 = one per CPU core)
+    pub(crate) threads: usize,
+    /// How tiles are merged into bricks
+    pub(crate) optimizer: Optimizer,
+    /// Fill for the underground part of each column, None to use the surface color
+    pub(crate) subsoil: Option<Subsoil>,
+    /// Seed reserved for randomised generation steps, none use it yet
+    /// It is part of the block cache key, so caches made with another seed aren't reused
+    pub(crate) seed: u64,
+}
+
+/// Fill for everything below the top of each column
+/// Sharing one color lets the underground of neighboring columns merge into a few large bricks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subsoil {
+    /// Color of the underground bricks (in the save's linear RGB, like colormap colors)
+    pub color: [u8; 4],
+    /// Minimum height in Brickadia units at the top of each column that keeps the surface color
+    #[serde(default = "default_subsoil_depth")]
+    pub depth: u32,
+    /// Type of the underground bricks, None for the surface type
+    #[serde(default)]
+    pub kind: Option<BrickKind>,
+    /// Whether the underground bricks have player, weapon and interaction collision
+    #[serde(default = "default_subsoil_collide")]
+    pub collide: bool,
+}
+
+/// Subsoil depth used when a config file leaves it out, the same as `--subsoil_depth`
+fn default_subsoil_depth() -> u32 {
+    40
+}
+
+/// Subsoil bricks collide unless a config file turns it off
+fn default_subsoil_collide() -> bool {
+    true
+}
+
+impl GenOptions {
+    /// Start building options from the defaults
+    ///
+    /// # Example
+    /// ```
+    /// use heightmap::util::{BrickKind, GenOptions};
+    ///
+    /// let options = GenOptions::builder().kind(BrickKind::Micro).pixel_size(2).build().unwrap();
+    /// assert_eq!(options.size(), 2);
+    /// assert!(GenOptions::builder().img(true).full_layers_above(10).build().is_err());
+    /// ```
+    pub fn builder() -> GenOptionsBuilder {
+        GenOptionsBuilder::default()
+    }
+
+    /// Size of each brick in Brickadia units
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Vertical scale multiplier for height values
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Type of brick to generate
+    pub fn kind(&self) -> BrickKind {
+        self.kind
+    }
+
+    /// Whether bottom-level and transparent bricks are removed
+    pub fn cull(&self) -> bool {
+        self.cull
+    }
+
+    /// Whether brick positions snap to Brickadia's grid
+    pub fn snap(&self) -> bool {
+        self.snap
+    }
+
+    /// Whether a flat image is made instead of terrain
+    pub fn img(&self) -> bool {
+        self.img
+    }
+
+    /// Whether bricks glow
+    pub fn glow(&self) -> bool {
+        self.glow
+    }
+
+    /// Whether the heightmap uses high-detail RGBA encoding
+    pub fn hdmap(&self) -> bool {
+        self.hdmap
+    }
+
+    /// Whether input colors are in linear RGB
+    pub fn lrgb(&self) -> bool {
+        self.lrgb
+    }
+
+    /// Whether brick collision is disabled
+    pub fn nocollide(&self) -> bool {
+        self.nocollide
+    }
+
+    /// Whether the quadtree merges tiles
+    pub fn quadtree(&self) -> bool {
+        self.quadtree
+    }
+
+    /// Height above which full layers are generated
+    pub fn full_layers_above(&self) -> u32 {
+        self.gen_full_layers_above_height
+    }
+
+    /// Number of threads, 0 for one per CPU core
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// How tiles are merged into bricks
+    pub fn optimizer(&self) -> Optimizer {
+        self.optimizer
+    }
+
+    /// Fill for the underground part of each column
+    pub fn subsoil(&self) -> Option<Subsoil> {
+        self.subsoil
+    }
+
+    /// Seed reserved for randomised generation steps
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Size limits of the brick asset being generated
+    pub fn limits(&self) -> AssetLimits {
+        ASSET_LIMITS[self.kind.asset() as usize]
+    }
+
+    /// Longest side of a brick, in pixels
+    pub fn max_tiles(&self) -> u32 {
+        (self.limits().max_xy / self.size.max(1)).max(1)
+    }
+
+    /// Check options that weren't made by the builder, like ones read from a config file
+    ///
+    /// # Returns
+    /// * `Err(GenError::Invalid)` - If options can't be combined, like a subsoil on a flat image
+    /// * `Err(GenError::LimitExceeded)` - If pixels make bricks the asset doesn't allow
+    pub fn validate(&self) -> Result<(), GenError> {
+        if self.scale == 0 {
+            return Err(GenError::Invalid("Vertical scale must be at least 1".to_string()));
+        }
+        if self.img {
+            // Images are a single flat layer, so there is nothing below or above the surface
+            if self.subsoil.is_some() {
+                return Err(GenError::Invalid("A subsoil can't be used with a flat image".to_string()));
+            }
+            if self.gen_full_layers_above_height > 0 {
+                return Err(GenError::Invalid("Full layers can't be used with a flat image".to_string()));
+            }
+        }
+        self.check_limits()
+    }
+
+    /// Check that one pixel makes a brick size the asset allows
+    ///
+    /// # Returns
+    /// * `Err(GenError::LimitExceeded)` - If pixels are larger than the biggest brick or off the asset's grid
+    pub fn check_limits(&self) -> Result<(), GenError> {
+        let limits = self.limits();
+        let asset = BRICK_ASSETS[self.kind.asset() as usize];
+        if self.size == 0 || self.size > limits.max_xy {
+            Err(GenError::LimitExceeded(format!(
+                "{} bricks can't be {} units wide (at most {})",
+                asset,
+                u64::from(self.size) * 2,
+                u64::from(limits.max_xy) * 2
+            )))
+        } else if !self.size.is_multiple_of(limits.xy_step) {
+            Err(GenError::LimitExceeded(format!(
+                "{} bricks must be a multiple of {} units wide",
+                asset,
+                u64::from(limits.xy_step) * 2
+            )))
+        } else if let Some(subsoil_kind) = self.subsoil.and_then(|s| s.kind) {
+            // Subsoil bricks are the same width as the surface bricks above them
+            let subsoil = GenOptions {
+                kind: subsoil_kind,
+                subsoil: None,
+                ..self.clone()
+            };
+            subsoil.check_limits().map_err(|e| GenError::LimitExceeded(format!("Subsoil: {}", e)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Default options: 1 stud cube bricks at a vertical scale of 1, merged with the quadtree
+impl Default for GenOptions {
+    fn default() -> Self {
+        GenOptions {
+            size: 5,
+            scale: 1,
+            kind: BrickKind::Brick,
+            cull: false,
+            snap: false,
+            img: false,
+            glow: false,
+            hdmap: false,
+            lrgb: false,
+            nocollide: false,
+            quadtree: true,
+            gen_full_layers_above_height: 0,
+            threads: 0,
+            optimizer: Optimizer::default(),
+            subsoil: None,
+            seed: 0,
+        }
+    }
+}
+
+/// Builds `GenOptions` that are checked to go together
+/// The pixel size is given in studs (or micro bricks) and turned into Brickadia units for the brick type
+#[derive(Debug, Clone)]
+pub struct GenOptionsBuilder {
+    /// Options being built, with `size` not yet converted
+    options: GenOptions,
+    /// Width of each pixel in studs, or micro bricks for `BrickKind::Micro`
+    pixel_size: u32,
+}
+
+impl Default for GenOptionsBuilder {
+    fn default() -> Self {
+        GenOptionsBuilder {
+            options: GenOptions::default(),
+            pixel_size: 1,
+        }
+    }
+}
+
+/// Continue building from existing options, like ones read from a config file
+/// The pixel size is kept in studs (or micro bricks), so changing the brick type keeps it
+impl From<GenOptions> for GenOptionsBuilder {
+    fn from(options: GenOptions) -> Self {
+        GenOptionsBuilder {
+            pixel_size: options.size / options.kind.unit(),
+            options,
+        }
+    }
+}
+
+impl GenOptionsBuilder {
+    /// Start from a preset, later calls override its settings
+    pub fn preset(self, preset: Preset) -> Self {
+        match preset {
+            Preset::Terrain => self.kind(BrickKind::Tile),
+            Preset::Img2Brick => self.kind(BrickKind::Tile).img(true).cull(true),
+            Preset::MicroDetail => self.kind(BrickKind::Micro).pixel_size(1),
+        }
+    }
+
+    /// Type of brick to generate
+    pub fn kind(mut self, kind: BrickKind) -> Self {
+        self.options.kind = kind;
+        self
+    }
+
+    /// Width of each pixel in studs, or micro bricks for `BrickKind::Micro` (default 1)
+    pub fn pixel_size(mut self, pixel_size: u32) -> Self {
+        self.pixel_size = pixel_size;
+        self
+    }
+
+    /// Vertical scale multiplier for height values (default 1)
+    pub fn scale(mut self, scale: u32) -> Self {
+        self.options.scale = scale;
+        self
+    }
+
+    /// Remove bottom-level and transparent bricks
+    pub fn cull(mut self, cull: bool) -> Self {
+        self.options.cull = cull;
+        self
+    }
+
+    /// Snap brick positions to Brickadia's grid
+    pub fn snap(mut self, snap: bool) -> Self {
+        self.options.snap = snap;
+        self
+    }
+
+    /// Make the map flat and render the colormap as an image
+    pub fn img(mut self, img: bool) -> Self {
+        self.options.img = img;
+        self
+    }
+
+    /// Make bricks glow with 0 intensity
+    pub fn glow(mut self, glow: bool) -> Self {
+        self.options.glow = glow;
+        self
+    }
+
+    /// Read heightmaps as high detail RGB encoded (or 16-bit grayscale) heights
+    pub fn hdmap(mut self, hdmap: bool) -> Self {
+        self.options.hdmap = hdmap;
+        self
+    }
+
+    /// Read colormap colors as linear RGB instead of sRGB
+    pub fn lrgb(mut self, lrgb: bool) -> Self {
+        self.options.lrgb = lrgb;
+        self
+    }
+
+    /// Disable brick collision
+    pub fn nocollide(mut self, nocollide: bool) -> Self {
+        self.options.nocollide = nocollide;
+        self
+    }
+
+    /// Merge tiles into larger bricks (default true)
+    pub fn quadtree(mut self, quadtree: bool) -> Self {
+        self.options.quadtree = quadtree;
+        self
+    }
+
+    /// Generate full layers above this height (default 0, off)
+    pub fn full_layers_above(mut self, height: u32) -> Self {
+        self.options.gen_full_layers_above_height = height;
+        self
+    }
+
+    /// Number of threads to generate with (default 0, one per CPU core)
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.options.threads = threads;
+        self
+    }
+
+    /// How tiles are merged into bricks
+    pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
+        self.options.optimizer = optimizer;
+        self
+    }
+
+    /// Fill for the underground part of each column
+    pub fn subsoil(mut self, subsoil: Option<Subsoil>) -> Self {
+        self.options.subsoil = subsoil;
+        self
+    }
+
+    /// Seed reserved for randomised generation steps, no step uses it yet
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.options.seed = seed;
+        self
+    }
+
+    /// Check the options go together and convert the pixel size for the brick type
+    ///
+    /// # Returns
+    /// * `Err(GenError)` - If the options don't validate, see `GenOptions::validate`
+    pub fn build(self) -> Result<GenOptions, GenError> {
+        let GenOptionsBuilder { mut options, pixel_size } = self;
+        if pixel_size == 0 {
+            return Err(GenError::Invalid("Pixel size must be at least 1".to_string()));
+        }
+        options.size = pixel_size
+            .checked_mul(options.kind.unit())
+            .ok_or_else(|| GenError::LimitExceeded(format!("Pixel size {} is too large", pixel_size)))?;
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+/// Named starting points for common kinds of saves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Tiled terrain from a heightmap
+    Terrain,
+    /// A flat image of culled tiles from a colormap
+    Img2Brick,
+    /// Terrain of single micro brick pixels
+    MicroDetail,
+}
+
+impl Preset {
+    /// Every preset, in the order they are listed
+    pub const ALL: [Preset; 3] = [Preset::Terrain, Preset::Img2Brick, Preset::MicroDetail];
+}
+
+/// Preset names as used on the command line
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Preset::Terrain => "terrain",
+            Preset::Img2Brick => "img2brick",
+            Preset::MicroDetail => "micro_detail",
+        })
+    }
+}
+
+/// Parse a preset by name, with spaces, dashes or underscores between words
+impl FromStr for Preset {
+    type Err = GenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace([' ', '-'], "_").as_str() {
+            "terrain" => Ok(Preset::Terrain),
+            "img2brick" => Ok(Preset::Img2Brick),
+            "micro_detail" => Ok(Preset::MicroDetail),
+            _ => Err(GenError::Invalid(format!(
+                "Unknown preset '{}', expected terrain, img2brick or micro_detail",
+                s
+            ))),
+        }
+    }
+}
+
+/// Parse a color as `RRGGBB` or `#RRGGBB` hex, or as `r,g,b` with values from 0 to 255
+///
+/// # Returns
+/// * The opaque RGBA color, not converted to linear RGB
+pub fn parse_color(s: &str) -> Result<[u8; 4], GenError> {
+    let invalid = || GenError::Invalid(format!("Invalid color '{}', expected RRGGBB or r,g,b", s));
+    let channels: Vec<u8> = if s.contains(',') {
+        s.split(',')
+            .map(|c| c.trim().parse::<u8>().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?
+    } else {
+        let hex = s.trim_start_matches('#');
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        (0..3)
+            .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?
+    };
+    match channels[..] {
+        [r, g, b] => Ok([r, g, b, 255]),
+        _ => Err(invalid()),
+    }
+}
+
+/// Type of brick that pixels are turned into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BrickKind {
+    /// Default cube bricks
+    #[default]
+    Brick,
+    /// Tiles, bricks without studs
+    Tile,
+    /// Micro bricks, 1/5 of a stud wide
+    Micro,
+    /// Studded cubes on the stud grid
+    Stud,
+}
+
+impl BrickKind {
+    /// Index of the brick asset in `BRICK_ASSETS`
+    pub fn asset(self) -> u32 {
+        match self {
+            BrickKind::Brick => 0,
+            BrickKind::Tile => 1,
+            BrickKind::Micro => 2,
+            BrickKind::Stud => 3,
+        }
+    }
+
+    /// Half extent in Brickadia units of a brick one unit wide: a stud, or a micro brick for `Micro`
+    pub fn unit(self) -> u32 {
+        match self {
+            BrickKind::Micro => 1,
+            _ => 5,
+        }
+    }
+}
+
+/// Brick type names as used on the command line
+impl fmt::Display for BrickKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BrickKind::Brick => "brick",
+            BrickKind::Tile => "tile",
+            BrickKind::Micro => "micro",
+            BrickKind::Stud => "stud",
+        })
+    }
+}
+
+/// Parse a brick type from its short name (brick, tile, micro or stud)
+impl FromStr for BrickKind {
+    type Err = GenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "brick" => Ok(BrickKind::Brick),
+            "tile" => Ok(BrickKind::Tile),
+            "micro" => Ok(BrickKind::Micro),
+            "stud" => Ok(BrickKind::Stud),
+            _ => Err(GenError::Invalid(format!(
+                "Unknown brick type '{}', expected brick, tile, micro or stud",
+                s
+            ))),
+        }
+    }
+}
+
+/// Strategy used to merge tiles into larger bricks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Optimizer {
+    /// Power-of-two quadtree merges followed by line merges
+    #[default]
+    Quadtree,
+    /// Greedy maximal rectangles of equal height and color, followed by line merges
+    Greedy,
+    /// Run both and keep whichever gives fewer bricks
+    Best,
+}
+
+/// Optimizer names as used on the command line and in reports
+impl fmt::Display for Optimizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Optimizer::Quadtree => "quadtree",
+            Optimizer::Greedy => "greedy",
+            Optimizer::Best => "best",
+        })
+    }
+}
+
+/// Parse an optimizer from its command line name
+impl FromStr for Optimizer {
+    type Err = GenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "quadtree" => Ok(Optimizer::Quadtree),
+            "greedy" => Ok(Optimizer::Greedy),
+            "best" => Ok(Optimizer::Best),
+            _ => Err(GenError::Invalid(format!(
+                "Unknown optimizer '{}', expected quadtree, greedy or best",
+                s
+            ))),
+        }
+    }
 }
 
 /// Convert a single color channel from sRGB gamma to linear gamma
@@ -77,6 +675,28 @@ pub fn to_linear_rgb(rgb: [u8; 4]) -> [u8; 4] {
     ]
 }
 
+/// Convert a single color channel from linear gamma back to sRGB gamma
+/// This is the inverse of `to_linear_gamma`, used when exporting colors to
+/// formats that expect display (sRGB) values
+///
+/// # Arguments
+/// * `c` - Color channel value in linear gamma space (0-255)
+///
+/// # Returns
+/// * Color channel value in sRGB gamma space (0-255)
+pub fn to_srgb_gamma(c: u8) -> u8 {
+    let cf = (c as f64) / 255.0;  // Normalize to 0.0-1.0 range
+    (if cf > 0.0031308 {
+        // Apply the gamma curve for values above the linear threshold
+        (1.055 * cf.powf(1.0 / 2.4) - 0.055) * 255.0
+    } else {
+        // Use linear scaling for small values
+        cf * 12.92 * 255.0
+    })
+    .round()
+    .clamp(0.0, 255.0) as u8
+}
+
 /// Convert a vector of bricks into a complete Brickadia save file structure
 /// This creates all the metadata and headers needed for a valid .brs save file
 /// 
@@ -117,14 +737,9 @@ pub fn bricks_to_save(bricks: Vec<Brick>, owner_id: String, owner_name: String)
         // Second header contains asset and material definitions
         header2: Header2 {
             // Define the brick assets used in this save (indices match GenOptions.asset)
-            brick_assets: vec![
-                String::from("PB_DefaultBrick"),     // Asset 0: Standard cube brick
-                String::from("PB_DefaultTile"),      // Asset 1: Tile brick
-                String::from("PB_DefaultMicroBrick"), // Asset 2: Micro brick
-                String::from("PB_DefaultStudded"),   // Asset 3: Studded brick
-            ],
+            brick_assets: BRICK_ASSETS.iter().map(|&a| a.into()).collect(),
             // Define the materials that can be applied to bricks
-            materials: vec!["BMC_Plastic".into(), "BMC_Glow".into()], // 0=plastic, 1=glow
+            materials: MATERIALS.iter().map(|&m| m.into()).collect(),
             brick_owners,  // Ownership information
             ..Default::default()  // Use defaults for remaining fields
         },
@@ -133,6 +748,72 @@ pub fn bricks_to_save(bricks: Vec<Brick>, owner_id: String, owner_name: String)
     }
 }
 
+/// Read a Brickadia save file, skipping its preview image
+pub fn read_save(path: &str) -> Result<SaveData, GenError> {
+    let file = File::open(path).map_err(|e| GenError::io(path, e))?;
+    SaveReader::new(BufReader::new(file))
+        .and_then(|mut reader| reader.read_all_skip_preview())
+        .map_err(|e| GenError::decode(path, e))
+}
+
+/// Take the bricks out of a save, in the form `bricks_to_save` writes them
+/// Asset and material indices are remapped onto `BRICK_ASSETS` and `MATERIALS`,
+/// and palette colors are turned into unique colors
+///
+/// # Returns
+/// * `Ok((bricks, owner_id, owner_name))` with the save's first brick owner (or its author)
+/// * `Err(GenError::Unsupported)` if a brick uses an asset or material this generator doesn't write
+pub fn save_to_bricks(save: SaveData) -> Result<(Vec<Brick>, String, String), GenError> {
+    let SaveData { header1, header2, bricks, .. } = save;
+    let remap = |names: &[String], known: &[&str], what: &str| -> Result<Vec<u32>, GenError> {
+        names
+            .iter()
+            .map(|name| {
+                known.iter().position(|k| k == name).map(|i| i as u32).ok_or_else(|| {
+                    GenError::Unsupported(format!("Unsupported {} '{}', expected one of {}", what, name, known.join(", ")))
+                })
+            })
+            .collect()
+    };
+    let assets = remap(&header2.brick_assets, &BRICK_ASSETS, "brick asset")?;
+    let materials = remap(&header2.materials, &MATERIALS, "material")?;
+
+    let bricks = bricks
+        .into_iter()
+        .map(|brick| {
+            let index = |table: &[u32], i: u32| table.get(i as usize).copied();
+            Ok(Brick {
+                asset_name_index: index(&assets, brick.asset_name_index)
+                    .ok_or_else(|| GenError::Invalid(format!("Brick asset index {} is out of range", brick.asset_name_index)))?,
+                material_index: index(&materials, brick.material_index)
+                    .ok_or_else(|| GenError::Invalid(format!("Material index {} is out of range", brick.material_index)))?,
+                color: match brick.color {
+                    BrickColor::Index(i) => BrickColor::Unique(header2.colors.get(i as usize).cloned().ok_or_else(|| {
+                        GenError::Invalid(format!("Color index {} is out of range", i))
+                    })?),
+                    color => color,
+                },
+                ..brick
+            })
+        })
+        .collect::<Result<Vec<Brick>, GenError>>()?;
+
+    let (owner_id, owner_name) = match header2.brick_owners.first() {
+        Some(owner) => (owner.id, owner.name.clone()),
+        None => (header1.author.id, header1.author.name),
+    };
+    Ok((bricks, owner_id.to_string(), owner_name))
+}
+
+/// Sort bricks bottom to top, then by row and column
+/// Bricks at the same position keep their order, so the same map always gives the same save
+///
+/// # Arguments
+/// * `bricks` - Bricks to sort in place
+pub fn sort_bricks(bricks: &mut [Brick]) {
+    bricks.sort_by_key(|b| (b.position.2, b.position.1, b.position.0));
+}
+
 /// Extract the file extension from a filename or path
 /// This is used to determine the file type for input validation
 /// 
@@ -140,13 +821,15 @@ pub fn bricks_to_save(bricks: Vec<Brick>, owner_id: String, owner_name: String)
 /// * `filename` - The filename or path to extract extension from
 /// 
 /// # Returns
-/// * `Some(&str)` - The file extension in lowercase (without the dot)
+/// * `Some(&str)` - The file extension as written (without the dot)
 /// * `None` - If there's no extension or it contains invalid UTF-8
 /// 
 /// # Examples
 /// ```
+/// use heightmap::util::file_ext;
+///
 /// assert_eq!(file_ext("image.png"), Some("png"));
-/// assert_eq!(file_ext("path/to/file.JPG"), Some("jpg"));  
+/// assert_eq!(file_ext("path/to/file.JPG"), Some("JPG"));
 /// assert_eq!(file_ext("no_extension"), None);
 /// ```
 #[allow(unused)]  // Allow unused warning since this may not be used in all contexts
@@ -155,3 +838,6 @@ pub fn file_ext(filename: &str) -> Option<&str> {
         .extension()           // Extract the extension (returns Option<&OsStr>)
         .and_then(OsStr::to_str) // Convert OsStr to &str (handles UTF-8 conversion)
 }
+
+/// Path that stands for standard input when reading images, and standard output when writing saves
+pub const STDIO_PATH: &str = "-";